@@ -0,0 +1,133 @@
+//! Sorting for names and titles in library lists. The Library registers
+//! `compare` with SQLite as the UNICODE collation and `generate_sort_name`
+//! as a function, so lists are ordered with e.g.
+//! `ORDER BY COALESCE(sort_name, generate_sort_name(name)) COLLATE UNICODE`.
+//!
+//! The sort name indexes from migration 10 are built on both, so a change
+//! to what either returns needs a migration that REINDEXes them, or the
+//! indexes will disagree with the queries that use them. SQLite evaluates
+//! them on every write to Artist and Release, so a connection that doesn't
+//! register them can't write those tables.
+
+use std::{cmp::Ordering, iter::Peekable, str::Chars};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization as _};
+
+/// The name of the collation registered on every library connection.
+pub const UNICODE: &str = "UNICODE";
+
+/// Compare ignoring case and diacritics, so that "Émilie" sorts with "Emma"
+/// instead of after "Zoe", and with runs of digits compared by value, so
+/// that "Disc 2" sorts before "Disc 10". Strings that are equal that way
+/// are ordered by their code points so that the order is total.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (fa, fb) = (fold(a), fold(b));
+    let (mut ca, mut cb) = (fa.chars().peekable(), fb.chars().peekable());
+    loop {
+        let ordering = match (ca.peek().copied(), cb.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                compare_numbers(&digits(&mut ca), &digits(&mut cb))
+            },
+            (Some(x), Some(y)) => {
+                ca.next();
+                cb.next();
+                x.cmp(&y)
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering
+        }
+    }
+}
+
+/// The name to sort by when none is tagged or known from MusicBrainz. A
+/// leading English article moves to the end, so "The Beatles" sorts as
+/// "Beatles, The". Personal names are left as they are, since there's no
+/// telling which part is the family name.
+pub fn generate_sort_name(name: &str) -> String {
+    let name = name.trim();
+    for article in ["The ", "A ", "An "] {
+        if let Some(rest) = name.get(..article.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(article))
+            .and_then(|_| name.get(article.len()..))
+            .map(str::trim_start)
+            .filter(|rest| !rest.is_empty()) {
+            return format!("{}, {}", rest, &name[..article.len() - 1])
+        }
+    }
+    name.to_string()
+}
+
+fn fold(s: &str) -> String {
+    s.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ø' => 'o',
+            'ł' => 'l',
+            'đ' => 'd',
+            c => c,
+        })
+        .collect()
+}
+
+fn digits(chars: &mut Peekable<Chars>) -> String {
+    let mut s = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        s.push(c);
+    }
+    s
+}
+
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::{library::Library, model::{Artist, ModelBasics as _}};
+
+    use super::{compare, generate_sort_name};
+
+    #[test]
+    fn collation() {
+        let mut names = vec!["Zoe", "émilie", "Emma", "Disc 10", "Disc 2", "abba", "ABBA"];
+        names.sort_by(|a, b| compare(a, b));
+        assert!(names == vec!["ABBA", "abba", "Disc 2", "Disc 10", "émilie", "Emma", "Zoe"]);
+        assert!(compare("Sigur Rós", "Sigur Ros") == Ordering::Greater);
+    }
+
+    #[test]
+    fn sort_names() {
+        assert!(generate_sort_name("The Beatles") == "Beatles, The");
+        assert!(generate_sort_name("A Tribe Called Quest") == "Tribe Called Quest, A");
+        assert!(generate_sort_name("the the") == "the, the");
+        assert!(generate_sort_name("The") == "The");
+        assert!(generate_sort_name("Theatre of Tragedy") == "Theatre of Tragedy");
+        assert!(generate_sort_name("Bob Dylan") == "Bob Dylan");
+    }
+
+    #[test]
+    fn order_by() {
+        let library = Library::open_memory();
+        for (name, sort_name) in [("The Beatles", None), ("Zappa", None), ("Émilie Simon", None),
+            ("Bob Dylan", Some("Dylan, Bob")), ("ABBA", None)] {
+            Artist {
+                name: Some(name.to_string()),
+                sort_name: sort_name.map(str::to_string),
+                ..Default::default()
+            }.save(&library);
+        }
+        let artists: Vec<Artist> = library.query("
+            SELECT * FROM Artist
+            ORDER BY COALESCE(sort_name, generate_sort_name(name)) COLLATE UNICODE ASC", ());
+        let names = artists.iter().map(|a| a.name.clone().unwrap()).collect::<Vec<_>>();
+        assert!(names == vec!["ABBA", "The Beatles", "Bob Dylan", "Émilie Simon", "Zappa"]);
+    }
+}
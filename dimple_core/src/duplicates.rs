@@ -0,0 +1,361 @@
+//! Finds Tracks that are the same recording, such as the MP3 and FLAC of a
+//! song imported separately, and merges them so that they share one play
+//! count, one set of playlists, and one set of metadata.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{fingerprint, librarian::normalize_name, library::Library, merge::CrdtRules, model::{Alias, ModelBasics as _, Track, TrackSource}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicateReason {
+    /// The tracks have the same MusicBrainz recording id.
+    MusicBrainzId,
+    /// The tracks have the same normalized title and artist, and about the
+    /// same duration.
+    Metadata,
+    /// The media files of the tracks have matching audio fingerprints.
+    Fingerprint,
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub tracks: Vec<Track>,
+    pub reason: DuplicateReason,
+    /// From 0.0 to 1.0, how likely it is that the tracks are the same.
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DuplicateOptions {
+    /// Also compare audio fingerprints. Only media files that have already
+    /// been fingerprinted are compared.
+    pub use_fingerprints: bool,
+    pub min_fingerprint_similarity: f64,
+    /// How far apart the durations of tracks matched by metadata may be.
+    pub duration_tolerance_ms: u64,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        Self {
+            use_fingerprints: false,
+            min_fingerprint_similarity: 0.85,
+            duration_tolerance_ms: 3_000,
+        }
+    }
+}
+
+/// Groups of duplicate tracks, most confident first. A set of tracks found
+/// for more than one reason is listed once, with the highest confidence.
+pub fn find_duplicate_tracks(library: &Library, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    let mut groups = vec![];
+    groups.extend(musicbrainz_id_duplicates(library));
+    groups.extend(metadata_duplicates(library, options));
+    if options.use_fingerprints {
+        groups.extend(fingerprint_duplicates(library, options));
+    }
+
+    groups.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    let mut seen = BTreeSet::new();
+    groups.retain(|group| {
+        let keys = group.tracks.iter().map(|t| t.key.clone().unwrap()).collect::<BTreeSet<_>>();
+        seen.insert(keys)
+    });
+    groups
+}
+
+fn musicbrainz_id_duplicates(library: &Library) -> Vec<DuplicateGroup> {
+    let tracks: Vec<Track> = library.query("
+        SELECT Track.* FROM Track
+        WHERE musicbrainz_id IN (
+            SELECT musicbrainz_id FROM Track
+            WHERE musicbrainz_id IS NOT NULL
+            GROUP BY musicbrainz_id HAVING COUNT(*) > 1)
+        ORDER BY musicbrainz_id, rowid
+    ", ());
+    tracks.into_iter()
+        .fold(HashMap::<String, Vec<Track>>::new(), |mut groups, track| {
+            groups.entry(track.musicbrainz_id.clone().unwrap()).or_default().push(track);
+            groups
+        })
+        .into_values()
+        .map(|tracks| DuplicateGroup {
+            tracks,
+            reason: DuplicateReason::MusicBrainzId,
+            confidence: 1.0,
+        })
+        .collect()
+}
+
+fn metadata_duplicates(library: &Library, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    let mut by_name = HashMap::<(String, String), Vec<Track>>::new();
+    for track in library.list::<Track>() {
+        let title = normalize_name(track.title.as_deref().unwrap_or_default());
+        let artist = normalize_name(&track.artist_name(library).unwrap_or_default());
+        if title.is_empty() || artist.is_empty() {
+            continue
+        }
+        by_name.entry((title, artist)).or_default().push(track);
+    }
+
+    let mut groups = vec![];
+    for (_, mut tracks) in by_name.into_iter().filter(|(_, tracks)| tracks.len() > 1) {
+        // Split the tracks into runs of similar duration, so that a live
+        // version or a remix with the same title isn't taken for the
+        // original. Tracks without a duration join every run, with less
+        // confidence.
+        let (unknown, mut known): (Vec<_>, Vec<_>) = tracks.drain(..).partition(|t| t.length_ms.is_none());
+        known.sort_by_key(|t| t.length_ms);
+        let mut runs: Vec<Vec<Track>> = vec![];
+        for track in known {
+            match runs.last_mut() {
+                Some(run) if track.length_ms.unwrap() - run.last().unwrap().length_ms.unwrap()
+                    <= options.duration_tolerance_ms => run.push(track),
+                _ => runs.push(vec![track]),
+            }
+        }
+        if runs.is_empty() {
+            runs.push(vec![]);
+        }
+        for mut run in runs {
+            let confidence = if unknown.is_empty() { 0.9 } else { 0.7 };
+            run.extend(unknown.iter().cloned());
+            if run.len() > 1 {
+                groups.push(DuplicateGroup {
+                    tracks: run,
+                    reason: DuplicateReason::Metadata,
+                    confidence,
+                });
+            }
+        }
+    }
+    groups
+}
+
+fn fingerprint_duplicates(library: &Library, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    let track_for_media_file = |media_file_key: &Option<String>| {
+        library.find::<TrackSource, _>("SELECT * FROM TrackSource WHERE media_file_key = ?1", (media_file_key,))
+            .and_then(|track_source| track_source.track(library))
+    };
+    let mut groups = vec![];
+    for (a, b, similarity) in fingerprint::find_duplicate_media_files(library, options.min_fingerprint_similarity) {
+        let (Some(a), Some(b)) = (track_for_media_file(&a.key), track_for_media_file(&b.key)) else {
+            continue
+        };
+        if a.key == b.key {
+            continue
+        }
+        groups.push(DuplicateGroup {
+            tracks: vec![a, b],
+            reason: DuplicateReason::Fingerprint,
+            confidence: similarity,
+        });
+    }
+    groups
+}
+
+/// Fold `duplicate` into `track`. The sources, playlist items, and artist,
+/// genre, link and image refs of the duplicate move to the track, the
+/// fields are combined with CrdtRules, and the duplicate is deleted.
+/// Listening history is left as it is, and the duplicate's title becomes
+/// an Alias of the track so its plays still count, see query::PLAYS. The
+/// merge is recorded in the ChangeLog so that synced libraries replay it.
+pub fn merge_tracks(library: &Library, track: &Track, duplicate: &Track) -> Track {
+    let merged = merge_track_rows(library, track, duplicate);
+    library.log_change("Track", &duplicate.key.clone().unwrap(), "merge",
+        None, track.key.clone());
+    merged
+}
+
+/// Merge without recording the change, for replaying a merge from sync.
+pub(crate) fn merge_track_rows(library: &Library, track: &Track, duplicate: &Track) -> Track {
+    log::info!("Merging track {:?} into {:?}", duplicate.key, track.key);
+    let (key, duplicate_key) = (track.key.clone(), duplicate.key.clone());
+
+    // All or nothing, so nothing is left pointing at a deleted track.
+    library.transaction(|conn| {
+        conn.execute("UPDATE TrackSource SET track_key = ?1 WHERE track_key = ?2",
+            (&key, &duplicate_key)).unwrap();
+        conn.execute("UPDATE PlaylistItem SET track_key = ?1 WHERE track_key = ?2",
+            (&key, &duplicate_key)).unwrap();
+        for table in ["ArtistRef", "GenreRef", "LinkRef", "DimageRef"] {
+            // Refs the track already has stay as they are.
+            conn.execute(&format!("UPDATE OR IGNORE {} SET model_key = ?1 WHERE model_key = ?2", table),
+                (&key, &duplicate_key)).unwrap();
+            conn.execute(&format!("DELETE FROM {} WHERE model_key = ?1", table),
+                (&duplicate_key,)).unwrap();
+        }
+        conn.execute("UPDATE Alias SET model_key = ?1 WHERE model_key = ?2", (&key, &duplicate_key)).unwrap();
+        Library::delete_row(conn, duplicate);
+    });
+    library.notify(duplicate);
+    if duplicate.title.is_some() && duplicate.title != track.title {
+        Alias {
+            model: "Track".to_string(),
+            model_key: key.clone().unwrap(),
+            name: duplicate.title.clone(),
+            source: Some("merge".to_string()),
+            ..Default::default()
+        }.save(library);
+    }
+
+    let track = Track::get(library, &key.clone().unwrap()).unwrap_or(track.clone());
+    let mut merged = CrdtRules::merge(track, duplicate.clone());
+    merged.key = key;
+    merged.save(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fingerprint::Fingerprint, library::Library, model::{Artist, ArtistRef, ChangeLog, Event, MediaFile, ModelBasics as _, Playlist, Track, TrackSource}, query};
+
+    use super::{find_duplicate_tracks, merge_tracks, DuplicateOptions, DuplicateReason};
+
+    #[test]
+    fn find_and_merge() {
+        let library = Library::open_memory();
+        let artist = Artist {
+            name: Some("Perturbator".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let mp3 = Track {
+            title: Some("Future Club".to_string()),
+            length_ms: Some(250_000),
+            ..Default::default()
+        }.save(&library);
+        let flac = Track {
+            title: Some("future club".to_string()),
+            length_ms: Some(251_000),
+            lyrics: Some("...".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let live = Track {
+            title: Some("Future Club".to_string()),
+            length_ms: Some(400_000),
+            ..Default::default()
+        }.save(&library);
+        for track in [&mp3, &flac, &live] {
+            ArtistRef::attach(&library, &artist, track);
+        }
+        TrackSource {
+            track_key: flac.key.clone(),
+            ..Default::default()
+        }.save(&library);
+        let playlist = Playlist::default().save(&library);
+        playlist.append(&library, &flac);
+
+        let groups = find_duplicate_tracks(&library, &DuplicateOptions::default());
+        assert!(groups.len() == 1);
+        assert!(groups[0].reason == DuplicateReason::Metadata);
+        assert!(groups[0].tracks.len() == 2);
+        assert!(!groups[0].tracks.contains(&live));
+
+        let merged = merge_tracks(&library, &mp3, &flac);
+        assert!(merged.key == mp3.key);
+        assert!(merged.lyrics == Some("...".to_string()));
+        assert!(Track::get(&library, &flac.key.clone().unwrap()).is_none());
+        assert!(library.track_sources_for_track(&merged).len() == 1);
+        assert!(playlist.tracks(&library) == vec![merged.clone()]);
+        assert!(merged.artists(&library).len() == 1);
+        let changelogs = ChangeLog::list(&library);
+        assert!(changelogs.iter().any(|c| c.op == "merge" && c.model_key == flac.key.clone().unwrap()));
+        assert!(find_duplicate_tracks(&library, &DuplicateOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn musicbrainz_id() {
+        let library = Library::open_memory();
+        let tracks = ["Roygbiv", "ROYGBIV (Remastered)"].map(|title| Track {
+            title: Some(title.to_string()),
+            musicbrainz_id: Some("a9b3ee5b-fa40-4c4f-9f6f-1e2ea5b1d2c4".to_string()),
+            ..Default::default()
+        }.save(&library));
+        let groups = find_duplicate_tracks(&library, &DuplicateOptions::default());
+        assert!(groups.len() == 1);
+        assert!(groups[0].reason == DuplicateReason::MusicBrainzId);
+        assert!(groups[0].confidence == 1.0);
+        assert!(groups[0].tracks.len() == 2);
+        assert!(tracks.iter().all(|track| groups[0].tracks.contains(track)));
+    }
+
+    #[test]
+    fn fingerprint() {
+        let library = Library::open_memory();
+        let fingerprint = Fingerprint {
+            sub_fingerprints: (0..100u32).map(|i| i.wrapping_mul(2_654_435_761)).collect(),
+            duration_ms: 200_000,
+        };
+        let tracks = ["Track 01", "Unknown"].map(|title| {
+            let track = Track {
+                title: Some(title.to_string()),
+                ..Default::default()
+            }.save(&library);
+            let media_file = MediaFile {
+                file_path: format!("/music/{}.mp3", title),
+                fingerprint: Some(fingerprint.encode()),
+                duration_ms: Some(fingerprint.duration_ms),
+                ..Default::default()
+            }.save(&library);
+            TrackSource {
+                track_key: track.key.clone(),
+                media_file_key: media_file.key.clone(),
+                ..Default::default()
+            }.save(&library);
+            track
+        });
+        assert!(find_duplicate_tracks(&library, &DuplicateOptions::default()).is_empty());
+        let groups = find_duplicate_tracks(&library, &DuplicateOptions {
+            use_fingerprints: true,
+            ..Default::default()
+        });
+        assert!(groups.len() == 1);
+        assert!(groups[0].reason == DuplicateReason::Fingerprint);
+        assert!(tracks.iter().all(|track| groups[0].tracks.contains(track)));
+    }
+
+    #[test]
+    fn merge_keeps_history() {
+        let library = Library::open_memory();
+        let perturbator = Artist {
+            name: Some("Perturbator".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let other = Artist {
+            name: Some("Other".to_string()),
+            ..Default::default()
+        }.save(&library);
+        // No releases, so no album names.
+        let mp3 = Track {
+            title: Some("Future Club".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let flac = Track {
+            title: Some("future club".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let cover = Track {
+            title: Some("future club".to_string()),
+            ..Default::default()
+        }.save(&library);
+        ArtistRef::attach(&library, &perturbator, &mp3);
+        ArtistRef::attach(&library, &perturbator, &flac);
+        ArtistRef::attach(&library, &other, &cover);
+        for (i, (title, artist)) in [("Future Club", "Perturbator"), ("future club", "Perturbator"),
+                ("future club", "Other")].iter().enumerate() {
+            Event {
+                event_type: "track_played".to_string(),
+                title: Some(title.to_string()),
+                artist: Some(artist.to_string()),
+                source_type: "test".to_string(),
+                source: i.to_string(),
+                ..Default::default()
+            }.save(&library);
+        }
+        let events = Event::list(&library);
+
+        let merged = merge_tracks(&library, &mp3, &flac);
+        assert!(Event::list(&library) == events);
+        assert!(query::tracks(&library, "plays:2").unwrap() == vec![merged]);
+        assert!(query::tracks(&library, "plays:1").unwrap() == vec![cover]);
+    }
+}
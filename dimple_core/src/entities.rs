@@ -0,0 +1,331 @@
+//! Manual fixes for entities the librarian matched wrong: merging two
+//! Artists, Releases or Genres that are the same, and splitting one that is
+//! really two.
+//!
+//! Merges move every reference to the merged entity over to the survivor,
+//! and leave an Alias so that future imports of the merged entity's name or
+//! MusicBrainz id resolve to the survivor.
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{librarian, library::Library, merge::CrdtRules, model::{Alias, Artist, Genre, LibraryModel, Medium, ModelBasics as _, Release, Track}};
+
+/// The Ref tables, all of which refer to the model they are attached to by
+/// model_key.
+const REF_TABLES: [&str; 4] = ["ArtistRef", "GenreRef", "LinkRef", "DimageRef"];
+
+pub trait Entity: LibraryModel + CrdtRules {
+    /// The Ref table that attaches this entity to other models, and its
+    /// column for the entity key.
+    const REF: Option<(&'static str, &'static str)>;
+    /// Other (table, column) pairs that refer to this entity by key.
+    const KEY_COLUMNS: &'static [(&'static str, &'static str)];
+
+    fn name(&self) -> Option<String>;
+    fn musicbrainz_id(&self) -> Option<String>;
+
+    /// Called with the survivor after a merge.
+    fn after_merge(&self, _library: &Library) {}
+
+    /// Called with the new entity after a split.
+    fn after_split(&self, _library: &Library, _original: &Self) {}
+}
+
+impl Entity for Artist {
+    const REF: Option<(&'static str, &'static str)> = Some(("ArtistRef", "artist_key"));
+    const KEY_COLUMNS: &'static [(&'static str, &'static str)] = &[];
+
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    fn musicbrainz_id(&self) -> Option<String> {
+        self.musicbrainz_id.clone()
+    }
+}
+
+impl Entity for Genre {
+    const REF: Option<(&'static str, &'static str)> = Some(("GenreRef", "genre_key"));
+    const KEY_COLUMNS: &'static [(&'static str, &'static str)] = &[];
+
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    fn musicbrainz_id(&self) -> Option<String> {
+        self.musicbrainz_id.clone()
+    }
+}
+
+impl Entity for Release {
+    const REF: Option<(&'static str, &'static str)> = None;
+    const KEY_COLUMNS: &'static [(&'static str, &'static str)] = &[("Track", "release_key"), ("Medium", "release_key")];
+
+    fn name(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    fn musicbrainz_id(&self) -> Option<String> {
+        self.musicbrainz_id.clone()
+    }
+
+    /// Both releases may have had a Medium at the same position. Tracks are
+    /// moved to the first one and the rest are removed.
+    fn after_merge(&self, library: &Library) {
+        let conn = library.conn();
+        conn.execute("
+            UPDATE Track SET medium_key = (
+                SELECT m2.key FROM Medium m1
+                JOIN Medium m2 ON (m2.release_key = m1.release_key AND m2.position IS m1.position)
+                WHERE m1.key = Track.medium_key
+                ORDER BY m2.rowid ASC LIMIT 1)
+            WHERE release_key = ?1 AND medium_key IS NOT NULL",
+            (&self.key,)).unwrap();
+        conn.execute("
+            DELETE FROM Medium
+            WHERE release_key = ?1 AND EXISTS (
+                SELECT 1 FROM Medium m2
+                WHERE m2.release_key = Medium.release_key AND m2.position IS Medium.position
+                    AND m2.rowid < Medium.rowid)",
+            (&self.key,)).unwrap();
+    }
+
+    /// The moved tracks get Media on the new release at the same positions.
+    fn after_split(&self, library: &Library, _original: &Self) {
+        let tracks: Vec<Track> = library.query("SELECT * FROM Track WHERE release_key = ?1", (&self.key,));
+        for mut track in tracks {
+            let Some(old_medium) = track.medium(library) else {
+                continue
+            };
+            if old_medium.release_key == self.key {
+                continue
+            }
+            let medium = librarian::merge_medium(library, &Medium {
+                key: None,
+                release_key: self.key.clone(),
+                ..old_medium
+            });
+            track.medium_key = medium.key;
+            track.save(library);
+        }
+    }
+}
+
+/// Merge `other` into `entity`. Everything that referred to `other` refers
+/// to `entity` afterwards, the fields are combined with CrdtRules, `other`
+/// is deleted, and an Alias records its name and MusicBrainz id.
+pub fn merge_entities<T: Entity>(library: &Library, entity: &T, other: &T) -> T {
+    let merged = merge_entity_rows(library, entity, other);
+    library.log_change(&entity.type_name(), &other.key().unwrap(), "merge", None, entity.key());
+    merged
+}
+
+/// Merge without recording the change, for replaying a merge from sync.
+pub(crate) fn merge_entity_rows<T: Entity>(library: &Library, entity: &T, other: &T) -> T {
+    let type_name = entity.type_name();
+    let (key, other_key) = (entity.key(), other.key());
+    log::info!("Merging {} {:?} into {:?}", type_name, other_key, key);
+
+    // All or nothing, so refs are never left pointing at a deleted row.
+    library.transaction(|conn| {
+        for table in REF_TABLES {
+            // Refs the survivor already has stay as they are.
+            conn.execute(&format!("UPDATE OR IGNORE {} SET model_key = ?1 WHERE model_key = ?2", table),
+                (&key, &other_key)).unwrap();
+            conn.execute(&format!("DELETE FROM {} WHERE model_key = ?1", table),
+                (&other_key,)).unwrap();
+        }
+        if let Some((table, column)) = T::REF {
+            conn.execute(&format!("UPDATE OR IGNORE {0} SET {1} = ?1 WHERE {1} = ?2", table, column),
+                (&key, &other_key)).unwrap();
+            conn.execute(&format!("DELETE FROM {} WHERE {} = ?1", table, column),
+                (&other_key,)).unwrap();
+        }
+        for (table, column) in T::KEY_COLUMNS {
+            conn.execute(&format!("UPDATE {0} SET {1} = ?1 WHERE {1} = ?2", table, column),
+                (&key, &other_key)).unwrap();
+        }
+        conn.execute("UPDATE Alias SET model_key = ?1 WHERE model_key = ?2", (&key, &other_key)).unwrap();
+        Library::delete_row(conn, other);
+    });
+    library.notify(other);
+
+    if other.name().is_some() || other.musicbrainz_id().is_some() {
+        Alias {
+            key: None,
+            model: type_name.clone(),
+            model_key: key.clone().unwrap(),
+            name: other.name(),
+            musicbrainz_id: other.musicbrainz_id(),
+            source: Some("merge".to_string()),
+        }.save(library);
+    }
+
+    let entity = library.get::<T>(&key.clone().unwrap()).unwrap_or(entity.clone());
+    let mut merged = CrdtRules::merge(entity, other.clone());
+    merged.set_key(key);
+    let merged = library.save(&merged);
+    merged.after_merge(library);
+    merged
+}
+
+/// Split the references from the models with keys in `refs` off of
+/// `entity` onto a new entity, created from `split`. For Artists and Genres
+/// the refs are the keys of the models they are attached to, and for
+/// Releases the keys of the Tracks to move. `split` needs to differ from
+/// `entity` in name or disambiguation, since those are unique.
+pub fn split_entity<T: Entity>(library: &Library, entity: &T, split: &T, refs: &[String]) -> T {
+    let mut split = split.clone();
+    split.set_key(None);
+    let split = library.save(&split);
+    split_entity_rows(library, entity, &split, refs);
+    // The new entity goes in the change too, since entities aren't
+    // otherwise synced.
+    library.log_change(&split.type_name(), &entity.key().unwrap(), "split",
+        None, Some(serde_json::json!({ "split": split, "refs": refs }).to_string()));
+    split
+}
+
+/// Split without recording the change, for replaying a split from sync.
+/// `split` must already be saved.
+pub(crate) fn split_entity_rows<T: Entity>(library: &Library, entity: &T, split: &T, refs: &[String]) {
+    let (key, split_key) = (entity.key(), split.key());
+    log::info!("Splitting {} {:?} from {:?}", split.type_name(), split_key, key);
+
+    let conn = library.conn();
+    for model_key in refs {
+        if let Some((table, column)) = T::REF {
+            conn.execute(&format!("UPDATE OR IGNORE {0} SET {1} = ?1 WHERE {1} = ?2 AND model_key = ?3", table, column),
+                (&split_key, &key, model_key)).unwrap();
+        }
+        for (table, column) in T::KEY_COLUMNS {
+            conn.execute(&format!("UPDATE {0} SET {1} = ?1 WHERE {1} = ?2 AND key = ?3", table, column),
+                (&split_key, &key, model_key)).unwrap();
+        }
+    }
+    drop(conn);
+
+    split.after_split(library, entity);
+}
+
+/// Replay a merge of an Artist, Release or Genre recorded by another
+/// library, if both entities exist here.
+pub(crate) fn apply_merge(library: &Library, model: &str, model_key: &str, into_key: &str) {
+    fn apply<T: Entity>(library: &Library, model_key: &str, into_key: &str) {
+        if let (Some(entity), Some(other)) = (library.get::<T>(into_key), library.get::<T>(model_key)) {
+            merge_entity_rows(library, &entity, &other);
+        }
+    }
+    match model {
+        "Artist" => apply::<Artist>(library, model_key, into_key),
+        "Release" => apply::<Release>(library, model_key, into_key),
+        "Genre" => apply::<Genre>(library, model_key, into_key),
+        _ => log::warn!("Can't apply merge of {}", model),
+    }
+}
+
+/// Replay a split of an Artist, Release or Genre recorded by another
+/// library, if the entity exists here and the split hasn't been replayed
+/// already. `value` is the value of the "split" ChangeLog.
+pub(crate) fn apply_split(library: &Library, model: &str, model_key: &str, value: &str) {
+    #[derive(Deserialize)]
+    struct Split<T> {
+        split: T,
+        refs: Vec<String>,
+    }
+
+    fn apply<T: Entity + DeserializeOwned>(library: &Library, model_key: &str, value: &str) {
+        let split = match serde_json::from_str::<Split<T>>(value) {
+            Ok(split) => split,
+            Err(e) => return log::warn!("Can't apply split of {}: {}", model_key, e),
+        };
+        let Some(split_key) = split.split.key() else {
+            return
+        };
+        if library.get::<T>(&split_key).is_some() {
+            return
+        }
+        if let Some(entity) = library.get::<T>(model_key) {
+            let new = library.insert(&split.split);
+            split_entity_rows(library, &entity, &new, &split.refs);
+        }
+    }
+    match model {
+        "Artist" => apply::<Artist>(library, model_key, value),
+        "Release" => apply::<Release>(library, model_key, value),
+        "Genre" => apply::<Genre>(library, model_key, value),
+        _ => log::warn!("Can't apply split of {}", model),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{librarian::{self, ArtistMetadata}, library::Library, model::{Artist, ArtistRef, Genre, GenreRef, ModelBasics as _, Release, Track}};
+
+    use super::{merge_entities, split_entity};
+
+    #[test]
+    fn merge_artists() {
+        let library = Library::open_memory();
+        let nirvana = Artist {
+            name: Some("Nirvana".to_string()),
+            musicbrainz_id: Some("5b11f4ce-a62d-471e-81fc-a69a8278c7da".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let nirvana2 = Artist {
+            name: Some("Nirvana (US)".to_string()),
+            country: Some("US".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let track = Track::default().save(&library);
+        let release = Release::default().save(&library);
+        ArtistRef::attach(&library, &nirvana, &track);
+        ArtistRef::attach(&library, &nirvana2, &track);
+        ArtistRef::attach(&library, &nirvana2, &release);
+        GenreRef::attach(&library, &Genre::new("grunge").save(&library), &nirvana2);
+
+        let events = library.notifier.observer();
+        let merged = merge_entities(&library, &nirvana, &nirvana2);
+        assert!(merged.key == nirvana.key);
+        assert!(events.try_iter().any(|event| Some(event.key) == nirvana2.key));
+        assert!(merged.country == Some("US".to_string()));
+        assert!(Artist::get(&library, &nirvana2.key.clone().unwrap()).is_none());
+        assert!(track.artists(&library) == vec![merged.clone()]);
+        assert!(release.artists(&library) == vec![merged.clone()]);
+        assert!(merged.genres(&library).len() == 1);
+
+        // Future imports of the merged name resolve to the survivor.
+        let matched = librarian::merge_artist_metadata(&library, &ArtistMetadata {
+            artist: Artist {
+                name: Some("Nirvana (US)".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }, None);
+        assert!(matched.key == nirvana.key);
+    }
+
+    #[test]
+    fn split_release() {
+        let library = Library::open_memory();
+        let release = Release {
+            title: Some("Greatest Hits".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let tracks = (0..4).map(|_| Track {
+                release_key: release.key.clone(),
+                ..Default::default()
+            }.save(&library))
+            .collect::<Vec<_>>();
+        let split = split_entity(&library, &release, &Release {
+            title: Some("Greatest Hits".to_string()),
+            disambiguation: Some("Queen".to_string()),
+            ..Default::default()
+        }, &[tracks[2].key.clone().unwrap(), tracks[3].key.clone().unwrap()]);
+        assert!(release.tracks(&library).len() == 2);
+        assert!(split.tracks(&library).len() == 2);
+
+        let merged = merge_entities(&library, &release, &split);
+        assert!(merged.tracks(&library).len() == 4);
+    }
+}
@@ -0,0 +1,570 @@
+//! Audio fingerprints compatible with Chromaprint's default algorithm, the
+//! one used by AcoustID. The audio is decoded with symphonia, mixed to mono
+//! and resampled to 11025 Hz, and the first two minutes are turned into
+//! chroma features and then into one 32 bit sub-fingerprint per frame.
+//!
+//! Fingerprints are stored on the MediaFile in Chromaprint's compressed,
+//! base64 encoded form, which is also what the AcoustID API accepts.
+//! Comparing two fingerprints needs no network, which is what duplicate
+//! detection uses.
+
+use std::{f64::consts::PI, io::Cursor, path::Path};
+
+use anyhow::anyhow;
+use rayon::iter::{IntoParallelIterator as _, ParallelIterator as _};
+use rustfft::{num_complex::Complex, FftPlanner};
+use symphonia::core::{audio::SampleBuffer, codecs::{DecoderOptions, CODEC_TYPE_NULL}, errors::Error as SymphoniaError, formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+
+use crate::{library::Library, model::{MediaFile, ModelBasics as _}};
+
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_STEP: usize = FRAME_SIZE / 3;
+const MAX_SECONDS: usize = 120;
+const MIN_FREQ: f64 = 28.;
+const MAX_FREQ: f64 = 3520.;
+const NUM_BANDS: usize = 12;
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+/// Chromaprint's algorithm id for the default algorithm, as written in the
+/// compressed fingerprint header.
+const ALGORITHM: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub sub_fingerprints: Vec<u32>,
+    /// The duration of the whole file, not only of the fingerprinted part.
+    pub duration_ms: u64,
+}
+
+impl Fingerprint {
+    /// Chromaprint's compressed fingerprint, base64 encoded with the URL
+    /// safe alphabet and no padding.
+    pub fn encode(&self) -> String {
+        base64_encode(&compress(&self.sub_fingerprints))
+    }
+
+    pub fn decode(encoded: &str, duration_ms: u64) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            sub_fingerprints: decompress(&base64_decode(encoded)?)?,
+            duration_ms,
+        })
+    }
+
+    pub fn from_media_file(media_file: &MediaFile) -> Option<Self> {
+        Self::decode(media_file.fingerprint.as_deref()?, media_file.duration_ms?).ok()
+    }
+}
+
+/// Fingerprint the MediaFile and store the fingerprint on it.
+pub fn fingerprint_media_file(library: &Library, media_file: &MediaFile) -> Result<MediaFile, anyhow::Error> {
+    let content = library.load_media_file_content(media_file)
+        .ok_or(anyhow!("Unable to load {}", media_file.file_path))?;
+    let extension = Path::new(&media_file.file_path).extension()
+        .and_then(|extension| extension.to_str());
+    let fingerprint = fingerprint(content, extension)?;
+    let mut media_file = media_file.clone();
+    media_file.fingerprint = Some(fingerprint.encode());
+    media_file.duration_ms = Some(fingerprint.duration_ms);
+    Ok(media_file.save(library))
+}
+
+/// Fingerprint every MediaFile that doesn't have a fingerprint yet.
+pub fn fingerprint_library(library: &Library) {
+    let media_files = library.query::<MediaFile, _>("
+        SELECT * FROM MediaFile WHERE fingerprint IS NULL
+    ", ());
+    log::info!("Fingerprinting {} media files.", media_files.len());
+    media_files.into_par_iter().for_each(|media_file| {
+        if let Err(e) = fingerprint_media_file(library, &media_file) {
+            log::error!("Error fingerprinting {}: {}", media_file.file_path, e);
+        }
+    });
+}
+
+/// Fingerprint encoded audio, such as the content of an MP3 or FLAC file.
+pub fn fingerprint(content: Vec<u8>, extension: Option<&str>) -> Result<Fingerprint, anyhow::Error> {
+    let (samples, sample_rate, duration_ms) = decode(content, extension)?;
+    let samples = resample(&samples, sample_rate, SAMPLE_RATE);
+    Ok(Fingerprint {
+        sub_fingerprints: sub_fingerprints(&chroma_image(&samples)),
+        duration_ms,
+    })
+}
+
+/// How similar two fingerprints are, from 0.0 to 1.0, as the fraction of
+/// matching bits at the best alignment within a few seconds. Unrelated
+/// audio scores around 0.5, and the same recording in different encodings
+/// usually scores above 0.85.
+pub fn similarity(a: &Fingerprint, b: &Fingerprint) -> f64 {
+    const MAX_OFFSET: isize = 80;
+    const MIN_OVERLAP: usize = 50;
+    let (a, b) = (&a.sub_fingerprints, &b.sub_fingerprints);
+    let mut best = 0.;
+    for offset in -MAX_OFFSET..=MAX_OFFSET {
+        let pairs = a.iter().enumerate()
+            .filter_map(|(i, x)| b.get(usize::try_from(i as isize + offset).ok()?).map(|y| (x, y)))
+            .collect::<Vec<_>>();
+        if pairs.len() < MIN_OVERLAP {
+            continue
+        }
+        let errors: u32 = pairs.iter().map(|(x, y)| (*x ^ *y).count_ones()).sum();
+        let score = 1. - errors as f64 / (pairs.len() * 32) as f64;
+        if score > best {
+            best = score;
+        }
+    }
+    best
+}
+
+/// Pairs of fingerprinted MediaFiles that contain the same audio, with
+/// their similarity, most similar first. Only files of about the same
+/// duration are compared.
+pub fn find_duplicate_media_files(library: &Library, min_similarity: f64) -> Vec<(MediaFile, MediaFile, f64)> {
+    const MAX_DURATION_DIFF_MS: u64 = 10_000;
+    let mut fingerprinted = library.query::<MediaFile, _>("
+        SELECT * FROM MediaFile WHERE fingerprint IS NOT NULL AND duration_ms IS NOT NULL
+    ", ()).into_iter()
+        .filter_map(|media_file| Fingerprint::from_media_file(&media_file).map(|fp| (media_file, fp)))
+        .collect::<Vec<_>>();
+    fingerprinted.sort_by_key(|(_, fp)| fp.duration_ms);
+
+    let mut duplicates = vec![];
+    for (i, (a, a_fp)) in fingerprinted.iter().enumerate() {
+        for (b, b_fp) in fingerprinted[i + 1..].iter() {
+            if b_fp.duration_ms - a_fp.duration_ms > MAX_DURATION_DIFF_MS {
+                break
+            }
+            let score = similarity(a_fp, b_fp);
+            if score >= min_similarity {
+                duplicates.push((a.clone(), b.clone(), score));
+            }
+        }
+    }
+    duplicates.sort_by(|a, b| b.2.total_cmp(&a.2));
+    duplicates
+}
+
+/// Decode up to MAX_SECONDS of audio as mono samples. Returns the samples,
+/// their sample rate, and the duration of the whole stream.
+fn decode(content: Vec<u8>, extension: Option<&str>) -> Result<(Vec<f64>, u32, u64), anyhow::Error> {
+    let mss = MediaSourceStream::new(Box::new(Cursor::new(content)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+    let track = format.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(anyhow!("No audio track"))?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or(anyhow!("Unknown sample rate"))?;
+    let n_frames = track.codec_params.n_frames;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    let max_samples = MAX_SECONDS * sample_rate as usize;
+    let mut samples = vec![];
+    let mut frames_decoded = 0u64;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(e)) => {
+                log::debug!("Skipping undecodable packet: {}", e);
+                continue
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channels) {
+            frames_decoded += 1;
+            if samples.len() < max_samples {
+                samples.push(frame.iter().map(|s| *s as f64).sum::<f64>() / channels as f64);
+            }
+        }
+        // The rest of the stream is only needed for the duration.
+        if samples.len() >= max_samples && n_frames.is_some() {
+            break
+        }
+    }
+    let duration_ms = n_frames.unwrap_or(frames_decoded) * 1000 / sample_rate as u64;
+    Ok((samples, sample_rate, duration_ms))
+}
+
+/// Windowed sinc resampling, with the cutoff at the lower of the two
+/// Nyquist frequencies so that nothing above it aliases into the chroma
+/// range.
+fn resample(samples: &[f64], from: u32, to: u32) -> Vec<f64> {
+    if from == to {
+        return samples.to_vec()
+    }
+    let ratio = from as f64 / to as f64;
+    let cutoff = (to as f64 / from as f64).min(1.);
+    let radius = (8. * ratio.max(1.)).ceil() as isize;
+    let sinc = |x: f64| if x == 0. { 1. } else { (PI * x).sin() / (PI * x) };
+    let len = (samples.len() as f64 / ratio) as usize;
+    (0..len).map(|i| {
+            let center = i as f64 * ratio;
+            let first = (center.floor() as isize - radius).max(0);
+            let last = (center.floor() as isize + radius).min(samples.len() as isize - 1);
+            (first..=last).map(|j| {
+                    let d = center - j as f64;
+                    let window = 0.5 * (1. + (PI * d / radius as f64).cos());
+                    samples[j as usize] * cutoff * sinc(cutoff * d) * window
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// The filtered and normalized chroma features, one row of NUM_BANDS per
+/// frame.
+fn chroma_image(samples: &[f64]) -> Vec<[f64; NUM_BANDS]> {
+    let window = (0..FRAME_SIZE)
+        .map(|i| 0.54 - 0.46 * (2. * PI * i as f64 / (FRAME_SIZE - 1) as f64).cos())
+        .collect::<Vec<_>>();
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+
+    // The note each FFT bin contributes to.
+    let freq_to_index = |freq: f64| (FRAME_SIZE as f64 * freq / SAMPLE_RATE as f64).round() as usize;
+    let min_index = freq_to_index(MIN_FREQ).max(1);
+    let max_index = freq_to_index(MAX_FREQ).min(FRAME_SIZE / 2);
+    let notes = (0..max_index)
+        .map(|i| {
+            let freq = i as f64 * SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+            let octave = (freq / (440. / 16.)).log2();
+            ((NUM_BANDS as f64 * (octave - octave.floor())) as usize).min(NUM_BANDS - 1)
+        })
+        .collect::<Vec<_>>();
+
+    let mut chroma = vec![];
+    let mut buffer = vec![Complex::new(0., 0.); FRAME_SIZE];
+    let mut start = 0;
+    while start + FRAME_SIZE <= samples.len() {
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.);
+        }
+        fft.process(&mut buffer);
+        let mut features = [0.; NUM_BANDS];
+        for i in min_index..max_index {
+            features[notes[i]] += buffer[i].norm_sqr();
+        }
+        chroma.push(features);
+        start += FRAME_STEP;
+    }
+
+    chroma.windows(CHROMA_FILTER.len())
+        .map(|rows| {
+            let mut features = [0.; NUM_BANDS];
+            for (row, coefficient) in rows.iter().zip(CHROMA_FILTER) {
+                for (feature, value) in features.iter_mut().zip(row) {
+                    *feature += value * coefficient;
+                }
+            }
+            let norm = features.iter().map(|f| f * f).sum::<f64>().sqrt();
+            if norm < 0.01 {
+                [0.; NUM_BANDS]
+            }
+            else {
+                features.map(|f| f / norm)
+            }
+        })
+        .collect()
+}
+
+/// A Haar-like filter over the image and the thresholds that quantize its
+/// response into two bits. `y` and `height` are in bands, `width` in
+/// frames.
+struct Classifier {
+    filter_type: u8,
+    y: usize,
+    height: usize,
+    width: usize,
+    thresholds: [f64; 3],
+}
+
+const fn classifier(filter_type: u8, y: usize, height: usize, width: usize, thresholds: [f64; 3]) -> Classifier {
+    Classifier { filter_type, y, height, width, thresholds }
+}
+
+/// The classifiers of Chromaprint's default algorithm.
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(0, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(4, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(1, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(3, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(3, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(4, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(1, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(5, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(3, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(3, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(1, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(3, 4, 2, 14, [-0.164292, -0.0321188, 0.0846339]),
+];
+
+fn sub_fingerprints(image: &[[f64; NUM_BANDS]]) -> Vec<u32> {
+    // Integral image with a zero row and column in front, so that
+    // integral[r][c] is the sum of image[..r][..c].
+    let mut integral = vec![[0.; NUM_BANDS + 1]; image.len() + 1];
+    for (r, row) in image.iter().enumerate() {
+        for c in 0..NUM_BANDS {
+            integral[r + 1][c + 1] = row[c] + integral[r][c + 1] + integral[r + 1][c] - integral[r][c];
+        }
+    }
+    let area = |x1: usize, y1: usize, x2: usize, y2: usize| {
+        integral[x2][y2] - integral[x1][y2] - integral[x2][y1] + integral[x1][y1]
+    };
+    let compare = |a: f64, b: f64| ((1. + a) / (1. + b)).ln();
+    let classify = |classifier: &Classifier, x: usize| {
+        let (y, w, h) = (classifier.y, classifier.width, classifier.height);
+        let value = match classifier.filter_type {
+            0 => compare(area(x, y, x + w, y + h), 0.),
+            1 => {
+                let h_2 = h / 2;
+                compare(area(x, y + h_2, x + w, y + h), area(x, y, x + w, y + h_2))
+            },
+            2 => {
+                let w_2 = w / 2;
+                compare(area(x + w_2, y, x + w, y + h), area(x, y, x + w_2, y + h))
+            },
+            3 => {
+                let (w_2, h_2) = (w / 2, h / 2);
+                compare(area(x, y + h_2, x + w_2, y + h) + area(x + w_2, y, x + w, y + h_2),
+                    area(x, y, x + w_2, y + h_2) + area(x + w_2, y + h_2, x + w, y + h))
+            },
+            4 => {
+                let h_3 = h / 3;
+                compare(area(x, y + h_3, x + w, y + 2 * h_3),
+                    area(x, y, x + w, y + h_3) + area(x, y + 2 * h_3, x + w, y + h))
+            },
+            _ => {
+                let w_3 = w / 3;
+                compare(area(x + w_3, y, x + 2 * w_3, y + h),
+                    area(x, y, x + w_3, y + h) + area(x + 2 * w_3, y, x + w, y + h))
+            },
+        };
+        let [t0, t1, t2] = classifier.thresholds;
+        let quantized = if value < t1 {
+            if value < t0 { 0 } else { 1 }
+        }
+        else if value < t2 { 2 } else { 3 };
+        // Gray code
+        [0u32, 1, 3, 2][quantized]
+    };
+
+    let max_width = CLASSIFIERS.iter().map(|c| c.width).max().unwrap();
+    if image.len() < max_width {
+        return vec![]
+    }
+    (0..=image.len() - max_width)
+        .map(|x| CLASSIFIERS.iter().fold(0u32, |bits, classifier| (bits << 2) | classify(classifier, x)))
+        .collect()
+}
+
+/// Chromaprint's fingerprint compression. Each sub-fingerprint is XORed
+/// with the previous one and stored as the gaps between its set bits,
+/// ending with a zero. Gaps are packed as 3 bit values, with values of 7
+/// and above continued in a 5 bit exception list.
+fn compress(sub_fingerprints: &[u32]) -> Vec<u8> {
+    let mut gaps = vec![];
+    let mut previous = 0;
+    for sub_fingerprint in sub_fingerprints {
+        let mut x = sub_fingerprint ^ previous;
+        previous = *sub_fingerprint;
+        let (mut bit, mut last_bit) = (1, 0);
+        while x != 0 {
+            if x & 1 != 0 {
+                gaps.push(bit - last_bit);
+                last_bit = bit;
+            }
+            x >>= 1;
+            bit += 1;
+        }
+        gaps.push(0);
+    }
+    let len = sub_fingerprints.len();
+    let mut output = vec![ALGORITHM, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    output.extend(pack(gaps.iter().map(|gap| (*gap).min(7)), 3));
+    output.extend(pack(gaps.iter().filter(|gap| **gap >= 7).map(|gap| gap - 7), 5));
+    output
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u32>, anyhow::Error> {
+    if data.len() < 4 {
+        return Err(anyhow!("Fingerprint is too short"))
+    }
+    let len = (data[1] as usize) << 16 | (data[2] as usize) << 8 | data[3] as usize;
+    let data = &data[4..];
+
+    // Read 3 bit values until the terminating zero of the last
+    // sub-fingerprint.
+    let mut gaps = vec![];
+    let mut zeros = 0;
+    let mut i = 0;
+    while zeros < len {
+        let gap = unpack(data, i, 3).ok_or(anyhow!("Fingerprint is truncated"))?;
+        if gap == 0 {
+            zeros += 1;
+        }
+        gaps.push(gap);
+        i += 1;
+    }
+    let exception_offset = (gaps.len() * 3).div_ceil(8) * 8;
+    let mut exceptions = 0;
+    for gap in gaps.iter_mut().filter(|gap| **gap == 7) {
+        *gap += unpack_at(data, exception_offset + exceptions * 5, 5)
+            .ok_or(anyhow!("Fingerprint is truncated"))?;
+        exceptions += 1;
+    }
+
+    let mut sub_fingerprints = Vec::with_capacity(len);
+    let (mut x, mut last_bit, mut previous) = (0u32, 0, 0u32);
+    for gap in gaps {
+        if gap == 0 {
+            previous ^= x;
+            sub_fingerprints.push(previous);
+            x = 0;
+            last_bit = 0;
+        }
+        else {
+            last_bit += gap;
+            if last_bit > 32 {
+                return Err(anyhow!("Invalid fingerprint"))
+            }
+            x |= 1 << (last_bit - 1);
+        }
+    }
+    Ok(sub_fingerprints)
+}
+
+/// Pack values into bytes, least significant bit first.
+fn pack(values: impl Iterator<Item = u32>, bits: usize) -> Vec<u8> {
+    let mut output = vec![];
+    let (mut acc, mut acc_bits) = (0u32, 0);
+    for value in values {
+        acc |= value << acc_bits;
+        acc_bits += bits;
+        while acc_bits >= 8 {
+            output.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    }
+    if acc_bits > 0 {
+        output.push(acc as u8);
+    }
+    output
+}
+
+fn unpack(data: &[u8], index: usize, bits: usize) -> Option<u32> {
+    unpack_at(data, index * bits, bits)
+}
+
+fn unpack_at(data: &[u8], bit_offset: usize, bits: usize) -> Option<u32> {
+    let mut value = 0;
+    for i in 0..bits {
+        let bit = bit_offset + i;
+        let byte = data.get(bit / 8)?;
+        value |= ((*byte as u32 >> (bit % 8)) & 1) << i;
+    }
+    Some(value)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            output.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    output
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let values = encoded.trim_end_matches('=').bytes()
+        .map(|c| match c {
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => BASE64.iter().position(|b| *b == c).map(|i| i as u32),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(anyhow!("Invalid base64"))?;
+    let mut output = vec![];
+    for chunk in values.chunks(4) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, v)| n | v << (18 - 6 * i));
+        for i in 0..chunk.len().saturating_sub(1) {
+            output.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{library::Library, model::MediaFile};
+
+    use super::{base64_decode, base64_encode, compress, decompress, find_duplicate_media_files, fingerprint, fingerprint_library, similarity, Fingerprint};
+
+    #[test]
+    fn it_works() {
+        let content = std::fs::read("tests/data/media_files/pink-noise-30s-192kbit.mp3").unwrap();
+        let a = fingerprint(content, Some("mp3")).unwrap();
+        assert!(a.duration_ms > 29_000 && a.duration_ms < 31_000);
+        // About 8 sub-fingerprints per second.
+        assert!(a.sub_fingerprints.len() > 200);
+        let encoded = a.encode();
+        assert!(Fingerprint::decode(&encoded, a.duration_ms).unwrap() == a);
+        assert!(similarity(&a, &a) == 1.);
+    }
+
+    #[test]
+    fn compression() {
+        let sub_fingerprints = vec![0, 1, 0xffffffff, 0x80000001, 0x12345678, 0x12345678];
+        let compressed = compress(&sub_fingerprints);
+        assert!(compressed[..4] == [1, 0, 0, 6]);
+        assert!(decompress(&compressed).unwrap() == sub_fingerprints);
+    }
+
+    #[test]
+    fn base64() {
+        for data in [&b""[..], b"a", b"ab", b"abc", b"abcd", &[0xfb, 0xff]] {
+            let encoded = base64_encode(data);
+            assert!(!encoded.contains('=') && !encoded.contains('+') && !encoded.contains('/'));
+            assert!(base64_decode(&encoded).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        let library = Library::open_memory();
+        library.import("tests/data/media_files");
+        fingerprint_library(&library);
+        let media_files = library.list::<MediaFile>();
+        assert!(media_files.iter().any(|media_file| media_file.fingerprint.is_some()));
+        // Every pair found has to be similar enough.
+        for (_, _, score) in find_duplicate_media_files(&library, 0.85) {
+            assert!(score >= 0.85);
+        }
+    }
+}
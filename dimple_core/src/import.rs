@@ -1,13 +1,18 @@
 pub mod spotify;
+pub mod apple_music;
+pub mod lastfm;
 pub mod lofty_tagged_media_file;
 pub mod symphonia_tagged_media_file;
+pub mod archive;
+pub mod playlist_file;
 
 use std::path::Path;
 
-use crate::{librarian, library::Library, merge::CrdtRules, model::{Artist, Dimage, DimageRef, Genre, Link, MediaFile, ModelBasics as _, Release, Track, TrackSource}};
+use crate::{librarian, library::Library, merge::CrdtRules, model::{dimage::DimageKind, Artist, Dimage, DimageRef, Event, Genre, Link, MediaFile, ModelBasics as _, Release, Track, TrackSource}};
 
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
+use itertools::Itertools as _;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator as _};
 use lofty_tagged_media_file::LoftyTaggedMediaFile;
 use walkdir::WalkDir;
@@ -20,18 +25,74 @@ pub fn import(library: &Library, path: &str) {
     let files = scan(path);
     log::info!("Scanned {} files.", files.len());
 
+    let (images, files): (Vec<_>, Vec<_>) = files.into_iter()
+        .partition(|file| is_image(Path::new(&file.path)));
+    // Playlist files are imported explicitly, see playlist_file::import,
+    // since each import creates a new Playlist.
+    let files = files.into_iter()
+        .filter(|file| !playlist_file::is_playlist_file(Path::new(&file.path)))
+        .collect::<Vec<_>>();
+
     files.par_iter().for_each(|file| {
         let path = Path::new(&file.path);
-        if let Err(e) = import_single_file(&library, path, force) {
+        if archive::is_archive(path) {
+            if let Err(e) = import_archive(&library, path, force) {
+                log::error!("  Error reading archive {:?}: {}", path, e);
+            }
+        }
+        else if let Err(e) = import_single_file(&library, path, force) {
             log::error!("  Error reading {:?}: {}", path, e);
         }
     });
+
+    // Sidecar images go last so that the tracks next to them have already
+    // been matched to releases.
+    import_sidecar_images(library, &images);
 }
 
-fn scan(path: &str) -> Vec<ScannedFile> {
-    const IGNORE_EXTENSIONS: [&str;5] = ["jpg", "png", "pdf", "m4p", "DS_Store"];
-    const IGNORE_FILENAMES: [&str;1] = [".DS_Store"];
+/// Counts from a listening history import. Entries that can't be read are
+/// logged and skipped rather than failing the whole file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryImport {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+impl HistoryImport {
+    /// Count the result of importing the entry at index i of the file.
+    pub(crate) fn add(&mut self, path: &Path, i: usize, result: Result<(), anyhow::Error>) {
+        match result {
+            Ok(_) => self.imported += 1,
+            Err(e) => {
+                log::warn!("Skipping entry #{} in {:?}: {}", i, path, e);
+                self.skipped += 1;
+            },
+        }
+    }
+
+    pub(crate) fn append(&mut self, other: &HistoryImport) {
+        self.imported += other.imported;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Insert the listening history event, or update the existing one with the
+/// same (source_type, source), so that re-importing history is idempotent.
+pub(crate) fn save_history_event(library: &Library, event: &Event) -> Event {
+    let existing = Event::find(library,
+        "SELECT * FROM Event WHERE source_type = ?1 AND source = ?2",
+        (&event.source_type, &event.source));
+    library.save(&Event {
+        key: existing.and_then(|existing| existing.key),
+        ..event.clone()
+    })
+}
 
+const IGNORE_EXTENSIONS: [&str;3] = ["pdf", "m4p", "DS_Store"];
+const IGNORE_FILENAMES: [&str;1] = [".DS_Store"];
+const IMAGE_EXTENSIONS: [&str;3] = ["jpg", "jpeg", "png"];
+
+fn scan(path: &str) -> Vec<ScannedFile> {
     let files = WalkDir::new(path).into_iter()
         .filter_map(|dir_entry| dir_entry.ok())
         .filter(|dir_entry| dir_entry.file_type().is_file())
@@ -54,18 +115,106 @@ fn import_single_file(library: &Library, path: &Path, _force: bool) -> Result<Tr
 
     // Read the tags from the file.
     let tags = LoftyTaggedMediaFile::new(path)?;
+
+    // Create or update a MediaFile by the file path.
+    let mut media_file = library.find_media_file_by_file_path(path.to_str().unwrap())
+        .unwrap_or_default();
+    media_file.file_path = path.to_str().unwrap().to_string();
+    media_file.last_imported = Utc::now();
+    let last_modified = path.metadata()?.modified()?.into();
+    clear_fingerprint_if_modified(&mut media_file, last_modified);
+    media_file.last_modified = last_modified;
+
+    import_tagged_media_file(library, &tags, &media_file)
+}
+
+/// Import the audio and cover art inside of a zip or tar archive. Each
+/// audio member gets a MediaFile with archive_path and archive_member set,
+/// so the content can be streamed back out of the archive later. Images
+/// are attached as cover art to the releases of the tracks next to them.
+fn import_archive(library: &Library, path: &Path, _force: bool) -> Result<Vec<TrackSource>, anyhow::Error> {
+    log::info!("Importing archive {:?}.", path);
+    let last_modified: DateTime<Utc> = path.metadata()?.modified()?.into();
+
+    // Images are kept until the pass is done, so that they can be attached
+    // to the tracks next to them.
+    let mut image_members: Vec<(String, DimageKind, Vec<u8>)> = vec![];
+    let mut imported: Vec<(String, TrackSource)> = vec![];
+    archive::read_members(path, |name, content| {
+        let member_path = Path::new(name);
+        let file_name = member_path.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        let extension = member_path.extension().unwrap_or_default().to_ascii_lowercase();
+        let extension = extension.to_str().unwrap_or_default();
+        if IMAGE_EXTENSIONS.contains(&extension) {
+            if let (Some(kind), Ok(content)) = (sidecar_image_kind(name), content) {
+                image_members.push((name.to_string(), kind, content));
+            }
+            return
+        }
+        if IGNORE_FILENAMES.contains(&file_name) || IGNORE_EXTENSIONS.contains(&extension) {
+            return
+        }
+        match content.and_then(|content| import_archive_member(library, path, name, content, last_modified)) {
+            Ok(track_source) => imported.push((name.to_string(), track_source)),
+            Err(e) => log::error!("  Error reading {:?} in {:?}: {}", name, path, e),
+        }
+    })?;
+
+    for (name, kind, content) in image_members {
+        if let Ok(dymage) = image::load_from_memory(&content) {
+            let mut dimage = Dimage::new(&dymage);
+            dimage.kind = Some(kind);
+            let parent = Path::new(&name).parent();
+            let track_sources = imported.iter()
+                .filter(|(name, _)| Path::new(name).parent() == parent)
+                .map(|(_, track_source)| track_source.clone())
+                .collect::<Vec<_>>();
+            attach_sidecar_image(library, &dimage, &track_sources);
+        }
+    }
+
+    Ok(imported.into_iter().map(|(_, track_source)| track_source).collect())
+}
+
+fn import_archive_member(library: &Library, path: &Path, member: &str, content: Vec<u8>, last_modified: DateTime<Utc>) -> Result<TrackSource, anyhow::Error> {
+    let file_path = archive::member_path(path, member);
+    let tags = LoftyTaggedMediaFile::from_bytes(&file_path, content)?;
+
+    let mut media_file = library.find_media_file_by_file_path(&file_path)
+        .unwrap_or_default();
+    media_file.file_path = file_path;
+    media_file.archive_path = Some(path.to_str().unwrap().to_string());
+    media_file.archive_member = Some(member.to_string());
+    media_file.last_imported = Utc::now();
+    clear_fingerprint_if_modified(&mut media_file, last_modified);
+    media_file.last_modified = last_modified;
+
+    import_tagged_media_file(library, &tags, &media_file)
+}
+
+/// The audio may have changed along with the file, so the fingerprint is
+/// recomputed the next time it's needed.
+fn clear_fingerprint_if_modified(media_file: &mut MediaFile, last_modified: DateTime<Utc>) {
+    if media_file.last_modified != last_modified {
+        media_file.fingerprint = None;
+        media_file.duration_ms = None;
+    }
+}
+
+fn import_tagged_media_file(library: &Library, tags: &LoftyTaggedMediaFile, media_file: &MediaFile) -> Result<TrackSource, anyhow::Error> {
+    let path = &tags.path;
     let track_metadata = tags.track_metadata();
     if track_metadata.track.title.is_none() {
-        log::warn!("  No track title {}", path.to_string_lossy());
+        log::warn!("  No track title {}", path);
     }
     if track_metadata.release.is_none() {
-        log::warn!("  No release {}", path.to_string_lossy());
+        log::warn!("  No release {}", path);
     }
     if track_metadata.release.clone().unwrap().release.title.is_none() {
-        log::warn!("  No release title {}", path.to_string_lossy());
+        log::warn!("  No release title {}", path);
     }
     if track_metadata.artists.is_empty() {
-        log::warn!("  No artists {}", path.to_string_lossy());
+        log::warn!("  No artists {}", path);
     }
     // log::info!("{:?} {:?} {:?} {:?}", 
     //     path.file_name().unwrap(), 
@@ -73,12 +222,8 @@ fn import_single_file(library: &Library, path: &Path, _force: bool) -> Result<Tr
     //     track_metadata.clone().release.unwrap().release.title,
     //     track_metadata.clone().track.title);
     
-    // Create or update a MediaFile by the file path.
-    let mut media_file = library.find_media_file_by_file_path(path.to_str().unwrap())
-        .unwrap_or_default();
-    media_file.file_path = path.to_str().unwrap().to_string();
-    media_file.last_imported = Utc::now();
-    media_file.last_modified = path.metadata()?.modified()?.into();
+    let mut media_file = media_file.clone();
+    media_file.bitrate = tags.bitrate.or(media_file.bitrate);
     let media_file = media_file.save(library);
     
     // Find or create a TrackSource by the MediaFile key. This is not yet saved,
@@ -99,6 +244,89 @@ fn import_single_file(library: &Library, path: &Path, _force: bool) -> Result<Tr
     Ok(track_source)
 }
 
+/// Attach images found next to tracks, such as cover.jpg or folder.png, to
+/// the Releases of those tracks. The kind of image is guessed from the file
+/// name and images that don't look like artwork are skipped.
+fn import_sidecar_images(library: &Library, images: &[ScannedFile]) {
+    for image in images {
+        let path = Path::new(&image.path);
+        let file_name = path.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        if let Some(kind) = sidecar_image_kind(file_name) {
+            let directory = path.parent().unwrap().to_str().unwrap();
+            let track_sources = library.track_sources_in_directory(directory);
+            if track_sources.is_empty() {
+                continue
+            }
+            match image::open(path) {
+                Ok(dymage) => {
+                    let mut dimage = Dimage::new(&dymage);
+                    dimage.kind = Some(kind);
+                    attach_sidecar_image(library, &dimage, &track_sources);
+                },
+                Err(e) => log::error!("  Error reading image {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// Artist photos and logos go to the artists of the release, everything
+/// else goes to the release itself.
+fn attach_sidecar_image(library: &Library, dimage: &Dimage, track_sources: &[TrackSource]) {
+    let releases = track_sources.iter()
+        .filter_map(|track_source| track_source.track(library))
+        .filter_map(|track| track.release(library))
+        .unique_by(|release| release.key.clone())
+        .collect::<Vec<_>>();
+    for release in releases {
+        match dimage.kind {
+            Some(DimageKind::MusicArtistThumb) | Some(DimageKind::MusicHdClearLogo) => {
+                for artist in release.artists(library) {
+                    librarian::merge_images(library, &[dimage.clone()], &artist);
+                }
+            },
+            _ => librarian::merge_images(library, &[dimage.clone()], &release),
+        }
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+    IMAGE_EXTENSIONS.contains(&extension.to_str().unwrap_or_default())
+}
+
+/// Guess the kind of a sidecar image from its file name, using the names
+/// that rippers and taggers commonly use. Returns None for images that
+/// don't look like artwork, such as scans of liner notes.
+fn sidecar_image_kind(file_name: &str) -> Option<DimageKind> {
+    let path = Path::new(file_name);
+    if !is_image(path) {
+        return None
+    }
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    let words = stem.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let has_any = |names: &[&str]| words.iter().any(|word| names.contains(word));
+    if has_any(&["back", "rear", "inlay"]) {
+        Some(DimageKind::MusicAlbumBack)
+    }
+    else if has_any(&["cd", "disc", "disk", "cdart", "media"]) {
+        Some(DimageKind::MusicCdArt)
+    }
+    else if has_any(&["logo"]) {
+        Some(DimageKind::MusicHdClearLogo)
+    }
+    else if has_any(&["artist", "band"]) {
+        Some(DimageKind::MusicArtistThumb)
+    }
+    else if has_any(&["cover", "folder", "front", "album", "albumart", "albumartsmall"]) {
+        Some(DimageKind::MusicAlbumCover)
+    }
+    else {
+        None
+    }
+}
+
 fn print_track(track: &Track, library: &Library) {
     println!("{:?}", track.title);
     println!("  Artists: {:?}", track.artists(library).iter().map(|a| a.name.clone()).collect::<Vec<_>>());
@@ -115,7 +343,7 @@ struct ScannedFile {
 }
 
 mod tests {
-    use crate::{library::Library, model::MediaFile};
+    use crate::{import::sidecar_image_kind, library::Library, model::{dimage::DimageKind, MediaFile, Playlist, Release}};
 
     #[test]
     fn import() {
@@ -127,5 +355,51 @@ mod tests {
         library.import("tests/data/media_files");
         assert!(library.list::<MediaFile>().len() == num_mediafiles);
     }    
+
+    #[test]
+    fn import_skips_playlists() {
+        let library = Library::open_memory();
+        library.import("tests/data");
+        library.import("tests/data");
+        assert!(library.list::<Playlist>().is_empty());
+    }
+
+    #[test]
+    fn import_archive() {
+        let library = Library::open_memory();
+        library.import("tests/data/archives");
+        let media_files = library.list::<MediaFile>();
+        assert!(media_files.len() == 2);
+        assert!(media_files.iter().all(|m| m.archive_path.is_some() && m.archive_member.is_some()));
+        let track = &library.list::<crate::model::Track>()[0];
+        assert!(library.load_track_content(track).unwrap().len() > 0);
+        library.import("tests/data/archives");
+        assert!(library.list::<MediaFile>().len() == 2);
+    }
+
+    #[test]
+    fn import_sidecar_images() {
+        let library = Library::open_memory();
+        library.import("tests/data/sidecar_images");
+        let release = &library.list::<Release>()[0];
+        let images = release.images(&library);
+        assert!(images.len() == 2);
+        assert!(images[0].kind == Some(DimageKind::MusicAlbumCover));
+        assert!(images[1].kind == Some(DimageKind::MusicAlbumBack));
+    }
+
+    #[test]
+    fn sidecar_image_kinds() {
+        assert!(sidecar_image_kind("cover.jpg") == Some(DimageKind::MusicAlbumCover));
+        assert!(sidecar_image_kind("Folder.JPG") == Some(DimageKind::MusicAlbumCover));
+        assert!(sidecar_image_kind("front.png") == Some(DimageKind::MusicAlbumCover));
+        assert!(sidecar_image_kind("AlbumArt_{A1B2}_Large.jpg") == Some(DimageKind::MusicAlbumCover));
+        assert!(sidecar_image_kind("Back Cover.jpg") == Some(DimageKind::MusicAlbumBack));
+        assert!(sidecar_image_kind("cd.png") == Some(DimageKind::MusicCdArt));
+        assert!(sidecar_image_kind("artist.jpg") == Some(DimageKind::MusicArtistThumb));
+        assert!(sidecar_image_kind("logo.png") == Some(DimageKind::MusicHdClearLogo));
+        assert!(sidecar_image_kind("booklet-03.jpg") == None);
+        assert!(sidecar_image_kind("cover.pdf") == None);
+    }
 }
 
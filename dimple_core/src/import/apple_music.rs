@@ -0,0 +1,121 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{library::Library, model::Event};
+
+use super::HistoryImport;
+
+use anyhow::anyhow;
+use chrono::DateTime;
+use walkdir::WalkDir;
+
+/// Imports listening history from the Apple Music privacy export. The
+/// history is in "Apple Music Play Activity.csv", which has a row for each
+/// start and end of a play. Only the play end rows are imported, since they
+/// have the play duration and end reason.
+///
+/// The column set has changed over the years, so columns are looked up by
+/// name and a few alternatives are tried for each. Rows that can't be read
+/// are skipped and counted.
+pub fn import(library: &Library, path: &str) -> HistoryImport {
+    let csv_files = WalkDir::new(path).into_iter()
+        .filter_map(|dir_entry| dir_entry.ok())
+        .filter(|dir_entry| dir_entry.file_type().is_file()
+            && dir_entry.file_name().to_str().is_some_and(|name| name.ends_with("Play Activity.csv")))
+        .collect::<Vec<_>>();
+    let mut stats = HistoryImport::default();
+    for csv_file in csv_files {
+        match import_play_activity(library, csv_file.path()) {
+            Ok(file_stats) => stats.append(&file_stats),
+            Err(e) => log::error!("Error importing Apple Music history {:?}: {}", csv_file.path(), e),
+        }
+    }
+    if stats.skipped > 0 {
+        log::warn!("Skipped {} invalid Apple Music history rows.", stats.skipped);
+    }
+    stats
+}
+
+fn import_play_activity(library: &Library, path: &Path) -> Result<HistoryImport, anyhow::Error> {
+    log::info!("Importing Apple Music play activity file {:?}", path);
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let mut stats = HistoryImport::default();
+    for (i, record) in reader.records().enumerate() {
+        let event = record.map_err(anyhow::Error::from).and_then(|record| {
+            let row = headers.iter().zip(record.iter())
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(header, value)| (header.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>();
+            play_activity_event(&row)
+        });
+        match event {
+            Ok(Some(event)) => {
+                super::save_history_event(library, &event);
+                stats.add(path, i, Ok(()));
+            },
+            Ok(None) => log::debug!("Skipping row #{}.", i),
+            Err(e) => stats.add(path, i, Err(e)),
+        }
+    }
+    Ok(stats)
+}
+
+/// The event for a play end row, or None for the other rows.
+fn play_activity_event(row: &BTreeMap<String, String>) -> Result<Option<Event>, anyhow::Error> {
+    let field = |names: &[&str]| names.iter().find_map(|name| row.get(*name)).cloned();
+
+    if field(&["Event Type"]).is_some_and(|event_type| event_type != "PLAY_END") {
+        return Ok(None)
+    }
+    let title = field(&["Song Name", "Content Name"]).ok_or(anyhow!("Missing song name."))?;
+    let timestamp = field(&["Event End Timestamp", "Event Start Timestamp", "Event Received Timestamp"])
+        .ok_or(anyhow!("Missing timestamp."))?;
+    let timestamp = DateTime::parse_from_rfc3339(&timestamp)?.into();
+    let skipped = field(&["End Reason Type"]).is_some_and(|reason| reason.contains("SKIP"));
+    Ok(Some(Event {
+        timestamp,
+        event_type: if skipped { "track_skipped" } else { "track_played" }.to_string(),
+        artist: field(&["Artist Name", "Container Artist Name"]),
+        album: field(&["Album Name", "Container Album Name"]),
+        title: Some(title),
+        source_type: "apple_music::PlayActivity".to_string(),
+        source: serde_json::to_string(row).unwrap(),
+        ms_played: field(&["Play Duration Milliseconds"]).and_then(|ms| ms.parse().ok()),
+        platform: field(&["Device Type", "Build Version"]),
+        shuffle: field(&["Shuffle Play"]).map(|shuffle| shuffle.eq_ignore_ascii_case("true")),
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{import::{self, HistoryImport}, library::Library, model::Event};
+
+    #[test]
+    fn it_works() {
+        let library = Library::open_memory();
+        import::apple_music::import(&library, "tests/data/apple_music_history");
+        let events = library.list::<Event>();
+        assert!(events.len() == 2);
+        assert!(events.iter().any(|e| e.event_type == "track_skipped"));
+        assert!(events.iter().all(|e| e.ms_played.is_some()));
+        import::apple_music::import(&library, "tests/data/apple_music_history");
+        assert!(library.list::<Event>().len() == 2);
+    }
+
+    #[test]
+    fn skips_invalid_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Apple Music Play Activity.csv"), "\
+Artist Name,Event End Timestamp,Event Type,Song Name
+Perturbator,2023-01-31T21:04:47.000Z,PLAY_START,Corrupted by Design
+Perturbator,2023-01-31T21:04:47.000Z,PLAY_END,Corrupted by Design
+Perturbator,yesterday,PLAY_END,Corrupted by Design
+Perturbator,PLAY_END
+").unwrap();
+        let library = Library::open_memory();
+        let stats = import::apple_music::import(&library, dir.path().to_str().unwrap());
+        assert!(stats == HistoryImport { imported: 1, skipped: 2 });
+        assert!(library.list::<Event>().len() == 1);
+    }
+}
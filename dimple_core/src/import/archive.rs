@@ -0,0 +1,205 @@
+//! Support for reading media out of zip and tar archives. Bandcamp downloads
+//! arrive as zips and it's nice to be able to import them without unpacking
+//! them by hand. Members are addressed by the path of the archive on disk
+//! plus the path of the member within the archive.
+
+use std::{fs::File, io::{Read, Seek}, path::Path};
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub length: u64,
+}
+
+/// Members larger than this aren't read, since the sizes in archive headers
+/// can't be trusted.
+pub const MAX_MEMBER_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let file_name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if file_name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    }
+    else if file_name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    }
+    else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    }
+    else {
+        None
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// List the file members of the archive. Directories are skipped.
+pub fn list_members(path: &Path) -> Result<Vec<ArchiveMember>, anyhow::Error> {
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => list_zip_members(File::open(path)?),
+        Some(ArchiveKind::Tar) => list_tar_members(File::open(path)?),
+        Some(ArchiveKind::TarGz) => list_tar_members(GzDecoder::new(File::open(path)?)),
+        None => Err(anyhow!("Not a supported archive: {:?}", path)),
+    }
+}
+
+/// Read the full contents of the named member of the archive.
+pub fn read_member(path: &Path, member: &str) -> Result<Vec<u8>, anyhow::Error> {
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => read_zip_member(File::open(path)?, member),
+        Some(ArchiveKind::Tar) => read_tar_member(File::open(path)?, member),
+        Some(ArchiveKind::TarGz) => read_tar_member(GzDecoder::new(File::open(path)?), member),
+        None => Err(anyhow!("Not a supported archive: {:?}", path)),
+    }
+}
+
+/// Read each file member of the archive in a single pass, handing its name
+/// and contents to f. Members that can't be read are passed as errors.
+pub fn read_members(path: &Path, f: impl FnMut(&str, Result<Vec<u8>, anyhow::Error>)) -> Result<(), anyhow::Error> {
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => read_zip_members(File::open(path)?, f),
+        Some(ArchiveKind::Tar) => read_tar_members(File::open(path)?, f),
+        Some(ArchiveKind::TarGz) => read_tar_members(GzDecoder::new(File::open(path)?), f),
+        None => Err(anyhow!("Not a supported archive: {:?}", path)),
+    }
+}
+
+/// Returns the path used to identify the member in the library, which is
+/// the archive path joined with the member path, as if the archive were a
+/// directory.
+pub fn member_path(path: &Path, member: &str) -> String {
+    path.join(member).to_string_lossy().to_string()
+}
+
+fn list_zip_members<R: Read + Seek>(reader: R) -> Result<Vec<ArchiveMember>, anyhow::Error> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut members = vec![];
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue
+        }
+        members.push(ArchiveMember {
+            name: file.name().to_string(),
+            length: file.size(),
+        });
+    }
+    Ok(members)
+}
+
+fn read_zip_member<R: Read + Seek>(reader: R, member: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let file = archive.by_name(member)?;
+    read_limited(file, member)
+}
+
+fn read_zip_members<R: Read + Seek>(reader: R, mut f: impl FnMut(&str, Result<Vec<u8>, anyhow::Error>)) -> Result<(), anyhow::Error> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue
+        }
+        let name = file.name().to_string();
+        f(&name, read_limited(file, &name));
+    }
+    Ok(())
+}
+
+fn list_tar_members<R: Read>(reader: R) -> Result<Vec<ArchiveMember>, anyhow::Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut members = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue
+        }
+        members.push(ArchiveMember {
+            name: entry.path()?.to_string_lossy().to_string(),
+            length: entry.size(),
+        });
+    }
+    Ok(members)
+}
+
+fn read_tar_member<R: Read>(reader: R, member: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_string_lossy() == member {
+            return read_limited(&mut entry, member)
+        }
+    }
+    Err(anyhow!("Member {} not found in archive.", member))
+}
+
+fn read_tar_members<R: Read>(reader: R, mut f: impl FnMut(&str, Result<Vec<u8>, anyhow::Error>)) -> Result<(), anyhow::Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        f(&name, read_limited(&mut entry, &name));
+    }
+    Ok(())
+}
+
+/// Read up to MAX_MEMBER_SIZE bytes, failing if there's more.
+fn read_limited(reader: impl Read, member: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut content = vec![];
+    reader.take(MAX_MEMBER_SIZE + 1).read_to_end(&mut content)?;
+    if content.len() as u64 > MAX_MEMBER_SIZE {
+        return Err(anyhow!("Member {} is larger than {} bytes.", member, MAX_MEMBER_SIZE))
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{is_archive, list_members, read_member, read_members};
+
+    #[test]
+    fn zip() {
+        let path = Path::new("tests/data/archives/pink-noise.zip");
+        assert!(is_archive(path));
+        let members = list_members(path).unwrap();
+        assert!(members.len() == 2);
+        let member = members.iter().find(|m| m.name.ends_with(".mp3")).unwrap();
+        let content = read_member(path, &member.name).unwrap();
+        assert!(content.len() as u64 == member.length);
+    }
+
+    #[test]
+    fn tar_gz() {
+        let path = Path::new("tests/data/archives/pink-noise.tar.gz");
+        assert!(is_archive(path));
+        let members = list_members(path).unwrap();
+        assert!(members.len() == 1);
+        let content = read_member(path, &members[0].name).unwrap();
+        assert!(content.len() as u64 == members[0].length);
+        let mut read = vec![];
+        read_members(path, |name, content| read.push((name.to_string(), content.unwrap().len() as u64))).unwrap();
+        assert!(read == vec![(members[0].name.clone(), members[0].length)]);
+    }
+
+    #[test]
+    fn not_archive() {
+        assert!(!is_archive(Path::new("tests/data/media_files/pink-noise-1s-192kbit.mp3")));
+    }
+}
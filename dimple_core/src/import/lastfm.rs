@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use crate::{library::Library, model::Event};
+
+use super::HistoryImport;
+
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// Imports Last.fm scrobble exports. Last.fm doesn't offer an export itself,
+/// so this reads the CSV and JSON produced by the common export tools:
+/// - CSV with a header row, including either `uts` or `utc_time`.
+/// - CSV without a header, as `artist,album,track,date`.
+/// - JSON in the shape of the `user.getRecentTracks` API response, either
+///   as the raw response, an array of tracks, or an array of pages.
+///
+/// Scrobbles from either format are stored with the same source, so
+/// importing the same history as CSV and JSON doesn't duplicate it. Rows
+/// that can't be read are skipped and counted.
+pub fn import(library: &Library, path: &str) -> HistoryImport {
+    let files = WalkDir::new(path).into_iter()
+        .filter_map(|dir_entry| dir_entry.ok())
+        .filter(|dir_entry| dir_entry.file_type().is_file())
+        .collect::<Vec<_>>();
+    let mut stats = HistoryImport::default();
+    for file in files {
+        let extension = file.path().extension().unwrap_or_default().to_ascii_lowercase();
+        let result = if extension == "csv" {
+            import_csv(library, file.path())
+        }
+        else if extension == "json" {
+            import_json(library, file.path())
+        }
+        else {
+            continue
+        };
+        match result {
+            Ok(file_stats) => stats.append(&file_stats),
+            Err(e) => log::error!("Error importing Last.fm history {:?}: {}", file.path(), e),
+        }
+    }
+    if stats.skipped > 0 {
+        log::warn!("Skipped {} invalid Last.fm scrobbles.", stats.skipped);
+    }
+    stats
+}
+
+/// The identity of a scrobble. This is serialized as the Event source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Scrobble {
+    pub uts: i64,
+    pub artist: String,
+    pub album: Option<String>,
+    pub track: String,
+}
+
+impl Scrobble {
+    fn event(&self) -> Result<Event, anyhow::Error> {
+        Ok(Event {
+            timestamp: DateTime::<Utc>::from_timestamp(self.uts, 0)
+                .ok_or(anyhow!("Invalid timestamp {}", self.uts))?,
+            event_type: "track_played".to_string(),
+            artist: Some(self.artist.clone()),
+            album: self.album.clone(),
+            title: Some(self.track.clone()),
+            source_type: "lastfm::Scrobble".to_string(),
+            source: serde_json::to_string(self)?,
+            ..Default::default()
+        })
+    }
+}
+
+fn import_csv(library: &Library, path: &Path) -> Result<HistoryImport, anyhow::Error> {
+    log::info!("Importing Last.fm CSV file {:?}", path);
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)?;
+    let mut headers: Option<Vec<String>> = None;
+    let mut stats = HistoryImport::default();
+    for (i, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                stats.add(path, i, Err(e.into()));
+                continue
+            },
+        };
+        let fields = record.iter().map(|f| f.trim().to_string()).collect::<Vec<_>>();
+        if i == 0 && fields.iter().any(|f| f.eq_ignore_ascii_case("artist"))
+            && fields.iter().any(|f| f.eq_ignore_ascii_case("track")) {
+            headers = Some(fields.iter().map(|f| f.to_ascii_lowercase()).collect());
+            continue
+        }
+        let scrobble = match &headers {
+            Some(headers) => {
+                let field = |name: &str| headers.iter().position(|h| h == name)
+                    .and_then(|i| fields.get(i))
+                    .filter(|f| !f.is_empty())
+                    .cloned();
+                let uts = field("uts").and_then(|uts| uts.parse().ok())
+                    .or_else(|| field("utc_time").and_then(|date| parse_date(&date)))
+                    .or_else(|| field("date").and_then(|date| parse_date(&date)));
+                match (uts, field("artist"), field("track")) {
+                    (Some(uts), Some(artist), Some(track)) => Some(Scrobble {
+                        uts,
+                        artist,
+                        album: field("album"),
+                        track,
+                    }),
+                    _ => None,
+                }
+            },
+            None => {
+                match (fields.first(), fields.get(2), fields.get(3).and_then(|date| parse_date(date))) {
+                    (Some(artist), Some(track), Some(uts)) => Some(Scrobble {
+                        uts,
+                        artist: artist.clone(),
+                        album: fields.get(1).filter(|album| !album.is_empty()).cloned(),
+                        track: track.clone(),
+                    }),
+                    _ => None,
+                }
+            },
+        };
+        let result = scrobble
+            .ok_or(anyhow!("Missing date, artist, or track."))
+            .and_then(|scrobble| scrobble.event())
+            .map(|event| {
+                super::save_history_event(library, &event);
+            });
+        stats.add(path, i, result);
+    }
+    Ok(stats)
+}
+
+fn import_json(library: &Library, path: &Path) -> Result<HistoryImport, anyhow::Error> {
+    log::info!("Importing Last.fm JSON file {:?}", path);
+    let json = std::fs::read_to_string(path)?;
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    let mut tracks = vec![];
+    collect_json_tracks(&value, &mut tracks);
+    let mut stats = HistoryImport::default();
+    for (i, track) in tracks.into_iter().enumerate() {
+        let uts = track.get("date")
+            .and_then(|date| date.get("uts"))
+            .and_then(|uts| match uts {
+                serde_json::Value::String(s) => s.parse().ok(),
+                serde_json::Value::Number(n) => n.as_i64(),
+                _ => None,
+            });
+        let artist = track.get("artist").and_then(json_text);
+        let name = track.get("name").and_then(json_text);
+        // Tracks without a date are "now playing" and not scrobbles yet.
+        if let (Some(uts), Some(artist), Some(name)) = (uts, artist, name) {
+            let scrobble = Scrobble {
+                uts,
+                artist,
+                album: track.get("album").and_then(json_text),
+                track: name,
+            };
+            let result = scrobble.event().map(|event| {
+                super::save_history_event(library, &event);
+            });
+            stats.add(path, i, result);
+        }
+    }
+    Ok(stats)
+}
+
+/// Finds the track objects anywhere in the document, which handles the
+/// different ways the export tools wrap the API responses.
+fn collect_json_tracks<'a>(value: &'a serde_json::Value, tracks: &mut Vec<&'a serde_json::Value>) {
+    match value {
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_json_tracks(value, tracks);
+            }
+        },
+        serde_json::Value::Object(object) => {
+            if object.contains_key("name") && object.contains_key("artist") {
+                tracks.push(value);
+            }
+            else {
+                for value in object.values() {
+                    collect_json_tracks(value, tracks);
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Last.fm values are either strings or objects like
+/// `{ "mbid": "", "#text": "Perturbator" }`, or with `name` in the extended
+/// format.
+fn json_text(value: &serde_json::Value) -> Option<String> {
+    let text = match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Object(object) => object.get("#text")
+            .or(object.get("name"))
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        _ => None,
+    };
+    text.filter(|text| !text.is_empty())
+}
+
+/// Dates are like "31 Jan 2023 21:04" or "31 Jan 2023, 21:04" in UTC.
+fn parse_date(date: &str) -> Option<i64> {
+    ["%d %b %Y %H:%M", "%d %b %Y, %H:%M", "%Y-%m-%d %H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|date| date.and_utc().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{import::{self, HistoryImport}, library::Library, model::Event};
+
+    use super::parse_date;
+
+    #[test]
+    fn it_works() {
+        let library = Library::open_memory();
+        import::lastfm::import(&library, "tests/data/lastfm_history");
+        // The CSV and JSON exports contain the same two scrobbles, and the
+        // headerless CSV one more.
+        assert!(library.list::<Event>().len() == 3);
+        import::lastfm::import(&library, "tests/data/lastfm_history");
+        assert!(library.list::<Event>().len() == 3);
+    }
+
+    #[test]
+    fn skips_invalid_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("scrobbles.csv"), "\
+uts,artist,album,track
+1675199040,Perturbator,New Model,Corrupted by Design
+99999999999999999,Perturbator,New Model,Corrupted by Design
+,Perturbator,New Model,Corrupted by Design
+").unwrap();
+        let library = Library::open_memory();
+        let stats = import::lastfm::import(&library, dir.path().to_str().unwrap());
+        assert!(stats == HistoryImport { imported: 1, skipped: 2 });
+        assert!(library.list::<Event>().len() == 1);
+    }
+
+    #[test]
+    fn dates() {
+        assert!(parse_date("31 Jan 2023 21:04") == Some(1675199040));
+        assert!(parse_date("31 Jan 2023, 21:04") == Some(1675199040));
+        assert!(parse_date("yesterday") == None);
+    }
+}
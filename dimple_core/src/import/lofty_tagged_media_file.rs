@@ -1,11 +1,13 @@
-use std::{collections::{HashMap, HashSet}, fs::File, path::Path, sync::Arc};
+use std::{collections::{HashMap, HashSet}, fs::File, io::Cursor, path::Path, sync::Arc};
 
 use anyhow::anyhow;
 use image::DynamicImage;
 use itertools::Itertools;
-use lofty::{file::{TaggedFile, TaggedFileExt}, picture::PictureType, tag::{Accessor, ItemKey, Tag, TagExt, TagItem, TagType}};
+use lofty::{file::{AudioFile, TaggedFile, TaggedFileExt}, picture::PictureType, probe::Probe, tag::{Accessor, ItemKey, Tag, TagExt, TagItem, TagType}};
 
-use crate::{librarian::{ArtistMetadata, ReleaseMetadata, TrackMetadata}, model::{dimage::DimageKind, Artist, Dimage, Genre, Link, Release, Track}};
+use crate::{librarian::{ArtistMetadata, ReleaseMetadata, TrackMetadata}, model::{artist_ref::is_featuring_join_phrase, dimage::DimageKind, Artist, ArtistRole, Dimage, Genre, Link, Release, ReleaseGroup, Track}};
+
+use super::symphonia_tagged_media_file::parse_bpm;
 
 /// https://picard-docs.musicbrainz.org/en/variables/tags_basic.html
 /// https://picard-docs.musicbrainz.org/en/appendices/tag_mapping.html
@@ -19,23 +21,36 @@ use crate::{librarian::{ArtistMetadata, ReleaseMetadata, TrackMetadata}, model::
 /// A Sphere of Influence dupes
 /// A Tulip Rose From The Bone Dry Dust dupes and no artists
 /// Everything in Genre (17) is pretty fucked
-/// "Bohren & Der Club of Gore" got split into ("Bohren", "Der Club of Gore")
 #[derive(Clone)]
 pub struct LoftyTaggedMediaFile {
     pub path: String,
     pub tags: Tag,
+    /// The audio bitrate in kbps, if the format reports one.
+    pub bitrate: Option<u32>,
 }
 
 impl LoftyTaggedMediaFile {
     pub fn new(path: &Path) -> Result<LoftyTaggedMediaFile, anyhow::Error> {
         let tagged_file = lofty::read_from_path(path)?;
+        Self::from_tagged_file(path.to_str().unwrap(), &tagged_file)
+    }
+
+    /// Read tags from content that isn't a file on disk, such as a member
+    /// of an archive. The path is only used to identify the file.
+    pub fn from_bytes(path: &str, content: Vec<u8>) -> Result<LoftyTaggedMediaFile, anyhow::Error> {
+        let tagged_file = Probe::new(Cursor::new(content)).guess_file_type()?.read()?;
+        Self::from_tagged_file(path, &tagged_file)
+    }
+
+    fn from_tagged_file(path: &str, tagged_file: &TaggedFile) -> Result<LoftyTaggedMediaFile, anyhow::Error> {
         let tag = tagged_file.primary_tag()
             .or(tagged_file.first_tag())
             .ok_or(anyhow!("No tags found."))?;
 
         let media_file = LoftyTaggedMediaFile {
-            path: path.to_str().unwrap().to_string(),
+            path: path.to_string(),
             tags: tag.clone(),
+            bitrate: tagged_file.properties().audio_bitrate(),
         };
 
         Ok(media_file)
@@ -46,8 +61,12 @@ impl LoftyTaggedMediaFile {
             if let Ok(dymage) = image::load_from_memory(pic.data()) {
                 let mut dimage = Dimage::new(&dymage);
                 dimage.kind = match pic.pic_type() {
-                    // TODO more
-                    PictureType::Artist => Some(DimageKind::MusicArtistThumb),
+                    PictureType::CoverFront => Some(DimageKind::MusicAlbumCover),
+                    PictureType::CoverBack => Some(DimageKind::MusicAlbumBack),
+                    PictureType::Media => Some(DimageKind::MusicCdArt),
+                    PictureType::BandLogo => Some(DimageKind::MusicHdClearLogo),
+                    PictureType::PublisherLogo => Some(DimageKind::MusicRecordLabel),
+                    PictureType::Artist | PictureType::LeadArtist | PictureType::Band => Some(DimageKind::MusicArtistThumb),
                     _ => None,
                 };
                 Some(dimage)
@@ -76,9 +95,19 @@ impl LoftyTaggedMediaFile {
             position: self.tags.track(),
             length_ms: self.tags.get_string(&ItemKey::Length).map(|l| u64::from_str_radix(l, 10).ok()).flatten(),
             lyrics: self.tags.get_string(&ItemKey::Lyrics).map(Into::into),
+            isrc: self.tags.get_string(&ItemKey::Isrc).map(Into::into),
+            bpm: self.tags.get_string(&ItemKey::IntegerBpm)
+                .or(self.tags.get_string(&ItemKey::Bpm))
+                .and_then(parse_bpm),
+            initial_key: self.tags.get_string(&ItemKey::InitialKey).map(Into::into),
+            work: self.tags.get_string(&ItemKey::Work).map(Into::into),
+            mood: self.tags.get_string(&ItemKey::Mood).map(Into::into),
+            comment: self.tags.comment().map(Into::into),
             musicbrainz_id: self.tags.get_string(&ItemKey::MusicBrainzTrackId).map(Into::into),
             media_position: self.tags.disk(),
             media_track_count: self.tags.track_total(),
+            media_title: self.tags.get_string(&ItemKey::SetSubtitle).map(Into::into),
+            media_format: self.tags.get_string(&ItemKey::OriginalMediaType).map(Into::into),
             ..Default::default()
         }
     }
@@ -91,9 +120,22 @@ impl LoftyTaggedMediaFile {
             genres: self.release_genres(),            
             images: self.images(),
             tracks: vec![],
+            media: vec![],
+            release_group: self.release_group(),
+            aliases: vec![],
         }
     }    
 
+    /// Only set when the file is tagged with a release group id. Otherwise
+    /// the librarian groups the release by its title.
+    fn release_group(&self) -> Option<ReleaseGroup> {
+        let musicbrainz_id = self.tags.get_string(&ItemKey::MusicBrainzReleaseGroupId)?;
+        Some(ReleaseGroup {
+            musicbrainz_id: Some(musicbrainz_id.to_string()),
+            ..Default::default()
+        })
+    }
+
     fn release_genres(&self) -> Vec<Genre> {
         self.tags.get_string(&ItemKey::Genre).iter()
             .flat_map(|s| parse_genre_tag(s))
@@ -117,61 +159,77 @@ impl LoftyTaggedMediaFile {
     fn release(&self) -> Release {
         Release {
             title: self.tags.album().map(Into::into),
+            sort_name: self.tags.get_string(&ItemKey::AlbumTitleSortOrder).map(Into::into),
             barcode: self.tags.get_string(&ItemKey::Barcode).map(Into::into),
+            original_date: self.tags.get_string(&ItemKey::OriginalReleaseDate).map(Into::into),
+            label: self.tags.get_string(&ItemKey::Label).map(Into::into),
+            catalog_number: self.tags.get_string(&ItemKey::CatalogNumber).map(Into::into),
             musicbrainz_id: self.tags.get_string(&ItemKey::MusicBrainzReleaseId).map(Into::into),
             ..Default::default()
         }    
     }
 
     fn release_artists(&self) -> Vec<ArtistMetadata> {
-        let artists = self.tags.get_strings(&ItemKey::AlbumArtist)
-            .zip_longest(self.tags.get_strings(&ItemKey::MusicBrainzReleaseArtistId))
-            .map(|artist| {
-                ArtistMetadata {
-                    artist: Artist {
-                        name: artist.clone().left().map(Into::into),
-                        musicbrainz_id: artist.clone().right().map(Into::into),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                }
-            })
-            .collect::<Vec<ArtistMetadata>>();
-        if !artists.is_empty() {
-            return artists
-        }
-        artists
+        let names = self.tags.get_strings(&ItemKey::AlbumArtist).map(String::from).collect::<Vec<_>>();
+        let mbids = self.tags.get_strings(&ItemKey::MusicBrainzReleaseArtistId).collect::<Vec<_>>();
+        let credits = match names.as_slice() {
+            // A single album artist is only split when MusicBrainz says
+            // there is more than one artist.
+            [name] if mbids.len() > 1 => split_artist_credit(name),
+            [name] => vec![(name.clone(), None)],
+            _ => names.into_iter().map(|name| (name, None)).collect(),
+        };
+        with_sort_name(artist_credits(credits, &mbids), self.tags.get_string(&ItemKey::AlbumArtistSortOrder))
     }
 
+    /// The track artist credit comes from, in order of preference:
+    /// - The ARTISTS multi-value tag, with join phrases taken from ARTIST.
+    /// - Multiple ARTIST values.
+    /// - The single ARTIST value, split on common separators if there's no
+    ///   single MusicBrainz artist id to say it's one artist.
+    /// Composers and remixers are added after the performing artists.
     fn track_artists(&self) -> Vec<ArtistMetadata> {
-        let artists: Vec<_> = self.tags.get_strings(&ItemKey::TrackArtists)
-            .zip_longest(self.tags.get_strings(&ItemKey::MusicBrainzArtistId))
-            .map(|artist| {
-                ArtistMetadata {
-                    artist: Artist {
-                        name: artist.clone().left().map(Into::into),
-                        musicbrainz_id: artist.clone().right().map(Into::into),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                }
-            })
-            .collect();
-        if !artists.is_empty() {
-            return artists
+        let display = self.tags.get_string(&ItemKey::TrackArtist);
+        let names = self.tags.get_strings(&ItemKey::TrackArtists).map(String::from).collect::<Vec<_>>();
+        let values = self.tags.get_strings(&ItemKey::TrackArtist).map(String::from).collect::<Vec<_>>();
+        let mbids = self.tags.get_strings(&ItemKey::MusicBrainzArtistId).collect::<Vec<_>>();
+        let credits = if !names.is_empty() {
+            let join_phrases = credit_join_phrases(display, &names);
+            names.into_iter().zip(join_phrases).collect()
+        }
+        else if values.len() > 1 {
+            values.into_iter().map(|name| (name, None)).collect()
+        }
+        else if let Some(display) = display {
+            if mbids.len() == 1 {
+                vec![(display.to_string(), None)]
+            }
+            else {
+                split_artist_credit(display)
+            }
         }
-        self.tags.get_strings(&ItemKey::TrackArtist)
-            .flat_map(|a| parse_artist_tag(a))
-            .zip_longest(self.tags.get_strings(&ItemKey::MusicBrainzArtistId))
-            .map(|artist| {
-                ArtistMetadata {
-                    artist: Artist {
-                        name: artist.clone().left().map(Into::into),
-                        musicbrainz_id: artist.clone().right().map(Into::into),
-                        ..Default::default()
-                    },
+        else {
+            vec![]
+        };
+        let mut artists = with_sort_name(artist_credits(credits, &mbids), self.tags.get_string(&ItemKey::TrackArtistSortOrder));
+        artists.extend(self.role_artists(&ItemKey::Remixer, ArtistRole::Remixer));
+        artists.extend(self.role_artists(&ItemKey::Composer, ArtistRole::Composer));
+        artists.extend(self.role_artists(&ItemKey::Conductor, ArtistRole::Conductor));
+        artists
+    }
+
+    fn role_artists(&self, key: &ItemKey, role: ArtistRole) -> Vec<ArtistMetadata> {
+        self.tags.get_strings(key)
+            .flat_map(|s| s.split(';'))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|name| ArtistMetadata {
+                artist: Artist {
+                    name: Some(name.to_string()),
                     ..Default::default()
-                }
+                },
+                role: role.clone(),
+                ..Default::default()
             })
             .collect()
     }
@@ -193,43 +251,103 @@ impl LoftyTaggedMediaFile {
     }
 }
 
-/// Split artist string handling various separators
-/// TODO need to collect examples of the ones currently failing and add them
-/// as tests.
-fn parse_artist_tag(artist_str: &str) -> Vec<String> {
-    let mut artists = Vec::new();
-    
-    // Common separators in music tags
-    for part in artist_str.split(&['/', ',', ';'][..]) {
-        let part = part.trim();
-        if !part.is_empty() {
-            // Handle "feat." and "featuring" specially
-            if let Some(feat_pos) = part.to_lowercase().find("feat.") {
-                let (artist, featuring) = part.split_at(feat_pos);
-                if !artist.trim().is_empty() {
-                    artists.push(artist.trim().to_string());
-                }
-                let feat_artist = featuring.replacen("feat.", "", 1);
-                if !feat_artist.trim().is_empty() {
-                    artists.push(feat_artist.trim().to_string());
-                }
-            } 
-            else if let Some(feat_pos) = part.to_lowercase().find("featuring") {
-                let (artist, featuring) = part.split_at(feat_pos);
-                if !artist.trim().is_empty() {
-                    artists.push(artist.trim().to_string());
+/// Build the ArtistMetadata for an ordered credit of (name, join phrase),
+/// pairing MusicBrainz ids by position. Artists after a "feat." style join
+/// phrase are Featured.
+fn artist_credits(credits: Vec<(String, Option<String>)>, mbids: &[&str]) -> Vec<ArtistMetadata> {
+    let mut role = ArtistRole::Primary;
+    credits.into_iter().enumerate()
+        .map(|(i, (name, join_phrase))| {
+            let artist = ArtistMetadata {
+                artist: Artist {
+                    name: Some(name),
+                    musicbrainz_id: mbids.get(i).map(|mbid| mbid.to_string()),
+                    ..Default::default()
+                },
+                role: role.clone(),
+                join_phrase: join_phrase.clone(),
+                ..Default::default()
+            };
+            if join_phrase.as_deref().is_some_and(is_featuring_join_phrase) {
+                role = ArtistRole::Featured;
+            }
+            artist
+        })
+        .collect()
+}
+
+/// ARTISTSORT and ALBUMARTISTSORT are the sort name of the whole credit, so
+/// they only say something about the artist if there is just the one.
+fn with_sort_name(mut artists: Vec<ArtistMetadata>, sort_name: Option<&str>) -> Vec<ArtistMetadata> {
+    if let ([artist], Some(sort_name)) = (artists.as_mut_slice(), sort_name) {
+        artist.artist.sort_name = Some(sort_name.to_string());
+    }
+    artists
+}
+
+/// Find the join phrases between the names in the display string, e.g.
+/// ARTIST "Gorillaz feat. De La Soul" with ARTISTS ["Gorillaz",
+/// "De La Soul"] gives [" feat. ", None]. If the names can't be found in
+/// order there are no join phrases.
+fn credit_join_phrases(display: Option<&str>, names: &[String]) -> Vec<Option<String>> {
+    let mut join_phrases = vec![None; names.len()];
+    let Some(display) = display else {
+        return join_phrases
+    };
+    let mut spans = vec![];
+    let mut pos = 0;
+    for name in names {
+        match display[pos..].find(name.as_str()) {
+            Some(i) => {
+                spans.push((pos + i, pos + i + name.len()));
+                pos += i + name.len();
+            },
+            None => return join_phrases,
+        }
+    }
+    for i in 0..spans.len() {
+        let start = spans[i].1;
+        let end = spans.get(i + 1).map(|span| span.0).unwrap_or(display.len());
+        if start < end {
+            join_phrases[i] = Some(display[start..end].to_string());
+        }
+    }
+    join_phrases
+}
+
+/// Split an artist string on common separators, keeping the separators as
+/// join phrases. This is the last resort, used when there's no better
+/// information, so it doesn't split on "&", "and" or ",", which are too
+/// often part of a name, as in "Bohren & Der Club of Gore" or
+/// "Tyler, The Creator".
+fn split_artist_credit(artist_str: &str) -> Vec<(String, Option<String>)> {
+    const SEPARATORS: [&str; 7] = [" featuring ", " feat. ", " feat ", " ft. ", " ft ", " / ", "; "];
+    let mut credits = vec![];
+    let mut rest = artist_str;
+    loop {
+        // ASCII lowercase keeps the byte offsets the same.
+        let lower = rest.to_ascii_lowercase();
+        let next = SEPARATORS.iter()
+            .filter_map(|sep| lower.find(sep).map(|i| (i, sep.len())))
+            .min_by_key(|(i, len)| (*i, usize::MAX - len));
+        match next {
+            Some((i, len)) => {
+                let name = rest[..i].trim();
+                if !name.is_empty() {
+                    credits.push((name.to_string(), Some(rest[i..i + len].to_string())));
                 }
-                let feat_artist = featuring.replacen("featuring", "", 1);
-                if !feat_artist.trim().is_empty() {
-                    artists.push(feat_artist.trim().to_string());
+                rest = &rest[i + len..];
+            },
+            None => {
+                let name = rest.trim();
+                if !name.is_empty() {
+                    credits.push((name.to_string(), None));
                 }
-            } 
-            else {
-                artists.push(part.to_string());
-            }
+                break
+            },
         }
     }
-    artists
+    credits
 }
 
 /// Split genre string handling various separators
@@ -240,7 +358,31 @@ fn parse_genre_tag(genre_str: &str) -> Vec<&str> {
         .collect()
 }
 
-mod test {
+#[cfg(test)]
+mod tests {
+    use super::{credit_join_phrases, split_artist_credit};
+
+    #[test]
+    fn join_phrases() {
+        let names = vec!["Gorillaz".to_string(), "De La Soul".to_string()];
+        assert!(credit_join_phrases(Some("Gorillaz feat. De La Soul"), &names)
+            == vec![Some(" feat. ".to_string()), None]);
+        assert!(credit_join_phrases(Some("Someone Else"), &names) == vec![None, None]);
+    }
+
+    #[test]
+    fn split() {
+        assert!(split_artist_credit("Bohren & Der Club of Gore")
+            == vec![("Bohren & Der Club of Gore".to_string(), None)]);
+        assert!(split_artist_credit("Gorillaz feat. De La Soul")
+            == vec![("Gorillaz".to_string(), Some(" feat. ".to_string())), ("De La Soul".to_string(), None)]);
+        for name in ["Earth, Wind & Fire", "Crosby, Stills, Nash & Young", "Tyler, The Creator"] {
+            assert!(split_artist_credit(name) == vec![(name.to_string(), None)]);
+        }
+        assert!(split_artist_credit("A; B")
+            == vec![("A".to_string(), Some("; ".to_string())), ("B".to_string(), None)]);
+    }
+
     // #[test]
     // fn parse_n_of_m_tag() {
     //     assert!(parse_n_of_m_tag("") == (None, None));
@@ -0,0 +1,494 @@
+//! Import and export of playlist files. M3U / M3U8, PLS and XSPF are
+//! supported. Entries are resolved to Tracks by the path of their MediaFile
+//! first, and then by matching the title, artist and album if the file
+//! isn't in the library.
+
+use std::{collections::BTreeMap, fmt::Write as _, path::{Component, Path, PathBuf}};
+
+use anyhow::anyhow;
+use quick_xml::{escape::escape, events::Event, Reader};
+use reqwest::Url;
+
+use crate::{librarian::{self, ArtistMetadata, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, ModelBasics as _, Playlist, Release, Track, TrackSource}};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlaylistEntry {
+    pub location: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub length_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistImport {
+    pub playlist: Playlist,
+    /// Entries that could not be resolved to a Track in the library. These
+    /// are not added to the playlist.
+    pub unresolved: Vec<PlaylistEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStyle {
+    Absolute,
+    /// Relative to the directory the playlist file is written to.
+    Relative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+fn playlist_format(path: &Path) -> Option<PlaylistFormat> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+        "pls" => Some(PlaylistFormat::Pls),
+        "xspf" => Some(PlaylistFormat::Xspf),
+        _ => None,
+    }
+}
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    playlist_format(path).is_some()
+}
+
+/// Read the playlist file and create a new Playlist from the entries that
+/// can be resolved to Tracks in the library.
+pub fn import(library: &Library, path: &Path) -> Result<PlaylistImport, anyhow::Error> {
+    log::info!("Importing playlist {:?}.", path);
+    let format = playlist_format(path).ok_or(anyhow!("Not a supported playlist: {:?}", path))?;
+    // M3U files are often Latin-1, so be forgiving.
+    let content = String::from_utf8_lossy(&std::fs::read(path)?).to_string();
+    let (name, entries) = match format {
+        PlaylistFormat::M3u => parse_m3u(&content),
+        PlaylistFormat::Pls => parse_pls(&content),
+        PlaylistFormat::Xspf => parse_xspf(&content)?,
+    };
+    let name = name.or_else(|| path.file_stem().and_then(|s| s.to_str()).map(Into::into));
+
+    let playlist_dir = path.parent().unwrap_or(Path::new(""));
+    let playlist = Playlist {
+        name,
+        ..Default::default()
+    }.save(library);
+    let mut unresolved = vec![];
+    for entry in entries {
+        match resolve_entry(library, playlist_dir, &entry, format == PlaylistFormat::Xspf) {
+            Some(track) => playlist.append(library, &track),
+            None => {
+                log::warn!("  Unresolved playlist entry {:?}", entry);
+                unresolved.push(entry);
+            },
+        }
+    }
+
+    Ok(PlaylistImport {
+        playlist,
+        unresolved,
+    })
+}
+
+/// Write the playlist to the file, with the format chosen by the extension.
+/// Returns the tracks that were left out because they have no local file.
+pub fn export(library: &Library, playlist: &Playlist, path: &Path, path_style: PathStyle) -> Result<Vec<Track>, anyhow::Error> {
+    let format = playlist_format(path).ok_or(anyhow!("Not a supported playlist: {:?}", path))?;
+    let playlist_dir = path.parent().unwrap_or(Path::new(""));
+    let playlist_dir = std::fs::canonicalize(playlist_dir).unwrap_or(playlist_dir.to_path_buf());
+
+    let mut entries = vec![];
+    let mut skipped = vec![];
+    for track in playlist.tracks(library) {
+        match local_file_path(library, &track) {
+            Some(file_path) => {
+                let location = match (format, path_style) {
+                    (PlaylistFormat::Xspf, PathStyle::Absolute) => Url::from_file_path(&file_path)
+                        .map_err(|_| anyhow!("Invalid path {:?}", file_path))?
+                        .to_string(),
+                    (PlaylistFormat::Xspf, PathStyle::Relative) => {
+                        let base = Url::from_directory_path(&playlist_dir)
+                            .map_err(|_| anyhow!("Invalid path {:?}", playlist_dir))?;
+                        let url = Url::from_file_path(&file_path)
+                            .map_err(|_| anyhow!("Invalid path {:?}", file_path))?;
+                        base.make_relative(&url).unwrap_or(url.to_string())
+                    },
+                    (_, PathStyle::Absolute) => file_path.to_str().unwrap().to_string(),
+                    (_, PathStyle::Relative) => relative_path(&playlist_dir, &file_path).to_str().unwrap().to_string(),
+                };
+                entries.push(PlaylistEntry {
+                    location,
+                    title: track.title.clone(),
+                    artist: track.artist_name(library),
+                    album: track.album_name(library),
+                    length_ms: track.length_ms,
+                });
+            },
+            None => {
+                log::warn!("  No local file for track {:?}, skipping.", track.key);
+                skipped.push(track);
+            },
+        }
+    }
+
+    let name = playlist.name.clone().unwrap_or_default();
+    let content = match format {
+        PlaylistFormat::M3u => write_m3u(&name, &entries),
+        PlaylistFormat::Pls => write_pls(&entries),
+        PlaylistFormat::Xspf => write_xspf(&name, &entries),
+    };
+    std::fs::write(path, content)?;
+
+    Ok(skipped)
+}
+
+fn resolve_entry(library: &Library, playlist_dir: &Path, entry: &PlaylistEntry, is_uri: bool) -> Option<Track> {
+    if let Some(path) = resolve_location(playlist_dir, &entry.location, is_uri) {
+        // MediaFile paths are stored as they were given to the importer,
+        // which may be relative to the working directory, so try a few
+        // spellings of the same path.
+        let canonical = std::fs::canonicalize(&path).ok();
+        let relative_to_cwd = canonical.as_ref()
+            .zip(std::env::current_dir().and_then(std::fs::canonicalize).ok())
+            .and_then(|(path, cwd)| path.strip_prefix(cwd).ok().map(Path::to_path_buf));
+        let candidates = [Some(normalize_path(&path)), canonical, relative_to_cwd];
+        for candidate in candidates.iter().flatten() {
+            if let Some(media_file) = library.find_media_file_by_file_path(candidate.to_str().unwrap()) {
+                let track_source: Option<TrackSource> = TrackSource::find(library,
+                    "SELECT * FROM TrackSource WHERE media_file_key = ?1",
+                    (&media_file.key,));
+                if let Some(track) = track_source.and_then(|ts| ts.track(library)) {
+                    return Some(track)
+                }
+            }
+        }
+    }
+
+    // Fall back to matching by tags.
+    entry.title.as_ref()?;
+    let artists = entry.artist.iter()
+        .map(|name| ArtistMetadata {
+            artist: Artist {
+                name: Some(name.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    librarian::match_track(library, &TrackMetadata {
+        track: Track {
+            title: entry.title.clone(),
+            ..Default::default()
+        },
+        artists: artists.clone(),
+        release: Some(ReleaseMetadata {
+            release: Release {
+                title: entry.album.clone(),
+                ..Default::default()
+            },
+            artists,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// M3U and PLS locations are paths, absolute or relative to the playlist,
+/// or file URLs. XSPF locations are always URIs. Remote streams are not
+/// resolved.
+fn resolve_location(playlist_dir: &Path, location: &str, is_uri: bool) -> Option<PathBuf> {
+    if location.is_empty() {
+        return None
+    }
+    if location.starts_with("file:") {
+        return Url::parse(location).ok()?.to_file_path().ok()
+    }
+    if location.contains("://") {
+        return None
+    }
+    if is_uri {
+        let base = std::fs::canonicalize(playlist_dir).ok()?;
+        return Url::from_directory_path(base).ok()?.join(location).ok()?.to_file_path().ok()
+    }
+    let path = Path::new(location);
+    if path.is_absolute() {
+        Some(path.to_path_buf())
+    }
+    else {
+        Some(playlist_dir.join(path))
+    }
+}
+
+fn local_file_path(library: &Library, track: &Track) -> Option<PathBuf> {
+    library.track_sources_for_track(track).iter()
+        .filter_map(|track_source| track_source.media_file(library))
+        .filter(|media_file| media_file.archive_path.is_none())
+        .map(|media_file| {
+            let path = PathBuf::from(&media_file.file_path);
+            std::fs::canonicalize(&path).unwrap_or(path)
+        })
+        .next()
+}
+
+/// Resolves . and .. without touching the filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let base = base.components().collect::<Vec<_>>();
+    let path = path.components().collect::<Vec<_>>();
+    let common = base.iter().zip(path.iter()).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// "Artist - Title" is the convention for M3U and PLS titles.
+fn split_display_title(s: &str) -> (Option<String>, Option<String>) {
+    match s.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim().to_string()), Some(title.trim().to_string())),
+        None if !s.trim().is_empty() => (None, Some(s.trim().to_string())),
+        None => (None, None),
+    }
+}
+
+fn display_title(entry: &PlaylistEntry) -> String {
+    match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        _ => String::new(),
+    }
+}
+
+fn parse_m3u(content: &str) -> (Option<String>, Vec<PlaylistEntry>) {
+    let mut name = None;
+    let mut entries = vec![];
+    let mut next = PlaylistEntry::default();
+    for line in content.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() {
+            continue
+        }
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (length, display) = extinf.split_once(',').unwrap_or((extinf, ""));
+            next.length_ms = length.trim().parse::<i64>().ok()
+                .filter(|length| *length > 0)
+                .map(|length| length as u64 * 1000);
+            (next.artist, next.title) = split_display_title(display);
+        }
+        else if let Some(playlist) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(playlist.trim().to_string());
+        }
+        else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            next.album = Some(album.trim().to_string());
+        }
+        else if line.starts_with('#') {
+            continue
+        }
+        else {
+            next.location = line.to_string();
+            entries.push(std::mem::take(&mut next));
+        }
+    }
+    (name, entries)
+}
+
+fn parse_pls(content: &str) -> (Option<String>, Vec<PlaylistEntry>) {
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+    for line in content.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        for (prefix, field) in [("file", 0), ("title", 1), ("length", 2)] {
+            if let Some(Ok(index)) = key.strip_prefix(prefix).map(|i| i.parse::<u32>()) {
+                let entry = entries.entry(index).or_default();
+                match field {
+                    0 => entry.location = value.to_string(),
+                    1 => (entry.artist, entry.title) = split_display_title(value),
+                    _ => entry.length_ms = value.parse::<i64>().ok()
+                        .filter(|length| *length > 0)
+                        .map(|length| length as u64 * 1000),
+                }
+            }
+        }
+    }
+    (None, entries.into_values().filter(|e| !e.location.is_empty()).collect())
+}
+
+fn parse_xspf(content: &str) -> Result<(Option<String>, Vec<PlaylistEntry>), anyhow::Error> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().trim_text(true);
+    let mut name = None;
+    let mut entries = vec![];
+    let mut entry: Option<PlaylistEntry> = None;
+    let mut element = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                element = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if element == "track" {
+                    entry = Some(PlaylistEntry::default());
+                }
+            },
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"track" {
+                    entries.extend(entry.take());
+                }
+                element.clear();
+            },
+            Event::Text(e) => {
+                let text = e.unescape()?.to_string();
+                match (entry.as_mut(), element.as_str()) {
+                    (Some(entry), "location") => entry.location = text,
+                    (Some(entry), "title") => entry.title = Some(text),
+                    (Some(entry), "creator") => entry.artist = Some(text),
+                    (Some(entry), "album") => entry.album = Some(text),
+                    (Some(entry), "duration") => entry.length_ms = text.parse().ok(),
+                    (None, "title") => name = Some(text),
+                    _ => {},
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+    Ok((name, entries))
+}
+
+fn write_m3u(name: &str, entries: &[PlaylistEntry]) -> String {
+    let mut s = String::new();
+    writeln!(s, "#EXTM3U").unwrap();
+    if !name.is_empty() {
+        writeln!(s, "#PLAYLIST:{}", name).unwrap();
+    }
+    for entry in entries {
+        let length_s = entry.length_ms.map(|l| (l / 1000) as i64).unwrap_or(-1);
+        writeln!(s, "#EXTINF:{},{}", length_s, display_title(entry)).unwrap();
+        if let Some(album) = &entry.album {
+            writeln!(s, "#EXTALB:{}", album).unwrap();
+        }
+        writeln!(s, "{}", entry.location).unwrap();
+    }
+    s
+}
+
+fn write_pls(entries: &[PlaylistEntry]) -> String {
+    let mut s = String::new();
+    writeln!(s, "[playlist]").unwrap();
+    for (i, entry) in entries.iter().enumerate() {
+        let i = i + 1;
+        writeln!(s, "File{}={}", i, entry.location).unwrap();
+        writeln!(s, "Title{}={}", i, display_title(entry)).unwrap();
+        writeln!(s, "Length{}={}", i, entry.length_ms.map(|l| (l / 1000) as i64).unwrap_or(-1)).unwrap();
+    }
+    writeln!(s, "NumberOfEntries={}", entries.len()).unwrap();
+    writeln!(s, "Version=2").unwrap();
+    s
+}
+
+fn write_xspf(name: &str, entries: &[PlaylistEntry]) -> String {
+    let mut s = String::new();
+    writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(s, r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#).unwrap();
+    if !name.is_empty() {
+        writeln!(s, "  <title>{}</title>", escape(name)).unwrap();
+    }
+    writeln!(s, "  <trackList>").unwrap();
+    for entry in entries {
+        writeln!(s, "    <track>").unwrap();
+        writeln!(s, "      <location>{}</location>", escape(entry.location.as_str())).unwrap();
+        if let Some(title) = &entry.title {
+            writeln!(s, "      <title>{}</title>", escape(title.as_str())).unwrap();
+        }
+        if let Some(artist) = &entry.artist {
+            writeln!(s, "      <creator>{}</creator>", escape(artist.as_str())).unwrap();
+        }
+        if let Some(album) = &entry.album {
+            writeln!(s, "      <album>{}</album>", escape(album.as_str())).unwrap();
+        }
+        if let Some(length_ms) = entry.length_ms {
+            writeln!(s, "      <duration>{}</duration>", length_ms).unwrap();
+        }
+        writeln!(s, "    </track>").unwrap();
+    }
+    writeln!(s, "  </trackList>").unwrap();
+    writeln!(s, "</playlist>").unwrap();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::library::Library;
+
+    use super::{export, import, parse_m3u, parse_pls, PathStyle};
+
+    #[test]
+    fn import_formats() {
+        let library = Library::open_memory();
+        library.import("tests/data/media_files");
+        for file in ["test.m3u8", "test.pls", "test.xspf"] {
+            let path = Path::new("tests/data/playlists").join(file);
+            let result = import(&library, &path).unwrap();
+            assert!(result.playlist.len(&library) == 1);
+            assert!(result.unresolved.len() == 1);
+        }
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let library = Library::open_memory();
+        library.import("tests/data/media_files");
+        let imported = import(&library, Path::new("tests/data/playlists/test.m3u8")).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["out.m3u8", "out.pls", "out.xspf"] {
+            for path_style in [PathStyle::Absolute, PathStyle::Relative] {
+                let path = dir.path().join(file);
+                let skipped = export(&library, &imported.playlist, &path, path_style).unwrap();
+                assert!(skipped.is_empty());
+                let reimported = import(&library, &path).unwrap();
+                assert!(reimported.unresolved.is_empty());
+                assert!(reimported.playlist.tracks(&library) == imported.playlist.tracks(&library));
+            }
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let (name, entries) = parse_m3u("#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:123,Boards of Canada - Roygbiv\nmusic/roygbiv.flac\n");
+        assert!(name == Some("Mix".to_string()));
+        assert!(entries[0].artist == Some("Boards of Canada".to_string()));
+        assert!(entries[0].title == Some("Roygbiv".to_string()));
+        assert!(entries[0].length_ms == Some(123_000));
+        assert!(entries[0].location == "music/roygbiv.flac");
+
+        let (_, entries) = parse_pls("[playlist]\nFile2=b.mp3\nFile1=a.mp3\nTitle1=A\nLength1=-1\nNumberOfEntries=2\n");
+        assert!(entries.len() == 2);
+        assert!(entries[0].location == "a.mp3");
+        assert!(entries[0].title == Some("A".to_string()));
+        assert!(entries[0].length_ms == None);
+    }
+}
@@ -1,59 +1,218 @@
-use std::fs::{self, File};
+use std::{collections::HashSet, fs, path::Path};
 
-use crate::{library::Library, model::Event};
+use crate::{librarian::{self, ArtistMetadata, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Event, Release, Track}};
 
-use chrono::DateTime;
-use walkdir::{DirEntry, WalkDir};
+use super::HistoryImport;
+
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::de::DeserializeOwned;
+use walkdir::WalkDir;
 
 use serde::{Deserialize, Serialize};
 
-pub fn import(library: &Library, path: &str) {
+/// Imports Spotify listening history from a Spotify data export. Both the
+/// extended streaming history (Streaming_History_Audio_*.json and
+/// Streaming_History_Video_*.json) and the older account data format
+/// (StreamingHistory*.json) are supported, including podcasts.
+///
+/// Imports are idempotent. Events are identified by the unique index on
+/// (source_type, source) and re-importing the same file updates the existing
+/// rows. Entries that can't be read are skipped and counted.
+pub fn import(library: &Library, path: &str) -> HistoryImport {
     let json_files = WalkDir::new(path).into_iter()
         .filter(|dir_entry| dir_entry.is_ok())
         .map(|dir_entry| dir_entry.unwrap())
-        .filter(|dir_entry| dir_entry.file_type().is_file() 
+        .filter(|dir_entry| dir_entry.file_type().is_file()
             && dir_entry.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")))
         .collect::<Vec<_>>();
-    // dbg!(&json_files);
+    // Spotify track URIs that have already been linked, or failed to link,
+    // so each is only matched once per import.
+    let mut linked_uris = HashSet::new();
+    let mut stats = HistoryImport::default();
     for json_file in json_files.iter() {
         let filename = json_file.file_name().to_str().unwrap();
-        if filename.contains("Streaming_History_Audio") {
-            import_streaming_history_audio(library, json_file);
+        let result = if filename.starts_with("Streaming_History_Audio") {
+            import_extended_streaming_history(library, json_file.path(),
+                "spotify::StreamingHistoryAudioEntry", &mut linked_uris)
+        }
+        else if filename.starts_with("Streaming_History_Video") {
+            import_extended_streaming_history(library, json_file.path(),
+                "spotify::StreamingHistoryVideoEntry", &mut linked_uris)
+        }
+        else if filename.starts_with("StreamingHistory_podcast") {
+            import_account_podcast_history(library, json_file.path())
+        }
+        else if filename.starts_with("StreamingHistory") {
+            import_account_music_history(library, json_file.path())
+        }
+        else {
+            continue
+        };
+        match result {
+            Ok(file_stats) => stats.append(&file_stats),
+            Err(e) => log::error!("Error importing Spotify history {:?}: {}", json_file.path(), e),
+        }
+    }
+    if stats.skipped > 0 {
+        log::warn!("Skipped {} invalid Spotify history entries.", stats.skipped);
+    }
+    stats
+}
+
+/// Import each entry of the JSON array in the file with f. The file has to
+/// be valid JSON, but entries that can't be read or imported are skipped.
+fn import_entries<T: DeserializeOwned>(path: &Path, mut f: impl FnMut(T) -> Result<(), anyhow::Error>) -> Result<HistoryImport, anyhow::Error> {
+    let json = fs::read_to_string(path)?;
+    let values: Vec<serde_json::Value> = serde_json::from_str(&json)?;
+    let mut stats = HistoryImport::default();
+    for (i, value) in values.into_iter().enumerate() {
+        let result = serde_json::from_value(value)
+            .map_err(anyhow::Error::from)
+            .and_then(&mut f);
+        stats.add(path, i, result);
+    }
+    Ok(stats)
+}
+
+fn import_extended_streaming_history(library: &Library, path: &Path,
+    source_type: &str, linked_uris: &mut HashSet<String>) -> Result<HistoryImport, anyhow::Error> {
+
+    log::info!("Importing Spotify extended streaming history file {:?}", path);
+    import_entries(path, |value: serde_json::Value| {
+        import_extended_streaming_history_entry(library, value, source_type, linked_uris)
+    })
+}
+
+fn import_extended_streaming_history_entry(library: &Library, value: serde_json::Value,
+    source_type: &str, linked_uris: &mut HashSet<String>) -> Result<(), anyhow::Error> {
+
+    let entry: StreamingHistoryAudioEntry = serde_json::from_value(value.clone())?;
+    let details: StreamingHistoryDetails = serde_json::from_value(value)?;
+    let ts = entry.ts.clone().ok_or(anyhow!("Missing ts."))?;
+    let skipped = entry.skipped == Some(true);
+    let mut event = Event {
+        timestamp: DateTime::parse_from_rfc3339(&ts)?.into(),
+        source_type: source_type.to_string(),
+        // The source is the serialized entry, which is what identifies
+        // the event for re-imports. It is kept to the original subset of
+        // fields so that events imported by earlier versions still match.
+        source: serde_json::to_string(&entry)?,
+        ms_played: entry.ms_played,
+        platform: details.platform.clone(),
+        shuffle: details.shuffle,
+        ..Default::default()
+    };
+    if entry.master_metadata_track_name.is_some() {
+        event.event_type = if skipped { "track_skipped" } else { "track_played" }.to_string();
+        event.artist = entry.master_metadata_album_artist_name.clone();
+        event.album = entry.master_metadata_album_album_name.clone();
+        event.title = entry.master_metadata_track_name.clone();
+        event.spotify_uri = entry.spotify_track_uri.clone();
+    }
+    else if details.episode_name.is_some() {
+        event.event_type = if skipped { "episode_skipped" } else { "episode_played" }.to_string();
+        event.album = details.episode_show_name.clone();
+        event.title = details.episode_name.clone();
+        event.spotify_uri = details.spotify_episode_uri.clone();
+    }
+    else {
+        return Err(anyhow!("Missing track or episode name."))
+    }
+    super::save_history_event(library, &event);
+
+    if let Some(uri) = &entry.spotify_track_uri {
+        if linked_uris.insert(uri.clone()) {
+            link_track(library, uri, &event);
         }
     }
+    Ok(())
 }
 
-fn import_streaming_history_audio(library: &Library, json_file: &DirEntry) {
-    log::info!("Importing Spotify Streaming_History_Audio file {:?}", json_file.path().as_os_str());
-    let json = fs::read_to_string(json_file.path()).unwrap();
-    let entries: Vec<StreamingHistoryAudioEntry> = serde_json::from_str(&json).unwrap();
-    
-    for (i, entry) in entries.iter().enumerate() {
-        if !(entry.ts.is_some() 
-            && entry.master_metadata_album_artist_name.is_some() 
-            && entry.master_metadata_track_name.is_some()) {
-                log::warn!("Invalid entry #{}. Missing ts, artist, or title.", i);
-                continue
-        }         
-        // There is a unique index on (source_type, source) so if we're
-        // re-importing the same data we'll just update the existing row.
-        // TODO no longer true since removing upsert, will blow up
-        library.save(&Event {
-            timestamp: DateTime::parse_from_rfc3339(&entry.ts.clone().unwrap()).unwrap().into(),
-            event_type: match entry.skipped {
-                Some(true) => "track_skipped",
-                _ => "track_played",
-            }.to_string(),
-            artist: entry.master_metadata_album_artist_name.clone(),
-            album: entry.master_metadata_album_album_name.clone(),
-            title: entry.master_metadata_track_name.clone(),
-            source_type: "spotify::StreamingHistoryAudioEntry".to_string(),
-            source: serde_json::to_string(entry).unwrap(),
+fn import_account_music_history(library: &Library, path: &Path) -> Result<HistoryImport, anyhow::Error> {
+    log::info!("Importing Spotify account streaming history file {:?}", path);
+    import_entries(path, |entry: StreamingHistoryEntry| {
+        super::save_history_event(library, &Event {
+            timestamp: parse_end_time(&entry.end_time)?,
+            event_type: "track_played".to_string(),
+            artist: Some(entry.artist_name.clone()),
+            title: Some(entry.track_name.clone()),
+            source_type: "spotify::StreamingHistoryEntry".to_string(),
+            source: serde_json::to_string(&entry)?,
+            ms_played: Some(entry.ms_played),
             ..Default::default()
         });
+        Ok(())
+    })
+}
+
+fn import_account_podcast_history(library: &Library, path: &Path) -> Result<HistoryImport, anyhow::Error> {
+    log::info!("Importing Spotify account podcast history file {:?}", path);
+    import_entries(path, |entry: StreamingHistoryPodcastEntry| {
+        super::save_history_event(library, &Event {
+            timestamp: parse_end_time(&entry.end_time)?,
+            event_type: "episode_played".to_string(),
+            album: Some(entry.podcast_name.clone()),
+            title: Some(entry.episode_name.clone()),
+            source_type: "spotify::StreamingHistoryPodcastEntry".to_string(),
+            source: serde_json::to_string(&entry)?,
+            ms_played: Some(entry.ms_played),
+            ..Default::default()
+        });
+        Ok(())
+    })
+}
+
+/// Find the library Track the event refers to and, if it doesn't have one
+/// yet, set its spotify_id from the track URI.
+fn link_track(library: &Library, uri: &str, event: &Event) {
+    let Some(spotify_id) = uri.strip_prefix("spotify:track:") else {
+        return
+    };
+    let artists = event.artist.iter()
+        .map(|name| ArtistMetadata {
+            artist: Artist {
+                name: Some(name.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let track = librarian::match_track(library, &TrackMetadata {
+        track: Track {
+            title: event.title.clone(),
+            ..Default::default()
+        },
+        artists: artists.clone(),
+        release: Some(ReleaseMetadata {
+            release: Release {
+                title: event.album.clone(),
+                ..Default::default()
+            },
+            artists,
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    if let Some(track) = track {
+        if track.spotify_id.is_none() {
+            library.save(&Track {
+                spotify_id: Some(spotify_id.to_string()),
+                ..track
+            });
+        }
     }
 }
 
+/// The account data format uses "2023-01-31 21:04" in UTC.
+fn parse_end_time(end_time: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    Ok(NaiveDateTime::parse_from_str(end_time, "%Y-%m-%d %H:%M")?.and_utc())
+}
+
+/// An entry of the extended streaming history. Streaming_History_Video_*.json
+/// files have exactly the same fields as the audio files, with video
+/// podcasts in the episode fields, so they're read with this too. Their
+/// events are told apart by source_type.
 #[derive(Debug, Deserialize, Clone, Serialize)]
 struct StreamingHistoryAudioEntry {
     pub ts: Option<String>,
@@ -67,6 +226,17 @@ struct StreamingHistoryAudioEntry {
     pub reason_end: Option<String>,
 }
 
+/// Fields of the extended history that are stored on the Event but are not
+/// part of its identity.
+#[derive(Debug, Deserialize, Clone)]
+struct StreamingHistoryDetails {
+    pub platform: Option<String>,
+    pub shuffle: Option<bool>,
+    pub episode_name: Option<String>,
+    pub episode_show_name: Option<String>,
+    pub spotify_episode_uri: Option<String>,
+}
+
 // {
 //     "ts": "2011-07-15T18:55:24Z",
 //     "username": "jvonnieda",
@@ -91,9 +261,34 @@ struct StreamingHistoryAudioEntry {
 //     "incognito_mode": null
 //   },
 
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamingHistoryEntry {
+    pub end_time: String,
+    pub artist_name: String,
+    pub track_name: String,
+    pub ms_played: u64,
+}
+
+// {
+//     "endTime" : "2023-01-31 21:04",
+//     "artistName" : "Perturbator",
+//     "trackName" : "Corrupted by Design",
+//     "msPlayed" : 286784
+// }
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamingHistoryPodcastEntry {
+    pub end_time: String,
+    pub podcast_name: String,
+    pub episode_name: String,
+    pub ms_played: u64,
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{import, library::Library, model::Event};
+    use crate::{import::{self, HistoryImport}, librarian::{self, ArtistMetadata, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Event, ModelBasics as _, Release, Track}};
 
     #[test]
     fn it_works() {
@@ -103,5 +298,75 @@ mod tests {
         import::spotify::import(&library, "tests/data/spotify_history");
         assert!(library.list::<Event>().len() > 0);
     }
-}
 
+    #[test]
+    fn idempotent() {
+        let library = Library::open_memory();
+        import::spotify::import(&library, "tests/data/spotify_history");
+        let count = library.list::<Event>().len();
+        import::spotify::import(&library, "tests/data/spotify_history");
+        assert!(library.list::<Event>().len() == count);
+    }
+
+    #[test]
+    fn skips_invalid_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Streaming_History_Audio_2024.json"), r#"[
+            null,
+            { "ts": "yesterday", "master_metadata_track_name": "Mandala", "ms_played": 1 },
+            { "ts": "2011-07-15T18:55:24Z", "master_metadata_track_name": "Mandala", "ms_played": 1 }
+        ]"#).unwrap();
+        std::fs::write(dir.path().join("StreamingHistory_music_0.json"), r#"[
+            { "endTime": "2023-01-31 21:04", "artistName": "Perturbator", "trackName": "Corrupted by Design", "msPlayed": 1 },
+            { "endTime": "2023-01-31 21:04", "artistName": "Perturbator" }
+        ]"#).unwrap();
+        let library = Library::open_memory();
+        let stats = import::spotify::import(&library, dir.path().to_str().unwrap());
+        assert!(stats == HistoryImport { imported: 2, skipped: 3 });
+        assert!(library.list::<Event>().len() == 2);
+    }
+
+    #[test]
+    fn formats() {
+        let library = Library::open_memory();
+        import::spotify::import(&library, "tests/data/spotify_history");
+        let events = library.list::<Event>();
+        assert!(events.iter().all(|e| e.ms_played.is_some()));
+        assert!(events.iter().any(|e| e.source_type == "spotify::StreamingHistoryEntry"));
+        assert!(events.iter().any(|e| e.source_type == "spotify::StreamingHistoryPodcastEntry"));
+        assert!(events.iter().any(|e| e.source_type == "spotify::StreamingHistoryVideoEntry"));
+        assert!(events.iter().any(|e| e.event_type == "episode_played"));
+    }
+
+    #[test]
+    fn links_tracks() {
+        let library = Library::open_memory();
+        let artists = vec![ArtistMetadata {
+            artist: Artist {
+                name: Some("Perturbator".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let track = librarian::merge_track_metadata(&library, &TrackMetadata {
+            track: Track {
+                title: Some("Corrupted by Design".to_string()),
+                ..Default::default()
+            },
+            artists: artists.clone(),
+            release: Some(ReleaseMetadata {
+                release: Release {
+                    title: Some("New Model".to_string()),
+                    ..Default::default()
+                },
+                artists,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }, None);
+        assert!(track.spotify_id.is_none());
+        import::spotify::import(&library, "tests/data/spotify_history");
+        let track = Track::get(&library, &track.key.unwrap()).unwrap();
+        assert!(track.spotify_id == Some("4ck5sxsVVOxOhqgaKf6RId".to_string()));
+    }
+}
@@ -152,12 +152,21 @@ impl SymphoniaTaggedMediaFile {
             // support in v0.6.
             synchronized_lyrics: None,
 
+            isrc: self.tag(StandardTagKey::IdentIsrc),
+            bpm: self.tag(StandardTagKey::Bpm).and_then(|s| parse_bpm(&s)),
+            // Not mapped by Symphonia 0.5.
+            initial_key: None,
+            work: None,
+            mood: self.tag(StandardTagKey::Mood),
+            comment: self.tag(StandardTagKey::Comment),
+
             discogs_id: None,
             lastfm_id: None,
             musicbrainz_id: self.tag(StandardTagKey::MusicBrainzTrackId).or_else(|| self.tag(StandardTagKey::MusicBrainzReleaseTrackId)),
             spotify_id: None,
             wikidata_id: None,
 
+            medium_key: None,
             media_format: self.tag(StandardTagKey::MediaFormat),
             media_position: self.tag(StandardTagKey::DiscNumber)
                 .and_then(|s| parse_n_of_m_tag(&s).0),
@@ -173,6 +182,7 @@ impl SymphoniaTaggedMediaFile {
         Release {
             key: None,
             title: self.tag(StandardTagKey::Album),
+            sort_name: self.tag(StandardTagKey::SortAlbum),
             disambiguation: None,
             summary: None,
             save: false,
@@ -187,10 +197,14 @@ impl SymphoniaTaggedMediaFile {
             barcode: self.tag(StandardTagKey::IdentBarcode),
             country: self.tag(StandardTagKey::ReleaseCountry),
             date: self.tag(StandardTagKey::Date),
+            original_date: self.tag(StandardTagKey::OriginalDate),
+            label: self.tag(StandardTagKey::Label),
+            catalog_number: self.tag(StandardTagKey::IdentCatalogNumber),
             packaging: self.tag(StandardTagKey::MediaFormat),
             quality: None,
             status: self.tag(StandardTagKey::MusicBrainzReleaseStatus),
             release_group_type: self.tag(StandardTagKey::MusicBrainzReleaseType),
+            release_group_key: None,
         }
     }
 
@@ -261,6 +275,13 @@ pub fn parse_n_of_m_tag(value: &str) -> (Option<u32>, Option<u32>) {
     (parts.next().flatten(), parts.next().flatten())
 }
 
+/// BPM is sometimes tagged with a fraction, e.g. "127.96", which is rounded.
+pub fn parse_bpm(value: &str) -> Option<u32> {
+    value.trim().parse::<f64>().ok()
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.)
+        .map(|bpm| bpm.round() as u32)
+}
+
 /// Split artist string handling various separators
 /// TODO need to collect examples of the ones currently failing and add them
 /// as tests.
@@ -5,5 +5,13 @@ pub mod import;
 pub mod player;
 pub mod sync;
 pub mod notifier;
+pub mod network;
 pub mod plugins;
 pub mod merge;
+pub mod tag_writer;
+pub mod fingerprint;
+pub mod duplicates;
+pub mod entities;
+pub mod collation;
+pub mod search;
+pub mod query;
\ No newline at end of file
@@ -1,6 +1,10 @@
+use std::hash::{DefaultHasher, Hash, Hasher as _};
+
 use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization as _};
 
-use crate::{librarian, library::Library, merge::CrdtRules, model::{Artist, ArtistRef, Dimage, DimageRef, Genre, GenreRef, LibraryModel, Link, LinkRef, Model, ModelBasics as _, Release, Track}, plugins::{plugin::Plugin, plugins::Plugins}};
+use crate::{librarian, library::Library, merge::CrdtRules, model::{Alias, Artist, ArtistRef, ArtistRole, Dimage, DimageRef, Genre, GenreRef, LibraryModel, Link, LinkRef, Medium, Model, ModelBasics as _, Release, ReleaseGroup, Track, release_group::edition_base_title}, plugins::{plugin::Plugin, plugins::Plugins}, search::{self, SearchHit, SearchOptions}};
 
 #[derive(Clone)]
 pub struct Librarian {
@@ -65,32 +69,19 @@ pub fn refresh_metadata(library: &Library, plugins: &Plugins, model: &dyn Model)
     }
 }
 
-pub fn search(library: &Library, plugins: &Plugins, query: &str) -> SearchResults {
-    // here we go again, but joyfully
-    let plugin_results = plugins.search(library, query);
-
-    for result in plugin_results {
-        for artist in result.artists {
-            librarian::merge_artist(library, &artist);
-        }
-    }
-
-    let query = format!("%{}%", query);
-    let artists = Artist::query(library, 
-        "SELECT * FROM Artist WHERE name LIKE ?1 LIMIT 25", (&query,));
-    let releases = Release::query(library, 
-        "SELECT * FROM Release WHERE title LIKE ?1 LIMIT 25", (&query,));
-    let genres = Genre::query(library, 
-        "SELECT * FROM Genre WHERE name LIKE ?1 LIMIT 25", (&query,));
-    let tracks = Track::query(library, 
-        "SELECT * FROM Track WHERE title LIKE ?1 LIMIT 25", (&query,));    
-
+/// Search the library, and the plugins for things that aren't in it yet.
+/// Library results are ranked best first and highlighted with the markers
+/// in options, see search::highlight_runs. Plugin results come back as
+/// candidates, which stay out of the library until materialized.
+pub fn search(library: &Library, plugins: &Plugins, query: &str, options: &SearchOptions) -> SearchResults {
+    let hits = search::search(library, query, options);
     SearchResults { 
-        artists, 
-        releases, 
-        genres, 
-        tracks, 
-        ..Default::default()
+        artists: hits.artists,
+        releases: hits.releases,
+        genres: hits.genres,
+        tracks: hits.tracks,
+        corrected_query: hits.corrected_query,
+        candidates: plugins.search(library, query),
     }
 }
 
@@ -107,6 +98,7 @@ pub fn merge_artist_metadata(library: &Library, artist: &ArtistMetadata, pre_mat
     merge_genres(library, &artist.genres, &merged);
     merge_links(library, &artist.links, &merged);
     merge_images(library, &artist.images, &merged);
+    merge_aliases(library, &artist.aliases, &merged);
     merged
 }
 
@@ -117,14 +109,43 @@ pub fn merge_artist_metadata(library: &Library, artist: &ArtistMetadata, pre_mat
 pub fn merge_release_metadata(library: &Library, metadata: &ReleaseMetadata, pre_match: Option<Release>) -> Release {
     let matched = pre_match.or_else(|| match_release(library, &metadata)).unwrap_or_default();
     let merged = CrdtRules::merge(matched, metadata.release.clone());
-    let merged = merged.save(library);
+    let mut merged = merged.save(library);
     merge_artists(library, &metadata.artists, &merged);
     merge_genres(library, &metadata.genres, &merged);
     merge_links(library, &metadata.links, &merged);
     merge_images(library, &metadata.images, &merged);
+    merge_aliases(library, &metadata.aliases, &merged);
+    for medium in &metadata.media {
+        merge_medium(library, &Medium {
+            release_key: merged.key.clone(),
+            ..medium.clone()
+        });
+    }
+    let release_group = merge_release_group(library, metadata, &merged);
+    if merged.release_group_key != release_group.key {
+        merged.release_group_key = release_group.key;
+        merged = merged.save(library);
+    }
     merged
 }
 
+/// Attach the Release to the ReleaseGroup it is an edition of, creating the
+/// group if needed. The artists must already be attached to the Release,
+/// since groups without a MusicBrainz id are matched by artist and title.
+pub fn merge_release_group(library: &Library, metadata: &ReleaseMetadata, release: &Release) -> ReleaseGroup {
+    let release_group = CrdtRules::merge(ReleaseGroup {
+        title: release.title.as_deref().map(edition_base_title),
+        primary_type: release.release_group_type.clone(),
+        first_release_date: release.date.clone(),
+        ..Default::default()
+    }, metadata.release_group.clone().unwrap_or_default());
+    let matched = release.release_group(library)
+        .or_else(|| match_release_group(library, &release_group, release))
+        .unwrap_or_default();
+    let merged = CrdtRules::merge(matched, release_group);
+    merged.save(library)
+}
+
 pub fn merge_track_metadata(library: &Library, metadata: &TrackMetadata, pre_match: Option<Track>) -> Track {
     let matched = pre_match.or_else(|| match_track(library, &metadata)).unwrap_or_default();
     let merged = CrdtRules::merge(matched, metadata.track.clone());
@@ -135,12 +156,29 @@ pub fn merge_track_metadata(library: &Library, metadata: &TrackMetadata, pre_mat
     merge_images(library, &metadata.images, &merged);
     if let Some(release) = metadata.release.clone() {
         let release = merge_release_metadata(library, &release, merged.release(library));
+        // Tracks without a disc number are on the first disc.
+        let medium = merge_medium(library, &Medium {
+            release_key: release.key.clone(),
+            position: Some(merged.media_position.unwrap_or(1)),
+            format: merged.media_format.clone(),
+            title: merged.media_title.clone(),
+            track_count: merged.media_track_count,
+            ..Default::default()
+        });
         merged.release_key = release.key;
+        merged.medium_key = medium.key;
         merged = merged.save(&library);
     }
     merged
 }
 
+/// Merge the Medium with the one at the same position on the same Release.
+pub fn merge_medium(library: &Library, medium: &Medium) -> Medium {
+    let matched = match_medium(library, medium).unwrap_or_default();
+    let merged = CrdtRules::merge(matched, medium.clone());
+    merged.save(library)
+}
+
 pub fn merge_link(library: &Library, link: &Link) -> Link {
     let matched = match_link(library, &link).unwrap_or_default();
     let link = CrdtRules::merge(matched, link.clone());
@@ -180,10 +218,32 @@ pub fn merge_genres<T: LibraryModel>(library: &Library, genres: &[Genre], model:
     }
 }
 
+/// Merge the artists and attach them to the model as its artist credit, in
+/// the order given.
 pub fn merge_artists<T: LibraryModel>(library: &Library, artists: &[ArtistMetadata], model: &T) {
-    for artist in artists {
-        let artist = merge_artist_metadata(library, &artist, None);
-        merge_artist_ref(library, &artist, model);
+    for (position, metadata) in artists.iter().enumerate() {
+        let artist = merge_artist_metadata(library, &metadata, None);
+        ArtistRef::attach_credit(library, &artist, model, position as u32,
+            &metadata.role, metadata.join_phrase.clone(), metadata.credited_name.clone());
+    }
+}
+
+/// Attach the aliases to the model, skipping any it already has.
+pub fn merge_aliases<T: LibraryModel>(library: &Library, aliases: &[Alias], model: &T) {
+    for alias in aliases {
+        let alias = Alias {
+            key: None,
+            model: model.type_name(),
+            model_key: model.key().unwrap(),
+            ..alias.clone()
+        };
+        let matched: Option<Alias> = library.find("
+            SELECT * FROM Alias
+            WHERE model_key = ?1 AND name IS ?2 AND musicbrainz_id IS ?3",
+            (&alias.model_key, &alias.name, &alias.musicbrainz_id));
+        if matched.is_none() {
+            alias.save(library);
+        }
     }
 }
 
@@ -204,8 +264,26 @@ pub fn match_artist(library: &Library, artist: &Artist) -> Option<Artist> {
         SELECT Artist.* 
         FROM Artist 
         WHERE (Artist.musicbrainz_id IS NOT NULL AND Artist.musicbrainz_id = ?1)
-        OR (Artist.name IS NOT NULL AND Artist.name = ?2 AND ((Artist.disambiguation IS NULL AND ?3 IS NULL) OR (Artist.disambiguation = ?3)))
-        ", (&artist.musicbrainz_id, &artist.name, &artist.disambiguation))
+        OR (Artist.name IS NOT NULL AND normalize_name(Artist.name) = ?2 AND ((Artist.disambiguation IS NULL AND ?3 IS NULL) OR (normalize_name(Artist.disambiguation) = ?3)))
+        ", (&artist.musicbrainz_id, normalize(&artist.name), normalize(&artist.disambiguation)))
+        .or_else(|| match_alias(library, &artist.musicbrainz_id, &artist.name))
+}
+
+/// The models that can have Aliases.
+const ALIASED_MODELS: [&str; 4] = ["Artist", "Release", "Genre", "Track"];
+
+/// Find the model that has an Alias with the MusicBrainz id or name.
+pub fn match_alias<T: LibraryModel>(library: &Library, musicbrainz_id: &Option<String>, name: &Option<String>) -> Option<T> {
+    let type_name = T::default().type_name();
+    // The table name can't be bound, so it's checked instead.
+    assert!(ALIASED_MODELS.contains(&type_name.as_str()), "{} can't have aliases", type_name);
+    library.find(&format!("
+        SELECT m.* FROM Alias
+        JOIN {} m ON (m.key = Alias.model_key)
+        WHERE Alias.model = ?3
+            AND ((Alias.musicbrainz_id IS NOT NULL AND Alias.musicbrainz_id = ?1)
+                OR (Alias.name IS NOT NULL AND normalize_name(Alias.name) = ?2))
+        ", type_name), (musicbrainz_id, normalize(name), &type_name))
 }
 
 pub fn match_release(library: &Library, release: &ReleaseMetadata) -> Option<Release> {
@@ -222,8 +300,26 @@ pub fn match_release(library: &Library, release: &ReleaseMetadata) -> Option<Rel
             SELECT r.* FROM Release r
             LEFT JOIN ArtistRef rar ON (rar.model_key = r.key)
             LEFT JOIN Artist ra ON (ra.key = rar.artist_key)
-            WHERE (r.title = ?1 AND ra.name = ?2)
-            ", (&release.release.title, artist.artist.name));
+            WHERE (normalize_name(r.title) = ?1 AND normalize_name(ra.name) = ?2)
+            ", (normalize(&release.release.title), normalize(&artist.artist.name)));
+        if matched_release.is_some() {
+            return matched_release
+        }
+    }
+    // Release titles aren't unique, so aliases are matched by title only
+    // together with an artist.
+    let matched_release = match_alias(library, &release.release.musicbrainz_id, &None);
+    if matched_release.is_some() {
+        return matched_release
+    }
+    for artist in release.artists.clone() {
+        let matched_release: Option<Release> = library.find("
+            SELECT r.* FROM Alias a
+            JOIN Release r ON (r.key = a.model_key)
+            JOIN ArtistRef rar ON (rar.model_key = r.key)
+            JOIN Artist ra ON (ra.key = rar.artist_key)
+            WHERE a.model = 'Release' AND normalize_name(a.name) = ?1 AND normalize_name(ra.name) = ?2
+            ", (normalize(&release.release.title), normalize(&artist.artist.name)));
         if matched_release.is_some() {
             return matched_release
         }
@@ -231,6 +327,28 @@ pub fn match_release(library: &Library, release: &ReleaseMetadata) -> Option<Rel
     None
 }
 
+/// Match by MusicBrainz id, or else by edition base title and a shared
+/// primary artist. Releases without artists are not grouped by title alone,
+/// since titles like "Greatest Hits" are not unique.
+pub fn match_release_group(library: &Library, release_group: &ReleaseGroup, release: &Release) -> Option<ReleaseGroup> {
+    let matched = library.find("
+        SELECT ReleaseGroup.*
+        FROM ReleaseGroup
+        WHERE ReleaseGroup.musicbrainz_id IS NOT NULL AND ReleaseGroup.musicbrainz_id = ?1",
+        (&release_group.musicbrainz_id,));
+    if matched.is_some() {
+        return matched
+    }
+    library.find("
+        SELECT g.* FROM ReleaseGroup g
+        JOIN Release r ON (r.release_group_key = g.key)
+        JOIN ArtistRef rar ON (rar.model_key = r.key AND rar.role = 'primary')
+        JOIN ArtistRef ar ON (ar.artist_key = rar.artist_key AND ar.role = 'primary')
+        WHERE ar.model_key = ?1 AND normalize_name(g.title) = ?2
+            AND (g.musicbrainz_id IS NULL OR ?3 IS NULL)
+        ", (&release.key, normalize(&release_group.title), &release_group.musicbrainz_id))
+}
+
 pub fn match_track(library: &Library, track: &TrackMetadata) -> Option<Track> {
     // Try to find the track by a unique identifier
     let matched_track = library.find("
@@ -251,8 +369,9 @@ pub fn match_track(library: &Library, track: &TrackMetadata) -> Option<Track> {
                 LEFT JOIN Artist ta ON (ta.key = tar.artist_key)
                 LEFT JOIN ArtistRef rar ON (rar.model_key = r.key)
                 LEFT JOIN Artist ra ON (ra.key = rar.artist_key)
-                WHERE (t.title = ?1 AND r.title = ?2 AND (ta.name = ?3 OR ra.name = ?3))
-                ", (&track.track.title, &release.release.title, artist.artist.name));
+                WHERE (normalize_name(t.title) = ?1 AND normalize_name(r.title) = ?2
+                    AND (normalize_name(ta.name) = ?3 OR normalize_name(ra.name) = ?3))
+                ", (normalize(&track.track.title), normalize(&release.release.title), normalize(&artist.artist.name)));
             if matched_track.is_some() {
                 return matched_track
             }
@@ -261,13 +380,21 @@ pub fn match_track(library: &Library, track: &TrackMetadata) -> Option<Track> {
     None
 }
 
+pub fn match_medium(library: &Library, medium: &Medium) -> Option<Medium> {
+    library.find("
+        SELECT Medium.* FROM Medium
+        WHERE Medium.release_key = ?1 AND Medium.position = ?2",
+        (&medium.release_key, &medium.position))
+}
+
 pub fn match_genre(library: &Library, genre: &Genre) -> Option<Genre> {
     library.find("
         SELECT Genre.* 
         FROM Genre 
         WHERE (Genre.musicbrainz_id IS NOT NULL AND Genre.musicbrainz_id = ?1)
-        OR (Genre.name IS NOT NULL AND Genre.name = ?2 COLLATE NOCASE AND ((Genre.disambiguation IS NULL AND ?3 IS NULL) OR (Genre.disambiguation = ?3 COLLATE NOCASE)))
-        ", (&genre.musicbrainz_id, &genre.name, &genre.disambiguation))
+        OR (Genre.name IS NOT NULL AND normalize_name(Genre.name) = ?2 AND ((Genre.disambiguation IS NULL AND ?3 IS NULL) OR (normalize_name(Genre.disambiguation) = ?3)))
+        ", (&genre.musicbrainz_id, normalize(&genre.name), normalize(&genre.disambiguation)))
+        .or_else(|| match_alias(library, &genre.musicbrainz_id, &genre.name))
 }
 
 pub fn match_link(library: &Library, link: &Link) -> Option<Link> {
@@ -286,15 +413,75 @@ pub fn match_dimage(library: &Library, dimage: &Dimage) -> Option<Dimage> {
         ", (&dimage.sha256,))
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
+/// Normalize a name or title for matching, so that differences in case,
+/// diacritics, punctuation, a leading article and "&" vs "and" are ignored:
+/// "The Beatles", "Beatles, The" and "beatles" are all "beatles". Registered
+/// with SQLite as normalize_name(), which the match queries compare against.
+///
+/// The indexes from migration 09 are built on it, so any change to what it
+/// returns needs a migration that REINDEXes them, as 15 does, or lookups
+/// will miss rows indexed with the old output. And since SQLite evaluates
+/// it on every write to those tables, a connection that doesn't register
+/// it, see LibraryConnectionCustomizer, can't write them.
+pub fn normalize_name(name: &str) -> String {
+    let folded = name.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase();
+    let mut s = String::with_capacity(folded.len());
+    for c in folded.chars() {
+        match c {
+            'ø' => s.push('o'),
+            'æ' => s.push_str("ae"),
+            'œ' => s.push_str("oe"),
+            'ß' => s.push_str("ss"),
+            'ł' => s.push('l'),
+            'đ' => s.push('d'),
+            '&' | '+' => s.push_str(" and "),
+            '\'' | '’' | '.' => (),
+            c if c.is_alphanumeric() => s.push(c),
+            _ => s.push(' '),
+        }
+    }
+    let mut words = s.split_whitespace().collect::<Vec<_>>();
+    // "Beatles, The" has lost its comma by now, so a trailing article is
+    // only dropped if the original had one.
+    if words.len() > 1 && words.last() == Some(&"the") && folded.trim_end().ends_with(", the") {
+        words.pop();
+    }
+    // The article is only dropped if what's left isn't an article too, so
+    // that "The The" doesn't become "The".
+    let is_article = |word: &str| matches!(word, "the" | "a" | "an");
+    if words.len() > 1 && is_article(words[0]) && !(words.len() == 2 && is_article(words[1])) {
+        words.remove(0);
+    }
+    if words.is_empty() {
+        return name.trim().to_lowercase()
+    }
+    words.join(" ")
+}
+
+fn normalize(name: &Option<String>) -> Option<String> {
+    name.as_deref().map(normalize_name)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ArtistMetadata {
     pub artist: Artist,
     pub genres: Vec<Genre>,
     pub links: Vec<Link>,
     pub images: Vec<Dimage>,
+    pub aliases: Vec<Alias>,
+    // Credit details, used when the artist is part of a Track or Release
+    // artist credit.
+    pub role: ArtistRole,
+    pub join_phrase: Option<String>,
+    pub credited_name: Option<String>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ReleaseMetadata {
     pub release: Release,
     pub artists: Vec<ArtistMetadata>,
@@ -302,9 +489,13 @@ pub struct ReleaseMetadata {
     pub links: Vec<Link>,
     pub tracks: Vec<TrackMetadata>,
     pub images: Vec<Dimage>,
+    pub media: Vec<Medium>,
+    pub release_group: Option<ReleaseGroup>,
+    pub aliases: Vec<Alias>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TrackMetadata {
     pub track: Track,
     pub artists: Vec<ArtistMetadata>,
@@ -314,18 +505,143 @@ pub struct TrackMetadata {
     pub images: Vec<Dimage>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Default)]
+#[derive(Clone, Debug, PartialEq, Default)]
 pub struct SearchResults {
-    pub tracks: Vec<Track>,
-    pub artists: Vec<Artist>,
-    pub releases: Vec<Release>,
-    pub genres: Vec<Genre>,
+    pub tracks: Vec<SearchHit<Track>>,
+    pub artists: Vec<SearchHit<Artist>>,
+    pub releases: Vec<SearchHit<Release>>,
+    pub genres: Vec<SearchHit<Genre>>,
+    /// Set to the query that was searched if typos were corrected.
+    pub corrected_query: Option<String>,
+    /// Results from plugins, which aren't in the library.
+    pub candidates: Candidates,
+}
+
+/// A search result from a plugin, such as an artist on MusicBrainz. It isn't
+/// in the library until it's materialized, which should only happen when
+/// the user opens or saves it, so that searching doesn't fill the library
+/// with things nobody asked for.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+pub struct Candidate<T> {
+    /// The type_name of the plugin that found it.
+    #[serde(default)]
+    pub plugin: String,
+    pub metadata: T,
 }
 
+impl<T> From<T> for Candidate<T> {
+    fn from(metadata: T) -> Self {
+        Self {
+            plugin: String::new(),
+            metadata,
+        }
+    }
+}
+
+/// What identifies a search result within the plugin that found it, see
+/// Candidate::id.
+pub trait CandidateSource: Hash {
+    /// The MusicBrainz id of the result, or else its first link.
+    fn source_id(&self) -> Option<String>;
+}
+
+impl CandidateSource for ArtistMetadata {
+    fn source_id(&self) -> Option<String> {
+        self.artist.musicbrainz_id.clone().or_else(|| self.links.first().map(|link| link.url.clone()))
+    }
+}
+
+impl CandidateSource for ReleaseMetadata {
+    fn source_id(&self) -> Option<String> {
+        self.release.musicbrainz_id.clone().or_else(|| self.links.first().map(|link| link.url.clone()))
+    }
+}
+
+impl CandidateSource for TrackMetadata {
+    fn source_id(&self) -> Option<String> {
+        self.track.musicbrainz_id.clone().or_else(|| self.links.first().map(|link| link.url.clone()))
+    }
+}
+
+impl CandidateSource for Genre {
+    fn source_id(&self) -> Option<String> {
+        self.musicbrainz_id.clone()
+    }
+}
+
+impl<T: CandidateSource> Candidate<T> {
+    /// The same for the same result from the same plugin, across searches:
+    /// the plugin and the source id of the result, or a hash of the result
+    /// if it has no source id.
+    pub fn id(&self) -> String {
+        let source_id = self.metadata.source_id().unwrap_or_else(|| {
+            let mut hasher = DefaultHasher::new();
+            self.metadata.hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        });
+        format!("{}:{}", self.plugin, source_id)
+    }
+}
+
+impl Candidate<ArtistMetadata> {
+    pub fn materialize(&self, library: &Library) -> Artist {
+        merge_artist_metadata(library, &self.metadata, None)
+    }
+}
+
+impl Candidate<ReleaseMetadata> {
+    pub fn materialize(&self, library: &Library) -> Release {
+        merge_release_metadata(library, &self.metadata, None)
+    }
+}
+
+impl Candidate<TrackMetadata> {
+    pub fn materialize(&self, library: &Library) -> Track {
+        merge_track_metadata(library, &self.metadata, None)
+    }
+}
+
+impl Candidate<Genre> {
+    pub fn materialize(&self, library: &Library) -> Genre {
+        merge_genre(library, &self.metadata)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Candidates {
+    pub artists: Vec<Candidate<ArtistMetadata>>,
+    pub releases: Vec<Candidate<ReleaseMetadata>>,
+    pub tracks: Vec<Candidate<TrackMetadata>>,
+    pub genres: Vec<Candidate<Genre>>,
+}
+
+impl Candidates {
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.releases.is_empty()
+            && self.tracks.is_empty() && self.genres.is_empty()
+    }
+
+    /// Append the other candidates, tagged with the plugin they came from.
+    pub fn extend(&mut self, plugin: &str, other: Candidates) {
+        fn tag<T>(plugin: &str, candidates: Vec<Candidate<T>>) -> impl Iterator<Item = Candidate<T>> + '_ {
+            candidates.into_iter().map(move |candidate| Candidate {
+                plugin: plugin.to_string(),
+                ..candidate
+            })
+        }
+        self.artists.extend(tag(plugin, other.artists));
+        self.releases.extend(tag(plugin, other.releases));
+        self.tracks.extend(tag(plugin, other.tracks));
+        self.genres.extend(tag(plugin, other.genres));
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use crate::{librarian::{self, ArtistMetadata, Librarian}, library::Library, model::Artist, plugins::{example::ExamplePlugin, fanart_tv::FanartTvPlugin, lrclib::LrclibPlugin, musicbrainz::MusicBrainzPlugin, plugins::Plugins, wikidata::WikidataPlugin}};
+    use crate::{librarian::{self, ArtistMetadata, Candidates, Librarian}, library::Library, model::{Alias, Artist, Genre, ModelBasics as _}, plugins::{plugin::Plugin, example::ExamplePlugin, fanart_tv::FanartTvPlugin, lrclib::LrclibPlugin, musicbrainz::MusicBrainzPlugin, plugins::Plugins, wikidata::WikidataPlugin}, search::SearchOptions};
 
     #[test]
     fn merge_artist_metadata() {
@@ -370,6 +686,95 @@ mod tests {
         assert!(artist3.key == artist4.key);
     }
 
+    #[test]
+    fn normalize_name() {
+        let n = librarian::normalize_name;
+        assert!(n("The Beatles") == n("Beatles"));
+        assert!(n("Beatles, The") == n("beatles"));
+        assert!(n("Sigur Rós") == n("Sigur Ros"));
+        assert!(n("Simon & Garfunkel") == n("Simon and Garfunkel"));
+        assert!(n("Don't  Stop!") == n("dont stop"));
+        assert!(n("R.E.M.") == n("REM"));
+        assert!(n("The The") == "the the");
+        assert!(n("The The") != n("The"));
+        assert!(n("The") == "the");
+        assert!(n("A") == "a");
+        assert!(n("!!!") == "!!!");
+    }
+
+    struct CandidatePlugin;
+
+    impl Plugin for CandidatePlugin {
+        fn type_name(&self) -> String {
+            "CandidatePlugin".to_string()
+        }
+
+        fn search(&self, _host: &Plugins, _library: &Library, query: &str) -> Result<Candidates, anyhow::Error> {
+            Ok(Candidates {
+                artists: vec![ArtistMetadata {
+                    artist: Artist {
+                        name: Some(query.to_string()),
+                        musicbrainz_id: Some("65f4f0c5-ef9e-490c-aee3-909e7ae6b2ab".to_string()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }.into()],
+                genres: vec![Genre::new("metal").into()],
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn search_candidates() {
+        let library = Library::open_memory();
+        let plugins = Plugins::default();
+        plugins.add_plugin(Arc::new(CandidatePlugin));
+        let results = librarian::search(&library, &plugins, "Dethklok", &SearchOptions::default());
+        assert!(results.artists.is_empty());
+        assert!(results.candidates.artists.len() == 1);
+        assert!(results.candidates.artists[0].plugin == "CandidatePlugin");
+        assert!(results.candidates.artists[0].id() == "CandidatePlugin:65f4f0c5-ef9e-490c-aee3-909e7ae6b2ab");
+        assert!(results.candidates.genres[0].id() == librarian::search(&library, &plugins, "Dethklok", &SearchOptions::default()).candidates.genres[0].id());
+        assert!(Artist::list(&library).is_empty());
+        assert!(Genre::list(&library).is_empty());
+
+        // Materialized once opened, and then found locally.
+        let artist = results.candidates.artists[0].materialize(&library);
+        assert!(artist.key.is_some());
+        let results = librarian::search(&library, &plugins, "Dethklok", &SearchOptions::default());
+        assert!(results.artists.iter().map(|hit| hit.model.clone()).collect::<Vec<_>>() == vec![artist.clone()]);
+        assert!(results.candidates.artists[0].materialize(&library).key == artist.key);
+        assert!(Artist::list(&library).len() == 1);
+    }
+
+    #[test]
+    fn match_normalized_and_aliases() {
+        let library = Library::open_memory();
+        let artist = librarian::merge_artist_metadata(&library, &ArtistMetadata {
+            artist: Artist {
+                name: Some("Motörhead".to_string()),
+                ..Default::default()
+            },
+            aliases: vec![Alias {
+                name: Some("Headcat".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }, None);
+        for name in ["motorhead", "The Motorhead", "HEADCAT"] {
+            let matched = librarian::merge_artist_metadata(&library, &ArtistMetadata {
+                artist: Artist {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }, None);
+            assert!(matched.key == artist.key);
+        }
+        assert!(library.list::<Artist>().len() == 1);
+    }
+
     #[test]
     fn image() {
         let _ = env_logger::try_init();
@@ -1,16 +1,16 @@
-use std::{fmt::Debug, sync::{Arc, Mutex, RwLock}, time::Duration};
+use std::{fmt::Debug, path::Path, sync::{Arc, Mutex, RwLock}, time::Duration};
 
 use image::DynamicImage;
 use include_dir::{include_dir, Dir};
 use log::info;
 use r2d2::{CustomizeConnection, Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{backup::Backup, Connection, OptionalExtension, Params};
+use rusqlite::{backup::Backup, functions::FunctionFlags, Connection, OptionalExtension, Params};
 use rusqlite_migration::Migrations;
 use ulid::Generator;
 use uuid::Uuid;
 
-use crate::{model::{Artist, Blob, ChangeLog, FromRow, Genre, LibraryModel, MediaFile, Model, ModelBasics as _, Release, Track, TrackSource}, notifier::Notifier, sync::Sync};
+use crate::{collation, librarian, model::{release_group::edition_base_title, Artist, Blob, ChangeLog, FromRow, Genre, LibraryModel, MediaFile, Model, ModelBasics as _, Release, Track, TrackSource}, network::{Network, OfflineError}, notifier::Notifier, sync::Sync};
 
 #[derive(Clone)]
 pub struct Library {
@@ -21,14 +21,28 @@ pub struct Library {
     // sync from Library entirely.
     synchronizers: Arc<RwLock<Vec<Sync>>>,
     pub notifier: Notifier<LibraryEvent>,
+    /// Sync is skipped while offline.
+    pub network: Network,
 }
 
+static MIGRATION_DIR: Dir = include_dir!("./dimple_core/src/migrations");
+
 #[derive(Debug)]
 struct LibraryConnectionCustomizer;
 impl CustomizeConnection<rusqlite::Connection, rusqlite::Error> for LibraryConnectionCustomizer {
     fn on_acquire(&self, conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
         conn.pragma_update(None, "journal_mode", "WAL")?;
         conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.create_scalar_function("normalize_name", 1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|name| librarian::normalize_name(&name))))?;
+        conn.create_scalar_function("generate_sort_name", 1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|name| collation::generate_sort_name(&name))))?;
+        conn.create_scalar_function("edition_base_title", 1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|title| edition_base_title(&title))))?;
+        conn.create_collation(collation::UNICODE, collation::compare)?;
         Ok(())
     }
 }
@@ -47,6 +61,7 @@ impl Library {
             ulids: Arc::new(Mutex::new(Generator::new())),
             synchronizers: Arc::new(RwLock::new(vec![])),
             notifier: Notifier::new(),
+            network: Network::default(),
         };
 
         library.initialize_db();
@@ -71,6 +86,7 @@ impl Library {
             ulids: Arc::new(Mutex::new(Generator::new())),
             synchronizers: Arc::new(RwLock::new(vec![])),
             notifier: Notifier::new(),
+            network: Network::default(),
         };
         
         library.initialize_db();
@@ -81,7 +97,6 @@ impl Library {
     fn initialize_db(&self) {
         let mut conn = self.conn();
 
-        static MIGRATION_DIR: Dir = include_dir!("./dimple_core/src/migrations");
         let migrations = Migrations::from_directory(&MIGRATION_DIR).unwrap();
 
         migrations.to_latest(&mut conn).unwrap();
@@ -127,12 +142,13 @@ impl Library {
         self.synchronizers.write().unwrap().push(sync);
     }
 
-    pub fn sync(&self) {
+    pub fn sync(&self) -> Result<(), OfflineError> {
         if let Ok(syncs) = self.synchronizers.read() {
             for sync in syncs.iter() {
-                sync.sync(self);
+                sync.sync(self)?;
             }
         }
+        Ok(())
     }
 
     /// Generates a ulid that is guaranteed to be monotonic.
@@ -173,6 +189,38 @@ impl Library {
         obj
     }
 
+    /// Delete the object by key. Refs to it are left to the caller.
+    pub fn delete<T: LibraryModel>(&self, obj: &T) {
+        self.transaction(|conn| Self::delete_row(conn, obj));
+        self.notify(obj);
+    }
+
+    /// Delete the object's row on conn, for deletes inside transaction().
+    /// The caller sends the LibraryEvent with notify() after the commit.
+    pub(crate) fn delete_row<T: LibraryModel>(conn: &Connection, obj: &T) {
+        let sql = format!("DELETE FROM {} WHERE key = ?1", obj.type_name());
+        conn.execute(&sql, (&obj.key().unwrap(),)).unwrap();
+    }
+
+    /// Send the LibraryEvent for a change to the object.
+    pub(crate) fn notify<T: LibraryModel>(&self, obj: &T) {
+        self.notifier.notify(LibraryEvent {
+            type_name: obj.type_name(),
+            key: obj.key().unwrap(),
+            library: self.clone(),
+        });
+    }
+
+    /// Run f in a transaction on a single connection. It's rolled back if f
+    /// panics, which the library's queries do on errors.
+    pub fn transaction<R>(&self, f: impl FnOnce(&Connection) -> R) -> R {
+        let mut conn = self.conn();
+        let tx = conn.transaction().unwrap();
+        let result = f(&tx);
+        tx.commit().unwrap();
+        result
+    }
+
     pub fn get<T: LibraryModel>(&self, key: &str) -> Option<T> {
         let sql = format!("SELECT * FROM {} WHERE key = ?1", T::default().type_name());
         self.conn().query_row(&sql, (key,), 
@@ -256,6 +304,23 @@ impl Library {
         None
     }
 
+    /// Record a change that isn't a field change, such as a merge, so that
+    /// synced libraries replay it.
+    pub fn log_change(&self, model: &str, model_key: &str, op: &str, field: Option<String>, value: Option<String>) -> ChangeLog {
+        let changelog = ChangeLog {
+            key: Some(self.ulid()),
+            actor: self.id(),
+            timestamp: self.ulid(),
+            model: model.to_string(),
+            model_key: model_key.to_string(),
+            op: op.to_string(),
+            field,
+            value,
+        };
+        changelog.upsert(&self.conn());
+        changelog
+    }
+
     pub fn find_newest_changelog_by_field(&self, model: &str, model_key: &str, field: &str) -> Option<ChangeLog> {
         self.conn().query_row_and_then("SELECT * FROM ChangeLog 
             WHERE model = ?1 AND model_key = ?2 AND field = ?3
@@ -287,6 +352,19 @@ impl Library {
             .collect()
     }
         
+    /// Returns the TrackSources of the MediaFiles directly inside the
+    /// directory. Archive members are not included.
+    pub fn track_sources_in_directory(&self, directory: &str) -> Vec<TrackSource> {
+        let prefix = format!("{}{}", directory, std::path::MAIN_SEPARATOR);
+        self.query("
+            SELECT ts.* FROM TrackSource ts
+            JOIN MediaFile mf ON (mf.key = ts.media_file_key)
+            WHERE mf.archive_path IS NULL
+            AND substr(mf.file_path, 1, length(?1)) = ?1
+            AND instr(substr(mf.file_path, length(?1) + 1), ?2) = 0
+        ", (&prefix, std::path::MAIN_SEPARATOR.to_string()))
+    }
+
     pub fn track_sources_by_blob(&self, blob: &Blob) -> Vec<TrackSource> {
         let conn = self.conn();
         let mut stmt = conn.prepare("SELECT * FROM TrackSource
@@ -307,15 +385,24 @@ impl Library {
             .collect()
     }
 
+    /// Load the content of the MediaFile, reading it out of its archive if
+    /// it is an archive member.
+    pub fn load_media_file_content(&self, media_file: &MediaFile) -> Option<Vec<u8>> {
+        if let (Some(archive_path), Some(archive_member)) = (&media_file.archive_path, &media_file.archive_member) {
+            return crate::import::archive::read_member(Path::new(archive_path), archive_member).ok()
+        }
+        std::fs::read(&media_file.file_path).ok()
+    }
+
     pub fn load_blob_content(&self, blob: &Blob) -> Option<Vec<u8>> {
         for media_file in self.media_files_by_sha256(&blob.sha256) {
-            if let Ok(content) = std::fs::read(&media_file.file_path) {
+            if let Some(content) = self.load_media_file_content(&media_file) {
                 info!("Found blob sha256 {} at {}", blob.sha256, &media_file.file_path);
                 return Some(content)
             }
         }
         for sync in self.synchronizers.read().unwrap().iter() {
-            if let Some(content) = sync.load_blob_content(blob) {
+            if let Some(content) = sync.load_blob_content(self, blob) {
                 info!("Found blob sha256 {} in sync", blob.sha256);
                 return Some(content)
             }
@@ -325,7 +412,7 @@ impl Library {
 
     pub fn load_local_blob_content(&self, blob: &Blob) -> Option<Vec<u8>> {
         for media_file in self.media_files_by_sha256(&blob.sha256) {
-            if let Ok(content) = std::fs::read(media_file.file_path) {
+            if let Some(content) = self.load_media_file_content(&media_file) {
                 return Some(content)
             }
         }
@@ -343,7 +430,7 @@ impl Library {
             }
             if let Some(media_file_key) = source.media_file_key {
                 if let Some(media_file) = self.get::<MediaFile>(&media_file_key) {
-                    if let Ok(content) = std::fs::read(media_file.file_path) {
+                    if let Some(content) = self.load_media_file_content(&media_file) {
                         return Some(content)
                     }
                 }
@@ -374,15 +461,45 @@ impl Debug for LibraryEvent {
 mod tests {
     use std::time::Duration;
 
+    use r2d2::CustomizeConnection as _;
+    use rusqlite::Connection;
+    use rusqlite_migration::Migrations;
+
     use crate::model::Track;
 
-    use super::Library;
+    use super::{Library, LibraryConnectionCustomizer, MIGRATION_DIR};
 
     #[test]
     fn it_works() {
         let _library = Library::open_memory();
     }
 
+    #[test]
+    fn release_group_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        LibraryConnectionCustomizer.on_acquire(&mut conn).unwrap();
+        let migrations = Migrations::from_directory(&MIGRATION_DIR).unwrap();
+        migrations.to_version(&mut conn, 5).unwrap();
+        conn.execute_batch("
+            INSERT INTO Artist (key, name) VALUES ('a1', 'Fleetwood Mac');
+            INSERT INTO Release (key, title) VALUES ('r1', 'Rumours');
+            INSERT INTO Release (key, title) VALUES ('r2', 'Rumours (Deluxe Edition)');
+            INSERT INTO Release (key, title) VALUES ('r3', 'rumours [2004 Remaster]');
+            INSERT INTO Release (key, title) VALUES ('r4', 'Tusk');
+            INSERT INTO ArtistRef (model_key, artist_key) VALUES ('r1', 'a1'), ('r2', 'a1'), ('r3', 'a1'), ('r4', 'a1');
+        ").unwrap();
+        migrations.to_latest(&mut conn).unwrap();
+        let groups: Vec<(String, i64)> = conn.prepare("
+                SELECT g.title, COUNT(*) FROM ReleaseGroup g
+                JOIN Release r ON (r.release_group_key = g.key)
+                GROUP BY g.key ORDER BY g.title
+            ").unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert!(groups == vec![("Rumours".to_string(), 3), ("Tusk".to_string(), 1)]);
+    }
+
     #[test]
     fn load_track_content() {
         let library = Library::open_memory();
@@ -1,6 +1,6 @@
 use std::{env, sync::Arc, time::Duration};
 
-use dimple_core::{import::spotify, library::Library, model::{Artist, Blob, ChangeLog, ModelBasics as _, Release, Track}, player::Player, sync::{s3_storage::S3Storage, Sync}};
+use dimple_core::{duplicates::{self, DuplicateOptions}, fingerprint, import::{apple_music, lastfm, playlist_file, spotify}, library::Library, model::{Artist, Blob, ChangeLog, ModelBasics as _, Release, Track}, player::Player, query, tag_writer::{self, TagField, TagWriteOptions}, sync::{s3_storage::S3Storage, Sync}};
 use directories::ProjectDirs;
 
 fn main() {
@@ -40,6 +40,8 @@ fn main() {
         println!("    sync                            Sync the library with an S3 target.");
         println!("    changelogs                      List changelogs.");
         println!("    blobs                           List blobs.");
+        println!("    write_tags [track key] [--dry-run]  Write the track's metadata to its files.");
+        println!("    query \"[query]\"                 List the tracks matching a query, e.g. \"artist:björk year:1995..2000 -live\".");
         return
     }
 
@@ -128,7 +130,7 @@ fn main() {
         }
     }
     else if command == "sync" {
-        library.sync();
+        library.sync().unwrap();
     } 
     else if command == "changelogs" {
         let mut i = 0;
@@ -150,6 +152,79 @@ fn main() {
         let path = &args[2];
         spotify::import(&library, path);
     }
+    if command == "import_playlist" {
+        let path = &args[2];
+        let result = playlist_file::import(&library, std::path::Path::new(path)).unwrap();
+        println!("Imported {} tracks, {} unresolved.", 
+            result.playlist.len(&library), 
+            result.unresolved.len());
+        for entry in result.unresolved {
+            println!("  {}", entry.location);
+        }
+    }
+    if command == "import_apple_music" {
+        let path = &args[2];
+        apple_music::import(&library, path);
+    }
+    if command == "write_tags" {
+        let track = Track::get(&library, &args[2]).unwrap();
+        let options = TagWriteOptions {
+            fields: TagField::ALL.to_vec(),
+            dry_run: args.iter().any(|arg| arg == "--dry-run"),
+        };
+        for result in tag_writer::write_track_tags(&library, &track, &options).unwrap() {
+            println!("{} {}", result.media_file.file_path,
+                if result.written { "(written)" } else { "" });
+            if let Some(reason) = result.skipped {
+                println!("    skipped: {}", reason);
+            }
+            if let Some(error) = result.error {
+                println!("    error: {}", error);
+            }
+            for change in result.changes {
+                println!("    {:?}: {:?} -> {:?}", change.field, change.old, change.new);
+            }
+        }
+    }
+    if command == "import_lastfm" {
+        let path = &args[2];
+        lastfm::import(&library, path);
+    }
+    if command == "fingerprint" {
+        fingerprint::fingerprint_library(&library);
+    }
+    if command == "duplicates" {
+        let options = DuplicateOptions {
+            use_fingerprints: args.iter().any(|arg| arg == "--fingerprints"),
+            ..Default::default()
+        };
+        for group in duplicates::find_duplicate_tracks(&library, &options) {
+            println!("{:?} {:.2}", group.reason, group.confidence);
+            for track in group.tracks {
+                print_track(&library, &track);
+            }
+        }
+    }
+    if command == "merge_tracks" {
+        let track = Track::get(&library, &args[2]).unwrap();
+        let duplicate = Track::get(&library, &args[3]).unwrap();
+        print_track(&library, &duplicates::merge_tracks(&library, &track, &duplicate));
+    }
+    if command == "query" {
+        match query::tracks(&library, &args[2]) {
+            Ok(tracks) => {
+                for track in tracks {
+                    print_track(&library, &track);
+                }
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+    if command == "duplicate_files" {
+        for (a, b, score) in fingerprint::find_duplicate_media_files(&library, 0.85) {
+            println!("{:.3} | {} | {}", score, a.file_path, b.file_path);
+        }
+    }
 }
 
 fn print_artist(library: &Library, artist: &Artist) {
@@ -2,7 +2,7 @@ use std::collections::HashSet;
 
 use chrono::{DateTime, Utc};
 
-use crate::model::{Artist, Dimage, Genre, Link, MediaFile, Release, Track};
+use crate::model::{Artist, Dimage, Genre, Link, MediaFile, Medium, Release, ReleaseGroup, Track};
 
 pub trait CrdtRules {
     /// Commutative: A v B = B v A
@@ -56,6 +56,7 @@ impl CrdtRules for Artist {
         Self {
             key: CrdtRules::merge(l.key, r.key),
             name: CrdtRules::merge(l.name, r.name),
+            sort_name: CrdtRules::merge(l.sort_name, r.sort_name),
             disambiguation: CrdtRules::merge(l.disambiguation, r.disambiguation),
             summary: CrdtRules::merge(l.summary, r.summary),
             save: CrdtRules::merge(l.save, r.save),
@@ -77,6 +78,7 @@ impl CrdtRules for Release {
         Self {
             key: CrdtRules::merge(l.key, r.key),
             title: CrdtRules::merge(l.title, r.title),
+            sort_name: CrdtRules::merge(l.sort_name, r.sort_name),
             disambiguation: CrdtRules::merge(l.disambiguation, r.disambiguation),
             summary: CrdtRules::merge(l.summary, r.summary),
             save: CrdtRules::merge(l.save, r.save),
@@ -85,10 +87,17 @@ impl CrdtRules for Release {
             barcode: CrdtRules::merge(l.barcode, r.barcode),
             country: CrdtRules::merge(l.country, r.country),
             date: CrdtRules::merge(l.date, r.date),
+            original_date: match (l.original_date, r.original_date) {
+                (Some(l), Some(r)) => Some(l.min(r)),
+                (l, r) => l.or(r),
+            },
+            label: CrdtRules::merge(l.label, r.label),
+            catalog_number: CrdtRules::merge(l.catalog_number, r.catalog_number),
             packaging: CrdtRules::merge(l.packaging, r.packaging),
             status: CrdtRules::merge(l.status, r.status),
             quality: CrdtRules::merge(l.quality, r.quality),
             release_group_type: CrdtRules::merge(l.release_group_type, r.release_group_type),
+            release_group_key: CrdtRules::merge(l.release_group_key, r.release_group_key),
 
             discogs_id: CrdtRules::merge(l.discogs_id, r.discogs_id),
             lastfm_id: CrdtRules::merge(l.lastfm_id, r.lastfm_id),
@@ -115,12 +124,20 @@ impl CrdtRules for Track {
             lyrics: CrdtRules::merge(l.lyrics, r.lyrics),
             synchronized_lyrics: CrdtRules::merge(l.synchronized_lyrics, r.synchronized_lyrics),
 
+            isrc: CrdtRules::merge(l.isrc, r.isrc),
+            bpm: CrdtRules::merge(l.bpm, r.bpm),
+            initial_key: CrdtRules::merge(l.initial_key, r.initial_key),
+            work: CrdtRules::merge(l.work, r.work),
+            mood: CrdtRules::merge(l.mood, r.mood),
+            comment: CrdtRules::merge(l.comment, r.comment),
+
             discogs_id: CrdtRules::merge(l.discogs_id, r.discogs_id),
             lastfm_id: CrdtRules::merge(l.lastfm_id, r.lastfm_id),
             musicbrainz_id: CrdtRules::merge(l.musicbrainz_id, r.musicbrainz_id),
             spotify_id: CrdtRules::merge(l.spotify_id, r.spotify_id),
             wikidata_id: CrdtRules::merge(l.wikidata_id, r.wikidata_id),
 
+            medium_key: CrdtRules::merge(l.medium_key, r.medium_key),
             media_format: CrdtRules::merge(l.media_format, r.media_format),
             media_position: CrdtRules::merge(l.media_position, r.media_position),
             media_title: CrdtRules::merge(l.media_title, r.media_title),
@@ -129,6 +146,36 @@ impl CrdtRules for Track {
     }
 }
 
+impl CrdtRules for Medium {
+    fn merge(l: Self, r: Self) -> Self {
+        Self {
+            key: CrdtRules::merge(l.key, r.key),
+            release_key: CrdtRules::merge(l.release_key, r.release_key),
+            position: CrdtRules::merge(l.position, r.position),
+            format: CrdtRules::merge(l.format, r.format),
+            title: CrdtRules::merge(l.title, r.title),
+            track_count: CrdtRules::merge(l.track_count, r.track_count),
+        }
+    }
+}
+
+impl CrdtRules for ReleaseGroup {
+    fn merge(l: Self, r: Self) -> Self {
+        Self {
+            key: CrdtRules::merge(l.key, r.key),
+            title: CrdtRules::merge(l.title, r.title),
+            disambiguation: CrdtRules::merge(l.disambiguation, r.disambiguation),
+            primary_type: CrdtRules::merge(l.primary_type, r.primary_type),
+            // The earliest date of any edition.
+            first_release_date: match (l.first_release_date, r.first_release_date) {
+                (Some(l), Some(r)) => Some(l.min(r)),
+                (l, r) => l.or(r),
+            },
+            musicbrainz_id: CrdtRules::merge(l.musicbrainz_id, r.musicbrainz_id),
+        }
+    }
+}
+
 impl CrdtRules for Dimage {
     fn merge(l: Self, r: Self) -> Self {
         Self {
@@ -172,6 +219,11 @@ impl CrdtRules for MediaFile {
             last_imported: CrdtRules::merge(l.last_imported, r.last_imported),
             last_modified: CrdtRules::merge(l.last_modified, r.last_modified),
             sha256: CrdtRules::merge(l.sha256, r.sha256),
+            archive_path: CrdtRules::merge(l.archive_path, r.archive_path),
+            archive_member: CrdtRules::merge(l.archive_member, r.archive_member),
+            fingerprint: CrdtRules::merge(l.fingerprint, r.fingerprint),
+            duration_ms: CrdtRules::merge(l.duration_ms, r.duration_ms),
+            bitrate: CrdtRules::merge(l.bitrate, r.bitrate),
         }
     }
 }
@@ -0,0 +1,3 @@
+ALTER TABLE MediaFile ADD COLUMN archive_path TEXT;
+ALTER TABLE MediaFile ADD COLUMN archive_member TEXT;
+CREATE INDEX MediaFile_archive_path ON MediaFile (archive_path);
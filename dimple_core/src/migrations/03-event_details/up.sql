@@ -0,0 +1,5 @@
+ALTER TABLE Event ADD COLUMN ms_played INTEGER;
+ALTER TABLE Event ADD COLUMN platform TEXT;
+ALTER TABLE Event ADD COLUMN shuffle BOOL;
+ALTER TABLE Event ADD COLUMN spotify_uri TEXT;
+CREATE INDEX Event_spotify_uri ON Event (spotify_uri);
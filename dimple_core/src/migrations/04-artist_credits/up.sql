@@ -0,0 +1,12 @@
+ALTER TABLE ArtistRef ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE ArtistRef ADD COLUMN role TEXT NOT NULL DEFAULT 'primary';
+ALTER TABLE ArtistRef ADD COLUMN join_phrase TEXT;
+ALTER TABLE ArtistRef ADD COLUMN credited_name TEXT;
+-- Existing refs were ordered by insertion.
+UPDATE ArtistRef SET position = (
+    SELECT COUNT(*) FROM ArtistRef ar2
+    WHERE ar2.model_key = ArtistRef.model_key AND ar2.rowid < ArtistRef.rowid
+);
+DROP INDEX ArtistRef_unique_model_key_artist_key;
+CREATE UNIQUE INDEX ArtistRef_unique_model_key_artist_key_role ON ArtistRef (model_key, artist_key, role);
+CREATE INDEX ArtistRef_artist_key ON ArtistRef (artist_key);
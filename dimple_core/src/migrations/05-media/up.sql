@@ -0,0 +1,27 @@
+CREATE TABLE Medium (
+    key TEXT PRIMARY KEY,
+    release_key TEXT,
+    position INTEGER,
+    format TEXT,
+    title TEXT,
+    track_count INTEGER,
+    FOREIGN KEY (release_key) REFERENCES Release(key)
+);
+CREATE INDEX Medium_release_key_position ON Medium (release_key, position);
+
+ALTER TABLE Track ADD COLUMN medium_key TEXT;
+CREATE INDEX Track_medium_key ON Track (medium_key);
+
+-- Create Media from the values that were denormalized into Track.
+INSERT INTO Medium (key, release_key, position, format, title, track_count)
+    SELECT lower(hex(randomblob(16))), release_key, COALESCE(media_position, 1),
+        MAX(media_format), MAX(media_title), MAX(media_track_count)
+    FROM Track
+    WHERE release_key IS NOT NULL
+    GROUP BY release_key, COALESCE(media_position, 1);
+UPDATE Track SET medium_key = (
+    SELECT Medium.key FROM Medium
+    WHERE Medium.release_key = Track.release_key
+        AND Medium.position = COALESCE(Track.media_position, 1)
+)
+WHERE release_key IS NOT NULL;
@@ -0,0 +1,43 @@
+CREATE TABLE ReleaseGroup (
+    key TEXT PRIMARY KEY,
+    title TEXT,
+    disambiguation TEXT,
+    primary_type TEXT,
+    first_release_date TEXT,
+    musicbrainz_id TEXT
+);
+CREATE INDEX ReleaseGroup_title ON ReleaseGroup (title);
+CREATE INDEX ReleaseGroup_musicbrainz_id ON ReleaseGroup (musicbrainz_id);
+
+ALTER TABLE Release ADD COLUMN release_group_key TEXT;
+CREATE INDEX Release_release_group_key ON Release (release_group_key);
+
+-- Group the existing Releases by base title and first primary artist, the
+-- same as librarian::match_release_group, so editions like "Rumours" and
+-- "Rumours (Deluxe Edition)" share a group. Releases without a title or
+-- artist get a group of their own.
+CREATE TEMP TABLE ReleaseGroupBackfill AS
+    SELECT Release.key AS release_key,
+        COALESCE(normalize_name(edition_base_title(Release.title)) || char(31) || (
+            SELECT ArtistRef.artist_key FROM ArtistRef
+            WHERE ArtistRef.model_key = Release.key AND ArtistRef.role = 'primary'
+            ORDER BY ArtistRef.position ASC LIMIT 1
+        ), Release.key) AS group_id
+    FROM Release;
+CREATE TEMP TABLE ReleaseGroupBackfillKeys AS
+    SELECT group_id, lower(hex(randomblob(16))) AS key
+    FROM ReleaseGroupBackfill
+    GROUP BY group_id;
+INSERT INTO ReleaseGroup (key, title, primary_type, first_release_date)
+    SELECT k.key, edition_base_title(MIN(Release.title)), MAX(Release.release_group_type), MIN(Release.date)
+    FROM ReleaseGroupBackfillKeys k
+    JOIN ReleaseGroupBackfill b ON (b.group_id = k.group_id)
+    JOIN Release ON (Release.key = b.release_key)
+    GROUP BY k.key;
+UPDATE Release SET release_group_key = (
+    SELECT k.key FROM ReleaseGroupBackfill b
+    JOIN ReleaseGroupBackfillKeys k ON (k.group_id = b.group_id)
+    WHERE b.release_key = Release.key
+);
+DROP TABLE ReleaseGroupBackfill;
+DROP TABLE ReleaseGroupBackfillKeys;
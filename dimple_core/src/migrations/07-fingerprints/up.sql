@@ -0,0 +1,2 @@
+ALTER TABLE MediaFile ADD COLUMN fingerprint TEXT;
+ALTER TABLE MediaFile ADD COLUMN duration_ms INTEGER;
@@ -0,0 +1,11 @@
+CREATE TABLE Alias (
+    key TEXT PRIMARY KEY,
+    model TEXT NOT NULL,
+    model_key TEXT NOT NULL,
+    name TEXT,
+    musicbrainz_id TEXT,
+    source TEXT
+);
+CREATE INDEX Alias_model_name ON Alias (model, name);
+CREATE INDEX Alias_model_musicbrainz_id ON Alias (model, musicbrainz_id);
+CREATE INDEX Alias_model_key ON Alias (model_key);
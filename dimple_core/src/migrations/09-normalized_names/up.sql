@@ -0,0 +1,8 @@
+-- normalize_name() is registered on every connection by the Library. The
+-- librarian matches names and titles with it.
+CREATE INDEX Artist_normalized_name ON Artist (normalize_name(name));
+CREATE INDEX Genre_normalized_name ON Genre (normalize_name(name));
+CREATE INDEX Release_normalized_title ON Release (normalize_name(title));
+CREATE INDEX ReleaseGroup_normalized_title ON ReleaseGroup (normalize_name(title));
+CREATE INDEX Track_normalized_title ON Track (normalize_name(title));
+CREATE INDEX Alias_model_normalized_name ON Alias (model, normalize_name(name));
@@ -0,0 +1,4 @@
+ALTER TABLE Artist ADD COLUMN sort_name TEXT;
+ALTER TABLE Release ADD COLUMN sort_name TEXT;
+CREATE INDEX Artist_sort_name ON Artist (COALESCE(sort_name, generate_sort_name(name)) COLLATE UNICODE);
+CREATE INDEX Release_sort_name ON Release (COALESCE(sort_name, generate_sort_name(title)) COLLATE UNICODE);
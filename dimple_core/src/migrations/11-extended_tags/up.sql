@@ -0,0 +1,13 @@
+ALTER TABLE Track ADD COLUMN isrc TEXT;
+ALTER TABLE Track ADD COLUMN bpm INTEGER;
+ALTER TABLE Track ADD COLUMN initial_key TEXT;
+ALTER TABLE Track ADD COLUMN work TEXT;
+ALTER TABLE Track ADD COLUMN mood TEXT;
+ALTER TABLE Track ADD COLUMN comment TEXT;
+ALTER TABLE Release ADD COLUMN original_date TEXT;
+ALTER TABLE Release ADD COLUMN label TEXT;
+ALTER TABLE Release ADD COLUMN catalog_number TEXT;
+CREATE INDEX Track_isrc ON Track (isrc);
+CREATE INDEX Track_work ON Track (work);
+CREATE INDEX Release_label ON Release (label);
+CREATE INDEX Release_catalog_number ON Release (catalog_number);
@@ -0,0 +1,307 @@
+-- Full text search. Each searchable model has an FTS5 table, keyed by the
+-- rowid of the model row, holding a document built by its SearchDocument
+-- view. Triggers rebuild the document of a model whenever it, its refs, or
+-- a model its document mentions, such as the artist of a track, changes.
+-- The columns are the same in every table so that results can be ranked
+-- with the same weights:
+--   name: the name or title, with aliases and work
+--   credits: the names of the credited artists
+--   album: the title of the release of a track
+--   genres: the names of the genres
+--   text: lyrics, summaries, comments, labels and identifiers
+
+CREATE VIRTUAL TABLE ArtistSearch USING fts5(
+    key UNINDEXED, name, credits, album, genres, text,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+CREATE VIRTUAL TABLE ReleaseSearch USING fts5(
+    key UNINDEXED, name, credits, album, genres, text,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+CREATE VIRTUAL TABLE TrackSearch USING fts5(
+    key UNINDEXED, name, credits, album, genres, text,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+CREATE VIRTUAL TABLE GenreSearch USING fts5(
+    key UNINDEXED, name, credits, album, genres, text,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+-- The indexed terms, for correcting typos in queries.
+CREATE VIRTUAL TABLE ArtistSearchVocab USING fts5vocab(ArtistSearch, 'row');
+CREATE VIRTUAL TABLE ReleaseSearchVocab USING fts5vocab(ReleaseSearch, 'row');
+CREATE VIRTUAL TABLE TrackSearchVocab USING fts5vocab(TrackSearch, 'row');
+CREATE VIRTUAL TABLE GenreSearchVocab USING fts5vocab(GenreSearch, 'row');
+
+CREATE VIEW ArtistSearchDocument AS
+SELECT m.rowid AS id, m.key AS key,
+    concat_ws(' ', m.name, m.disambiguation, (SELECT group_concat(al.name, ' ') FROM Alias al WHERE al.model_key = m.key)) AS name,
+    NULL AS credits,
+    NULL AS album,
+    (SELECT group_concat(g.name, ' ') FROM GenreRef gr
+        JOIN Genre g ON (g.key = gr.genre_key) WHERE gr.model_key = m.key) AS genres,
+    concat_ws(' ', m.summary, m.country) AS text
+FROM Artist m;
+
+CREATE VIEW ReleaseSearchDocument AS
+SELECT m.rowid AS id, m.key AS key,
+    concat_ws(' ', m.title, m.disambiguation, (SELECT group_concat(al.name, ' ') FROM Alias al WHERE al.model_key = m.key)) AS name,
+    (SELECT group_concat(a.name, ' ') FROM ArtistRef ar
+        JOIN Artist a ON (a.key = ar.artist_key) WHERE ar.model_key = m.key) AS credits,
+    NULL AS album,
+    (SELECT group_concat(g.name, ' ') FROM GenreRef gr
+        JOIN Genre g ON (g.key = gr.genre_key) WHERE gr.model_key = m.key) AS genres,
+    concat_ws(' ', m.summary, m.label, m.catalog_number, m.barcode) AS text
+FROM Release m;
+
+CREATE VIEW TrackSearchDocument AS
+SELECT m.rowid AS id, m.key AS key,
+    concat_ws(' ', m.title, m.work) AS name,
+    (SELECT group_concat(a.name, ' ') FROM ArtistRef ar
+        JOIN Artist a ON (a.key = ar.artist_key) WHERE ar.model_key = m.key) AS credits,
+    (SELECT r.title FROM Release r WHERE r.key = m.release_key) AS album,
+    (SELECT group_concat(g.name, ' ') FROM GenreRef gr
+        JOIN Genre g ON (g.key = gr.genre_key) WHERE gr.model_key = m.key) AS genres,
+    concat_ws(' ', m.lyrics, m.summary, m.comment, m.mood, m.isrc) AS text
+FROM Track m;
+
+CREATE VIEW GenreSearchDocument AS
+SELECT m.rowid AS id, m.key AS key,
+    concat_ws(' ', m.name, m.disambiguation, (SELECT group_concat(al.name, ' ') FROM Alias al WHERE al.model_key = m.key)) AS name,
+    NULL AS credits,
+    NULL AS album,
+    NULL AS genres,
+    m.summary AS text
+FROM Genre m;
+
+INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text) SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument;
+
+INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text) SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument;
+
+INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text) SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument;
+
+INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text) SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument;
+
+
+CREATE TRIGGER ArtistSearch_insert AFTER INSERT ON Artist BEGIN
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER ArtistSearch_update AFTER UPDATE ON Artist BEGIN
+    DELETE FROM ArtistSearch WHERE rowid = OLD.rowid;
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER ArtistSearch_delete AFTER DELETE ON Artist BEGIN
+    DELETE FROM ArtistSearch WHERE rowid = OLD.rowid;
+END;
+
+CREATE TRIGGER ReleaseSearch_insert AFTER INSERT ON Release BEGIN
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER ReleaseSearch_update AFTER UPDATE ON Release BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid = OLD.rowid;
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER ReleaseSearch_delete AFTER DELETE ON Release BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid = OLD.rowid;
+END;
+
+CREATE TRIGGER TrackSearch_insert AFTER INSERT ON Track BEGIN
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER TrackSearch_update AFTER UPDATE ON Track BEGIN
+    DELETE FROM TrackSearch WHERE rowid = OLD.rowid;
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER TrackSearch_delete AFTER DELETE ON Track BEGIN
+    DELETE FROM TrackSearch WHERE rowid = OLD.rowid;
+END;
+
+CREATE TRIGGER GenreSearch_insert AFTER INSERT ON Genre BEGIN
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER GenreSearch_update AFTER UPDATE ON Genre BEGIN
+    DELETE FROM GenreSearch WHERE rowid = OLD.rowid;
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE id = NEW.rowid;
+END;
+
+CREATE TRIGGER GenreSearch_delete AFTER DELETE ON Genre BEGIN
+    DELETE FROM GenreSearch WHERE rowid = OLD.rowid;
+END;
+
+-- Renaming an artist changes the credits of its releases and tracks.
+CREATE TRIGGER ArtistSearch_rename AFTER UPDATE OF name ON Artist
+WHEN OLD.name IS NOT NEW.name BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (SELECT model_key FROM ArtistRef WHERE artist_key = NEW.key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (SELECT model_key FROM ArtistRef WHERE artist_key = NEW.key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (SELECT model_key FROM ArtistRef WHERE artist_key = NEW.key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (SELECT model_key FROM ArtistRef WHERE artist_key = NEW.key);
+END;
+
+-- Renaming a release changes the album of its tracks.
+CREATE TRIGGER ReleaseSearch_rename AFTER UPDATE OF title ON Release
+WHEN OLD.title IS NOT NEW.title BEGIN
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (SELECT key FROM Track WHERE release_key = NEW.key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (SELECT key FROM Track WHERE release_key = NEW.key);
+END;
+
+-- Renaming a genre changes the genres of everything in it.
+CREATE TRIGGER GenreSearch_rename AFTER UPDATE OF name ON Genre
+WHEN OLD.name IS NOT NEW.name BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (SELECT model_key FROM GenreRef WHERE genre_key = NEW.key);
+END;
+
+CREATE TRIGGER ArtistRefSearch_insert AFTER INSERT ON ArtistRef BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (NEW.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER ArtistRefSearch_update AFTER UPDATE ON ArtistRef BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (OLD.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (NEW.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER ArtistRefSearch_delete AFTER DELETE ON ArtistRef BEGIN
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (OLD.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (OLD.model_key);
+END;
+
+CREATE TRIGGER GenreRefSearch_insert AFTER INSERT ON GenreRef BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (NEW.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (NEW.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER GenreRefSearch_update AFTER UPDATE ON GenreRef BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (OLD.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (OLD.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (NEW.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (NEW.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER GenreRefSearch_delete AFTER DELETE ON GenreRef BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (OLD.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM TrackSearch WHERE rowid IN (SELECT rowid FROM Track WHERE key IN (OLD.model_key));
+    INSERT INTO TrackSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM TrackSearchDocument WHERE key IN (OLD.model_key);
+END;
+
+CREATE TRIGGER AliasSearch_insert AFTER INSERT ON Alias BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (NEW.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM GenreSearch WHERE rowid IN (SELECT rowid FROM Genre WHERE key IN (NEW.model_key));
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER AliasSearch_update AFTER UPDATE ON Alias BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (OLD.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM GenreSearch WHERE rowid IN (SELECT rowid FROM Genre WHERE key IN (OLD.model_key));
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (NEW.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (NEW.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (NEW.model_key);
+    DELETE FROM GenreSearch WHERE rowid IN (SELECT rowid FROM Genre WHERE key IN (NEW.model_key));
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE key IN (NEW.model_key);
+END;
+
+CREATE TRIGGER AliasSearch_delete AFTER DELETE ON Alias BEGIN
+    DELETE FROM ArtistSearch WHERE rowid IN (SELECT rowid FROM Artist WHERE key IN (OLD.model_key));
+    INSERT INTO ArtistSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ArtistSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM ReleaseSearch WHERE rowid IN (SELECT rowid FROM Release WHERE key IN (OLD.model_key));
+    INSERT INTO ReleaseSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM ReleaseSearchDocument WHERE key IN (OLD.model_key);
+    DELETE FROM GenreSearch WHERE rowid IN (SELECT rowid FROM Genre WHERE key IN (OLD.model_key));
+    INSERT INTO GenreSearch (rowid, key, name, credits, album, genres, text)
+        SELECT id, key, name, credits, album, genres, text FROM GenreSearchDocument WHERE key IN (OLD.model_key);
+END;
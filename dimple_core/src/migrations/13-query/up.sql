@@ -0,0 +1,2 @@
+ALTER TABLE MediaFile ADD COLUMN bitrate INTEGER;
+CREATE INDEX Event_title ON Event (title);
@@ -0,0 +1,3 @@
+ALTER TABLE Playlist ADD COLUMN smart_query TEXT;
+ALTER TABLE Playlist ADD COLUMN smart_sort TEXT;
+ALTER TABLE Playlist ADD COLUMN smart_limit INTEGER;
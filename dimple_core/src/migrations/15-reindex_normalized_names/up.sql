@@ -0,0 +1,8 @@
+-- normalize_name() changed to keep "The The" distinct from "The", so the
+-- indexes built on it are rebuilt. See librarian::normalize_name.
+REINDEX Artist_normalized_name;
+REINDEX Genre_normalized_name;
+REINDEX Release_normalized_title;
+REINDEX ReleaseGroup_normalized_title;
+REINDEX Track_normalized_title;
+REINDEX Alias_model_normalized_name;
@@ -0,0 +1,32 @@
+use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
+
+/// Another name or id that an Artist, Release or Genre is known by. The
+/// librarian resolves matches through aliases, so that an entity that was
+/// merged away resolves to the one it was merged into.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Alias {
+    pub key: Option<String>,
+    /// The type name of the aliased model, e.g. "Artist".
+    pub model: String,
+    pub model_key: String,
+    pub name: Option<String>,
+    pub musicbrainz_id: Option<String>,
+    // "merge" or "musicbrainz"
+    pub source: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::library::Library;
+
+    use super::Alias;
+
+    #[test]
+    fn library_crud() {
+        let library = Library::open_memory();
+        let model = library.save(&Alias::default());
+        assert!(model.key.is_some());
+    }
+}
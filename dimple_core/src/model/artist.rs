@@ -1,14 +1,19 @@
 use dimple_core_macro::{model_ignore, ModelSupport};
+use serde::{Deserialize, Serialize};
 
 use crate::library::Library;
 
-use super::{Dimage, Genre, Link, Release};
+use super::{Dimage, Genre, Link, Release, ReleaseGroup};
 
 // https://musicbrainz.org/doc/Artist
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Artist {
     pub key: Option<String>,
     pub name: Option<String>,
+    /// e.g. "Beatles, The". Lists sort by this, or by a sort name generated
+    /// from the name if there isn't one.
+    pub sort_name: Option<String>,
     pub disambiguation: Option<String>,
     pub summary: Option<String>,
     pub save: bool,
@@ -50,6 +55,18 @@ impl Artist {
         library.query(sql, (self.key.clone(),))
     }
 
+    /// The artist's release groups, oldest first, each with its editions.
+    pub fn release_groups(&self, library: &Library) -> Vec<(ReleaseGroup, Vec<Release>)> {
+        let groups = library.query("
+            SELECT DISTINCT g.* FROM ReleaseGroup g
+            JOIN Release r ON (r.release_group_key = g.key)
+            JOIN ArtistRef ar ON (ar.model_key = r.key)
+            WHERE ar.artist_key = ?1
+            ORDER BY g.first_release_date ASC, g.title ASC
+        ", (self.key.clone(),));
+        ReleaseGroup::with_editions(library, groups)
+    }
+
     pub fn images(&self, library: &Library) -> Vec<Dimage> {
         library.query("
             SELECT d.* FROM DimageRef dr 
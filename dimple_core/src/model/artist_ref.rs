@@ -1,25 +1,182 @@
+use rusqlite::{types::FromSql, ToSql};
+use serde::{Deserialize, Serialize};
+
 use crate::library::Library;
 
-use super::{Artist, LibraryModel};
+use super::{Artist, FromRow, LibraryModel};
 
+/// Links an Artist to a Track or Release. Together, the refs for a model
+/// form its artist credit: ordered by position, each with the phrase that
+/// joins it to the next one, e.g. "Lou Reed & Metallica" or
+/// "Gorillaz feat. De La Soul".
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct ArtistRef {
     pub model_key: String,
     pub artist_key: String,
+    pub position: u32,
+    pub role: ArtistRole,
+    pub join_phrase: Option<String>,
+    /// The name as credited, when it differs from the Artist name.
+    pub credited_name: Option<String>,
+}
+
+impl FromRow for ArtistRef {
+    fn from_row(row: &rusqlite::Row) -> Self {
+        Self {
+            model_key: row.get("model_key").unwrap(),
+            artist_key: row.get("artist_key").unwrap(),
+            position: row.get("position").unwrap(),
+            role: row.get("role").unwrap(),
+            join_phrase: row.get("join_phrase").unwrap(),
+            credited_name: row.get("credited_name").unwrap(),
+        }
+    }
 }
 
 impl ArtistRef {
+    /// Attach the artist as a primary artist after any existing ones.
     pub fn attach(library: &Library, artist: &Artist, model: &impl LibraryModel) {
         let _ = library.conn().execute(
-            "INSERT INTO ArtistRef (artist_key, model_key) VALUES (?, ?)", 
+            "INSERT INTO ArtistRef (artist_key, model_key, position)
+            VALUES (?1, ?2, (SELECT COALESCE(MAX(position) + 1, 0) FROM ArtistRef WHERE model_key = ?2))",
             (artist.key.clone(), model.key()));
     }
+
+    /// Attach the artist with a full credit, or update the credit if the
+    /// artist is already attached in the same role. Existing join phrases
+    /// and credited names are kept if the new credit doesn't have them.
+    pub fn attach_credit(library: &Library, artist: &Artist, model: &impl LibraryModel,
+        position: u32, role: &ArtistRole, join_phrase: Option<String>, credited_name: Option<String>) {
+
+        let _ = library.conn().execute(
+            "INSERT INTO ArtistRef (artist_key, model_key, position, role, join_phrase, credited_name)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (model_key, artist_key, role) DO UPDATE SET
+                position = excluded.position,
+                join_phrase = COALESCE(excluded.join_phrase, join_phrase),
+                credited_name = COALESCE(excluded.credited_name, credited_name)",
+            (artist.key.clone(), model.key(), position, role, join_phrase, credited_name));
+    }
+
+    /// The artist credits of the model, in order.
+    pub fn credits(library: &Library, model_key: &str) -> Vec<ArtistCredit> {
+        let conn = library.conn();
+        let mut stmt = conn.prepare("
+            SELECT ar.*, a.* FROM ArtistRef ar
+            JOIN Artist a ON (a.key = ar.artist_key)
+            WHERE ar.model_key = ?1
+            ORDER BY ar.position ASC, ar.rowid ASC").unwrap();
+        stmt.query_map((model_key,), |row| {
+                let artist_ref = ArtistRef::from_row(row);
+                Ok(ArtistCredit {
+                    artist: Artist::from_row(row),
+                    role: artist_ref.role,
+                    join_phrase: artist_ref.join_phrase,
+                    credited_name: artist_ref.credited_name,
+                })
+            })
+            .unwrap()
+            .map(|credit| credit.unwrap())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtistRole {
+    #[default]
+    Primary,
+    Featured,
+    Remixer,
+    Composer,
+    Conductor,
+}
+
+impl ArtistRole {
+    /// True for the roles that are part of the displayed artist credit.
+    pub fn is_performer(&self) -> bool {
+        matches!(self, ArtistRole::Primary | ArtistRole::Featured)
+    }
+}
+
+impl FromSql for ArtistRole {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value.as_str()? {
+            "primary" => Ok(ArtistRole::Primary),
+            "featured" => Ok(ArtistRole::Featured),
+            "remixer" => Ok(ArtistRole::Remixer),
+            "composer" => Ok(ArtistRole::Composer),
+            "conductor" => Ok(ArtistRole::Conductor),
+            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for ArtistRole {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        match self {
+            ArtistRole::Primary => Ok("primary".into()),
+            ArtistRole::Featured => Ok("featured".into()),
+            ArtistRole::Remixer => Ok("remixer".into()),
+            ArtistRole::Composer => Ok("composer".into()),
+            ArtistRole::Conductor => Ok("conductor".into()),
+        }
+    }
+}
+
+/// One artist in an artist credit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArtistCredit {
+    pub artist: Artist,
+    pub role: ArtistRole,
+    pub join_phrase: Option<String>,
+    pub credited_name: Option<String>,
+}
+
+impl ArtistCredit {
+    pub fn name(&self) -> Option<String> {
+        self.credited_name.clone().or(self.artist.name.clone())
+    }
+
+    /// The display string for the performing artists of a credit, such as
+    /// "Gorillaz feat. De La Soul". Credits without join phrases, from
+    /// older imports, are joined with ", ".
+    pub fn display(credits: &[ArtistCredit]) -> Option<String> {
+        let credits = credits.iter()
+            .filter(|credit| credit.role.is_performer())
+            .filter(|credit| credit.name().is_some())
+            .collect::<Vec<_>>();
+        if credits.is_empty() {
+            return None
+        }
+        let mut s = String::new();
+        for (i, credit) in credits.iter().enumerate() {
+            s.push_str(&credit.name().unwrap());
+            if i < credits.len() - 1 {
+                s.push_str(credit.join_phrase.as_deref().unwrap_or(", "));
+            }
+            else if let Some(join_phrase) = &credit.join_phrase {
+                s.push_str(join_phrase);
+            }
+        }
+        Some(s.trim_end().to_string())
+    }
+}
+
+/// True if the join phrase introduces featured artists, like " feat. " or
+/// " ft. ". Artists after such a phrase are credited as Featured.
+pub fn is_featuring_join_phrase(join_phrase: &str) -> bool {
+    let join_phrase = join_phrase.trim().to_lowercase();
+    ["feat.", "feat", "ft.", "ft", "featuring", "with"].iter()
+        .any(|phrase| join_phrase == *phrase || join_phrase.starts_with(&format!("{} ", phrase)))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{library::Library, model::{Artist, ArtistRef, Track}};
 
+    use super::{ArtistCredit, ArtistRole};
+
     #[test]
     fn library_crud() {
         let library = Library::open_memory();
@@ -28,5 +185,31 @@ mod tests {
         ArtistRef::attach(&library, &artist, &track);
         assert!(track.artists(&library).len() == 1);
     }
-}
 
+    #[test]
+    fn credits() {
+        let library = Library::open_memory();
+        let track = library.save(&Track::default());
+        let gorillaz = library.save(&Artist {
+            name: Some("Gorillaz".to_string()),
+            ..Default::default()
+        });
+        let de_la_soul = library.save(&Artist {
+            name: Some("De La Soul".to_string()),
+            ..Default::default()
+        });
+        let damon = library.save(&Artist {
+            name: Some("Damon Albarn".to_string()),
+            ..Default::default()
+        });
+        ArtistRef::attach_credit(&library, &damon, &track, 2, &ArtistRole::Composer, None, None);
+        ArtistRef::attach_credit(&library, &de_la_soul, &track, 1, &ArtistRole::Featured, None, None);
+        ArtistRef::attach_credit(&library, &gorillaz, &track, 0, &ArtistRole::Primary, Some(" feat. ".to_string()), None);
+        let credits = ArtistRef::credits(&library, &track.key.clone().unwrap());
+        assert!(credits.len() == 3);
+        assert!(credits[0].artist.name == Some("Gorillaz".to_string()));
+        assert!(ArtistCredit::display(&credits) == Some("Gorillaz feat. De La Soul".to_string()));
+        assert!(track.artists(&library).len() == 2);
+        assert!(track.artist_credit(&library) == Some("Gorillaz feat. De La Soul".to_string()));
+    }
+}
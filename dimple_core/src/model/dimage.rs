@@ -11,7 +11,8 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest as _, Sha256};
 
 /// A model for storing an image in Dimple. Not Image because too overloaded.
-#[derive(Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Dimage {
     pub key: Option<String>,
 
@@ -90,12 +91,13 @@ pub fn resize(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
 /// This list is based on and gives thanks to:
 // https://wiki.fanart.tv/ImageTypes/Music/hdmusiclogo/
 // https://fanart.tv/music-fanart/
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum DimageKind {
     #[default]
     MusicArtistThumb, // 1000x1000
     MusicHdClearLogo, // 800x310
     MusicAlbumCover, // 1000x1000
+    MusicAlbumBack,
     MusicCdArt, // 1000x1000
     MusicArtistBackground, // 1920x1080
     MusicBanner, // 1000x185
@@ -104,7 +106,17 @@ pub enum DimageKind {
 
 impl FromSql for DimageKind {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        Ok(DimageKind::MusicAlbumCover)
+        match value.as_str()? {
+            "MusicArtistThumb" => Ok(DimageKind::MusicArtistThumb),
+            "MusicHdClearLogo" => Ok(DimageKind::MusicHdClearLogo),
+            "MusicAlbumCover" => Ok(DimageKind::MusicAlbumCover),
+            "MusicAlbumBack" => Ok(DimageKind::MusicAlbumBack),
+            "MusicCdArt" => Ok(DimageKind::MusicCdArt),
+            "MusicArtistBackground" => Ok(DimageKind::MusicArtistBackground),
+            "MusicBanner" => Ok(DimageKind::MusicBanner),
+            "MusicRecordLabel" => Ok(DimageKind::MusicRecordLabel),
+            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
     }
 }
 
@@ -114,6 +126,7 @@ impl ToSql for DimageKind {
             DimageKind::MusicArtistThumb => Ok("MusicArtistThumb".into()),
             DimageKind::MusicHdClearLogo => Ok("MusicHdClearLogo".into()),
             DimageKind::MusicAlbumCover => Ok("MusicAlbumCover".into()),
+            DimageKind::MusicAlbumBack => Ok("MusicAlbumBack".into()),
             DimageKind::MusicCdArt => Ok("MusicCdArt".into()),
             DimageKind::MusicArtistBackground => Ok("MusicArtistBackground".into()),
             DimageKind::MusicBanner => Ok("MusicBanner".into()),
@@ -14,5 +14,10 @@ pub struct Event {
     pub title: Option<String>,
     pub source_type: String,
     pub source: String,
+    pub ms_played: Option<u64>,
+    pub platform: Option<String>,
+    pub shuffle: Option<bool>,
+    /// The Spotify URI of the track or episode, if the event came from Spotify.
+    pub spotify_uri: Option<String>,
 }
 
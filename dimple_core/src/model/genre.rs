@@ -1,11 +1,13 @@
 use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
 
 use crate::library::Library;
 
 use super::{Artist, Dimage, Link, Release};
 
 // https://musicbrainz.org/doc/Genre
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Genre {
     pub key: Option<String>,
     pub name: Option<String>,
@@ -42,7 +44,7 @@ impl Genre {
             SELECT Release.* FROM Release
             LEFT JOIN GenreRef ON (GenreRef.model_key = Release.key)
             WHERE GenreRef.genre_key = ?1
-            ORDER BY title ASC
+            ORDER BY COALESCE(sort_name, generate_sort_name(title)) COLLATE UNICODE ASC
         ";
         library.query(sql, (self.key.clone(),))
     }
@@ -52,7 +54,7 @@ impl Genre {
             SELECT Artist.* FROM Artist
             LEFT JOIN GenreRef ON (GenreRef.model_key = Artist.key)
             WHERE GenreRef.genre_key = ?1
-            ORDER BY name ASC
+            ORDER BY COALESCE(sort_name, generate_sort_name(name)) COLLATE UNICODE ASC
         ";
         library.query(sql, (self.key.clone(),))
     }
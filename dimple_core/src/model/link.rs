@@ -1,11 +1,13 @@
 use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
 
 use crate::library::Library;
 
 use super::Genre;
 
 // https://musicbrainz.org/doc/Artist
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Link {
     pub key: Option<String>,
     pub name: Option<String>,
@@ -14,6 +14,19 @@ pub struct MediaFile {
 
     pub last_modified: DateTime<Utc>,
     pub last_imported: DateTime<Utc>,
+
+    // Set when the file is a member of a zip or tar archive. In that case
+    // file_path is the archive path joined with the member path.
+    pub archive_path: Option<String>,
+    pub archive_member: Option<String>,
+
+    // Chromaprint compatible fingerprint and the duration of the audio,
+    // see fingerprint.rs.
+    pub fingerprint: Option<String>,
+    pub duration_ms: Option<u64>,
+
+    // The audio bitrate in kbps.
+    pub bitrate: Option<u32>,
 }
 
 #[cfg(test)]
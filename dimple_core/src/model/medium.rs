@@ -0,0 +1,73 @@
+use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
+
+use crate::library::Library;
+
+use super::{Release, Track};
+
+// https://musicbrainz.org/doc/Medium
+// A disc, side, or other part of a Release. Tracks on a single disc release
+// still get a Medium at position 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Medium {
+    pub key: Option<String>,
+    pub release_key: Option<String>,
+    pub position: Option<u32>,
+    // "CD", "Digital Media", "12\" Vinyl"
+    pub format: Option<String>,
+    pub title: Option<String>,
+    pub track_count: Option<u32>,
+}
+
+impl Medium {
+    pub fn release(&self, library: &Library) -> Option<Release> {
+        self.release_key.clone().and_then(|key| library.get(&key))
+    }
+
+    pub fn tracks(&self, library: &Library) -> Vec<Track> {
+        library.query("
+            SELECT Track.* FROM Track
+            WHERE Track.medium_key = ?1
+            ORDER BY Track.position ASC, Track.rowid ASC
+        ", (self.key.clone(),))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{library::Library, model::{ModelBasics as _, Release, Track}};
+
+    use super::Medium;
+
+    #[test]
+    fn library_crud() {
+        let library = Library::open_memory();
+        let model = library.save(&Medium::default());
+        assert!(model.key.is_some());
+    }
+
+    #[test]
+    fn tracks() {
+        let library = Library::open_memory();
+        let release = Release::default().save(&library);
+        let medium = Medium {
+            release_key: release.key.clone(),
+            position: Some(2),
+            ..Default::default()
+        }.save(&library);
+        for position in [2, 10, 1] {
+            Track {
+                release_key: release.key.clone(),
+                medium_key: medium.key.clone(),
+                position: Some(position),
+                ..Default::default()
+            }.save(&library);
+        }
+        let positions = medium.tracks(&library).iter()
+            .map(|t| t.position.unwrap())
+            .collect::<Vec<_>>();
+        assert!(positions == vec![1, 2, 10]);
+        assert!(medium.release(&library) == Some(release));
+    }
+}
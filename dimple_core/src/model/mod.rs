@@ -32,11 +32,17 @@ pub use genre::Genre;
 mod release;
 pub use release::Release;
 
+mod medium;
+pub use medium::Medium;
+
+pub mod release_group;
+pub use release_group::ReleaseGroup;
+
 mod event;
 pub use event::Event;
 
-mod artist_ref;
-pub use artist_ref::ArtistRef;
+pub mod artist_ref;
+pub use artist_ref::{ArtistCredit, ArtistRef, ArtistRole};
 
 mod genre_ref;
 pub use genre_ref::GenreRef;
@@ -56,6 +62,9 @@ pub use dimage_ref::DimageRef;
 mod playlist_item;
 pub use playlist_item::PlaylistItem;
 
+mod alias;
+pub use alias::Alias;
+
 use crate::library::Library;
 
 pub trait FromRow {
@@ -100,6 +109,20 @@ impl From<ChangeLogValue> for bool {
     }
 }
 
+impl From<Option<bool>> for ChangeLogValue {
+    fn from(value: Option<bool>) -> Self {
+        ChangeLogValue {
+            val: value.map(|v| if v { "true" } else { "false" }.to_string()),
+        }
+    }
+}
+
+impl From<ChangeLogValue> for Option<bool> {
+    fn from(value: ChangeLogValue) -> Self {
+        value.val.map(|v| v == "true")
+    }
+}
+
 impl From<Option<String>> for ChangeLogValue {
     fn from(value: Option<String>) -> Self {
         ChangeLogValue {
@@ -3,6 +3,7 @@ use fractional_index::FractionalIndex;
 use uuid::Uuid;
 use crate::library::Library;
 use crate::model::ModelBasics as _;
+use crate::query;
 
 use super::{Artist, ModelBasics as _, PlaylistItem, Release, Track};
 
@@ -20,15 +21,36 @@ pub struct Playlist {
     pub musicbrainz_id: Option<String>,
     pub spotify_id: Option<String>,
     pub wikidata_id: Option<String>,
+
+    /// Set for smart playlists, whose tracks are the ones matching the
+    /// query, see query.rs, instead of the playlist's items. For example
+    /// "liked:yes -played>90d" with the sort "plays desc" and a limit of 100
+    /// is liked tracks not played in 90 days, most played first.
+    pub smart_query: Option<String>,
+    pub smart_sort: Option<String>,
+    pub smart_limit: Option<u32>,
 }
 
 impl Playlist {
+    pub fn is_smart(&self) -> bool {
+        self.smart_query.is_some()
+    }
+
     pub fn len(&self, library: &Library) -> usize {
         // TODO Change to select count()
         self.tracks(library).len()
     }
 
+    /// The tracks in order. For smart playlists the query is evaluated on
+    /// every call, so the tracks are always up to date with the library.
     pub fn tracks(&self, library: &Library) -> Vec<Track> {
+        if let Some(smart_query) = &self.smart_query {
+            return query::select(library, smart_query, self.smart_sort.as_deref(), self.smart_limit)
+                .unwrap_or_else(|e| {
+                    log::error!("Invalid smart playlist {:?}: {}", self.key, e);
+                    vec![]
+                })
+        }
         let sql = "
             SELECT Track.*
             FROM PlaylistItem
@@ -54,6 +76,10 @@ impl Playlist {
     }
 
     pub fn insert(&self, library: &Library, model: &impl LibraryModel, index: usize) {
+        if self.is_smart() {
+            log::warn!("Can't insert into smart playlist {:?}", self.key);
+            return
+        }
         log::debug!("insert {} {:?} {} {}", 
             model.type_name(), 
             model.key(), 
@@ -77,6 +103,12 @@ impl Playlist {
                     self.insert(library, track, index + i);
                 }
             },
+            "Playlist" => {
+                let playlist = Playlist::get(library, &model.key().unwrap()).unwrap();
+                for (i, track) in playlist.tracks(library).iter().enumerate() {
+                    self.insert(library, track, index + i);
+                }
+            },
             "Track" => {
                 let track = Track::get(library, &model.key().unwrap()).unwrap();
                 let items = self.items(library);
@@ -102,6 +134,20 @@ impl Playlist {
         }
     }
 
+    /// Freeze the current tracks into a new static playlist with the same
+    /// name.
+    pub fn snapshot(&self, library: &Library) -> Playlist {
+        let snapshot = Playlist {
+            name: self.name.clone(),
+            summary: self.summary.clone(),
+            ..Default::default()
+        }.save(library);
+        for track in self.tracks(library) {
+            snapshot.append(library, &track);
+        }
+        snapshot
+    }
+
     pub fn ordinal_between(left: &Option<String>, right: &Option<String>) -> String {
         match (left, right) {
             (None, None) => FractionalIndex::default().to_string(),
@@ -159,6 +205,45 @@ mod tests {
         assert!(playlist.len(&library) == 20);
     }
 
+    #[test]
+    fn smart() {
+        let library = Library::open_memory();
+        for i in 0..5 {
+            Track {
+                title: Some(format!("track {}", i)),
+                save: i % 2 == 0,
+                ..Default::default()
+            }.save(&library);
+        }
+        let smart = Playlist {
+            name: Some("Liked".to_string()),
+            smart_query: Some("liked:yes".to_string()),
+            smart_sort: Some("title desc".to_string()),
+            smart_limit: Some(2),
+            ..Default::default()
+        }.save(&library);
+        let titles = |playlist: &Playlist| playlist.tracks(&library).iter()
+            .map(|t| t.title.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert!(titles(&smart) == vec!["track 4", "track 2"]);
+
+        // Smart playlists follow the library, and snapshots don't.
+        let snapshot = smart.snapshot(&library);
+        assert!(!snapshot.is_smart());
+        Track {
+            title: Some("track 9".to_string()),
+            save: true,
+            ..Default::default()
+        }.save(&library);
+        assert!(titles(&smart) == vec!["track 9", "track 4"]);
+        assert!(titles(&snapshot) == vec!["track 4", "track 2"]);
+
+        // They queue like static playlists.
+        let queue = Playlist::default().save(&library);
+        queue.append(&library, &smart);
+        assert!(titles(&queue) == vec!["track 9", "track 4"]);
+    }
+
     #[test]
     fn ordinals() {
         let a = Playlist::ordinal_between(&None, &None);
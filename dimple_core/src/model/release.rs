@@ -1,16 +1,19 @@
 use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
 
 use crate::library::Library;
 
-use super::{Artist, Dimage, Genre, Link, Track};
+use super::{Artist, ArtistCredit, ArtistRef, Dimage, Genre, Link, Medium, ReleaseGroup, Track};
 
 // https://musicbrainz.org/doc/Release
 // https://musicbrainz.org/release/a4864e94-6d75-4ade-bc93-0dabf3521453
 // https://musicbrainz.org/ws/2/release/a4864e94-6d75-4ade-bc93-0dabf3521453?fmt=json
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Release {
     pub key: Option<String>,
     pub title: Option<String>,
+    pub sort_name: Option<String>,
     pub disambiguation: Option<String>,
     pub summary: Option<String>,
     pub save: bool,
@@ -19,11 +22,16 @@ pub struct Release {
     pub barcode: Option<String>,
     pub country: Option<String>,
     pub date: Option<String>,
+    // The date of the first release of the release group, for reissues.
+    pub original_date: Option<String>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
     pub packaging: Option<String>,
     // "Official"
     pub status: Option<String>,
     pub quality: Option<String>,
     pub release_group_type: Option<String>,
+    pub release_group_key: Option<String>,
 
     pub discogs_id: Option<String>,
     pub lastfm_id: Option<String>,
@@ -41,17 +49,37 @@ impl Release {
         self.artist(library).and_then(|a| a.name)
     }
 
-    /// TODO this should return the artists in order, with the primary being
-    /// first. I'm not exactly sure how to indicate primary yet.
+    /// The primary and featured artists, in credit order.
     pub fn artists(&self, library: &Library) -> Vec<Artist> {
         library.query("
             SELECT a.* FROM ArtistRef ar 
             JOIN Artist a ON (a.key = ar.artist_key) 
-            WHERE ar.model_key = ?1
-            ORDER BY ar.rowid ASC
+            WHERE ar.model_key = ?1 AND ar.role IN ('primary', 'featured')
+            ORDER BY ar.position ASC, ar.rowid ASC
         ", (self.key.clone().unwrap(),))
     }
 
+    pub fn artist_credits(&self, library: &Library) -> Vec<ArtistCredit> {
+        ArtistRef::credits(library, &self.key.clone().unwrap())
+    }
+
+    /// The display artist credit, such as "Lou Reed & Metallica".
+    pub fn artist_credit(&self, library: &Library) -> Option<String> {
+        ArtistCredit::display(&self.artist_credits(library))
+    }
+
+    pub fn release_group(&self, library: &Library) -> Option<ReleaseGroup> {
+        self.release_group_key.clone().and_then(|key| library.get(&key))
+    }
+
+    /// The other editions in the same release group.
+    pub fn editions(&self, library: &Library) -> Vec<Release> {
+        self.release_group(library).iter()
+            .flat_map(|group| group.releases(library))
+            .filter(|release| release.key != self.key)
+            .collect()
+    }
+
     pub fn genres(&self, library: &Library) -> Vec<Genre> {
         library.query("
             SELECT g.* FROM GenreRef gr 
@@ -68,20 +96,45 @@ impl Release {
         ", (self.key.clone().unwrap(),))
     }
 
+    /// Tracks in disc order and then track order. Tracks without a disc
+    /// number are treated as being on the first disc.
     pub fn tracks(&self, library: &Library) -> Vec<Track> {
         let sql = "
             SELECT Track.* FROM Track
+            LEFT JOIN Medium ON (Medium.key = Track.medium_key)
             WHERE Track.release_key = ?1
-            ORDER BY media_position ASC, position ASC
+            ORDER BY COALESCE(Medium.position, Track.media_position, 1) ASC,
+                Track.position ASC, Track.rowid ASC
         ";
         library.query(sql, (self.key.clone(),))
     }
 
+    pub fn media(&self, library: &Library) -> Vec<Medium> {
+        library.query("
+            SELECT Medium.* FROM Medium
+            WHERE Medium.release_key = ?1
+            ORDER BY Medium.position ASC
+        ", (self.key.clone(),))
+    }
+
+    /// Tracks grouped by Medium, in disc order.
+    pub fn tracks_by_medium(&self, library: &Library) -> Vec<(Medium, Vec<Track>)> {
+        self.media(library).into_iter()
+            .map(|medium| {
+                let tracks = medium.tracks(library);
+                (medium, tracks)
+            })
+            .filter(|(_, tracks)| !tracks.is_empty())
+            .collect()
+    }
+
+    /// Front covers come first, since the first image is the one shown.
     pub fn images(&self, library: &Library) -> Vec<Dimage> {
         library.query("
             SELECT d.* FROM DimageRef dr 
             JOIN Dimage d ON (d.key = dr.dimage_key) 
             WHERE dr.model_key = ?1
+            ORDER BY CASE WHEN d.kind = 'MusicAlbumCover' THEN 0 ELSE 1 END, dr.rowid ASC
         ", (self.key.clone().unwrap(),))
     }
 }
@@ -0,0 +1,127 @@
+use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
+
+use crate::library::Library;
+
+use super::Release;
+
+// https://musicbrainz.org/doc/Release_Group
+// The album, single, or EP that the Releases are editions of, e.g. the
+// original CD, the vinyl reissue, and the deluxe edition.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReleaseGroup {
+    pub key: Option<String>,
+    pub title: Option<String>,
+    pub disambiguation: Option<String>,
+    // "Album", "Single", "EP"
+    pub primary_type: Option<String>,
+    pub first_release_date: Option<String>,
+
+    pub musicbrainz_id: Option<String>,
+}
+
+impl ReleaseGroup {
+    /// The editions of the group, oldest first.
+    pub fn releases(&self, library: &Library) -> Vec<Release> {
+        library.query("
+            SELECT Release.* FROM Release
+            WHERE Release.release_group_key = ?1
+            ORDER BY Release.date IS NULL, Release.date ASC, Release.rowid ASC
+        ", (self.key.clone(),))
+    }
+
+    /// One entry per group, with its editions nested, ordered by title.
+    pub fn list_with_editions(library: &Library) -> Vec<(ReleaseGroup, Vec<Release>)> {
+        let groups: Vec<ReleaseGroup> = library.query("
+            SELECT ReleaseGroup.* FROM ReleaseGroup
+            WHERE EXISTS (SELECT 1 FROM Release WHERE Release.release_group_key = ReleaseGroup.key)
+            ORDER BY generate_sort_name(ReleaseGroup.title) COLLATE UNICODE ASC
+        ", ());
+        Self::with_editions(library, groups)
+    }
+
+    pub fn with_editions(library: &Library, groups: Vec<ReleaseGroup>) -> Vec<(ReleaseGroup, Vec<Release>)> {
+        groups.into_iter()
+            .map(|group| {
+                let releases = group.releases(library);
+                (group, releases)
+            })
+            .collect()
+    }
+}
+
+/// The title of a release with edition qualifiers removed, so that
+/// "Rumours (Deluxe Edition)" and "Rumours [2004 Remaster]" both give
+/// "Rumours". Used to group editions when there is no release group id.
+pub fn edition_base_title(title: &str) -> String {
+    const EDITION_WORDS: [&str; 14] = ["edition", "deluxe", "remaster", "expanded", "anniversary",
+        "bonus", "version", "special", "limited", "reissue", "mono", "stereo", "collector", "super"];
+    let is_edition = |s: &str| {
+        let s = s.to_lowercase();
+        EDITION_WORDS.iter().any(|word| s.contains(word))
+    };
+    let mut title = title.trim().to_string();
+    loop {
+        let trimmed = if let Some(inner) = title.strip_suffix(')').and_then(|t| t.rsplit_once('(')) {
+            is_edition(inner.1).then(|| inner.0.to_string())
+        }
+        else if let Some(inner) = title.strip_suffix(']').and_then(|t| t.rsplit_once('[')) {
+            is_edition(inner.1).then(|| inner.0.to_string())
+        }
+        else if let Some((base, suffix)) = title.rsplit_once(" - ") {
+            is_edition(suffix).then(|| base.to_string())
+        }
+        else {
+            None
+        };
+        match trimmed {
+            Some(trimmed) if !trimmed.trim().is_empty() => title = trimmed.trim().to_string(),
+            _ => return title,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{library::Library, model::{ModelBasics as _, Release}};
+
+    use super::{edition_base_title, ReleaseGroup};
+
+    #[test]
+    fn library_crud() {
+        let library = Library::open_memory();
+        let model = library.save(&ReleaseGroup::default());
+        assert!(model.key.is_some());
+    }
+
+    #[test]
+    fn list_with_editions() {
+        let library = Library::open_memory();
+        let group = ReleaseGroup {
+            title: Some("Rumours".to_string()),
+            ..Default::default()
+        }.save(&library);
+        for date in ["2004", "1977"] {
+            Release {
+                title: Some("Rumours".to_string()),
+                date: Some(date.to_string()),
+                release_group_key: group.key.clone(),
+                ..Default::default()
+            }.save(&library);
+        }
+        let groups = ReleaseGroup::list_with_editions(&library);
+        assert!(groups.len() == 1);
+        assert!(groups[0].1.len() == 2);
+        assert!(groups[0].1[0].date == Some("1977".to_string()));
+    }
+
+    #[test]
+    fn base_title() {
+        assert!(edition_base_title("Rumours (Deluxe Edition)") == "Rumours");
+        assert!(edition_base_title("Abbey Road [2019 Remaster] (Super Deluxe)") == "Abbey Road");
+        assert!(edition_base_title("Heroes - 2017 Remaster") == "Heroes");
+        assert!(edition_base_title("(What's the Story) Morning Glory?") == "(What's the Story) Morning Glory?");
+        assert!(edition_base_title("Live (Bonus)") == "Live");
+    }
+}
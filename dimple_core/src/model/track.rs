@@ -1,12 +1,14 @@
 use dimple_core_macro::ModelSupport;
+use serde::{Deserialize, Serialize};
 
 use crate::library::Library;
 
-use super::{Artist, Dimage, Genre, Link, ModelBasics as _, Release};
+use super::{Artist, ArtistCredit, ArtistRef, ArtistRole, Dimage, Genre, Link, Medium, ModelBasics as _, Release};
 
 // // https://musicbrainz.org/doc/Track
 // // https://musicbrainz.org/ws/2/release/4d3ce256-ea71-44c5-8ce9-deb8f1e7dce4?inc=aliases%2Bartist-credits%2Blabels%2Bdiscids%2Brecordings&fmt=json
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, ModelSupport, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Track {
     pub key: Option<String>,
     pub title: Option<String>,
@@ -24,14 +26,25 @@ pub struct Track {
     // LRC format (https://en.wikipedia.org/wiki/LRC_(file_format))
     pub synchronized_lyrics: Option<String>,
 
+    pub isrc: Option<String>,
+    pub bpm: Option<u32>,
+    // Musical key, as tagged, e.g. "Am" or "8A".
+    pub initial_key: Option<String>,
+    // The title of the composition, for classical recordings.
+    pub work: Option<String>,
+    pub mood: Option<String>,
+    pub comment: Option<String>,
+
     pub discogs_id: Option<String>,
     pub lastfm_id: Option<String>,
     pub musicbrainz_id: Option<String>,
     pub spotify_id: Option<String>,
     pub wikidata_id: Option<String>,
 
-    // Have decided not to create a separate model for Medium for now, so
-    // denormalized that data into Track.
+    pub medium_key: Option<String>,
+
+    // The Medium values as read from tags. The librarian uses these to find
+    // or create the Medium.
     pub media_track_count: Option<u32>,
     pub media_position: Option<u32>,
     pub media_title: Option<String>,
@@ -43,6 +56,10 @@ impl Track {
         self.release_key.clone().and_then(|key| Release::get(library, &key))
     }
 
+    pub fn medium(&self, library: &Library) -> Option<Medium> {
+        self.medium_key.clone().and_then(|key| Medium::get(library, &key))
+    }
+
     pub fn album_name(&self, library: &Library) -> Option<String> {
         self.release(library).and_then(|r| r.title)
     }
@@ -55,26 +72,49 @@ impl Track {
         self.artist(library).and_then(|a| a.name)
     }
 
-    /// TODO this should return the artists in order, with the primary being
-    /// first. I'm not exactly sure how to indicate primary yet.
+    /// The primary and featured artists, in credit order.
     pub fn artists(&self, library: &Library) -> Vec<Artist> {
         self.key.as_ref().map(|key| {
             library.query("
                 SELECT a.* FROM ArtistRef ar 
                 JOIN Artist a ON (a.key = ar.artist_key) 
-                WHERE ar.model_key = ?1
-                ORDER BY ar.rowid ASC
+                WHERE ar.model_key = ?1 AND ar.role IN ('primary', 'featured')
+                ORDER BY ar.position ASC, ar.rowid ASC
             ", (key,))
         }).unwrap_or_default()
     }
 
+    /// The artists credited in a role, such as the composers or conductors.
+    pub fn artists_with_role(&self, library: &Library, role: &ArtistRole) -> Vec<Artist> {
+        self.key.as_ref().map(|key| {
+            library.query("
+                SELECT a.* FROM ArtistRef ar
+                JOIN Artist a ON (a.key = ar.artist_key)
+                WHERE ar.model_key = ?1 AND ar.role = ?2
+                ORDER BY ar.position ASC, ar.rowid ASC
+            ", (key, role))
+        }).unwrap_or_default()
+    }
+
+    /// All artist credits, including composers and remixers.
+    pub fn artist_credits(&self, library: &Library) -> Vec<ArtistCredit> {
+        self.key.as_ref()
+            .map(|key| ArtistRef::credits(library, key))
+            .unwrap_or_default()
+    }
+
+    /// The display artist credit, such as "Gorillaz feat. De La Soul".
+    pub fn artist_credit(&self, library: &Library) -> Option<String> {
+        ArtistCredit::display(&self.artist_credits(library))
+    }
+
     pub fn genres(&self, library: &Library) -> Vec<Genre> {
         self.key.as_ref().map(|key| {
             library.query("
                 SELECT g.* FROM GenreRef gr 
                 JOIN Genre g ON (g.key = gr.genre_key) 
                 WHERE gr.model_key = ?1
-                ORDER BY g.name ASC
+                ORDER BY g.name COLLATE UNICODE ASC
             ", (key,))
         }).unwrap_or_default()
     }
@@ -103,7 +143,7 @@ impl Track {
 mod tests {
     use std::{hash::DefaultHasher};
 
-    use crate::{library::Library, model::{Artist, ArtistRef, Diff, Genre, GenreRef}};
+    use crate::{librarian::{self, ArtistMetadata, TrackMetadata}, library::Library, model::{Artist, ArtistRef, ArtistRole, Diff, Genre, GenreRef, ModelBasics as _}, plugins::plugins::Plugins, search::SearchOptions};
 
     use super::{Track};
 
@@ -136,6 +176,41 @@ mod tests {
         // dbg!(track.artists(&library));
     }
 
+    #[test]
+    fn extended_tags() {
+        let library = Library::open_memory();
+        let track = librarian::merge_track_metadata(&library, &TrackMetadata {
+            track: Track {
+                title: Some("I. Allegro".to_string()),
+                work: Some("Symphony No. 5 in C minor, Op. 67".to_string()),
+                isrc: Some("DEF056730100".to_string()),
+                bpm: Some(108),
+                initial_key: Some("Cm".to_string()),
+                ..Default::default()
+            },
+            artists: vec![ArtistMetadata {
+                artist: Artist {
+                    name: Some("Carlos Kleiber".to_string()),
+                    ..Default::default()
+                },
+                role: ArtistRole::Conductor,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }, None);
+        let track = Track::get(&library, &track.key.clone().unwrap()).unwrap();
+        assert!(track.bpm == Some(108));
+        assert!(track.work == Some("Symphony No. 5 in C minor, Op. 67".to_string()));
+        assert!(track.artists_with_role(&library, &ArtistRole::Conductor).len() == 1);
+        assert!(track.artists(&library).is_empty());
+
+        let plugins = Plugins::default();
+        for query in ["Symphony No. 5", "DEF056730100", "Kleiber"] {
+            let tracks = librarian::search(&library, &plugins, query, &SearchOptions::default()).tracks;
+            assert!(tracks.len() == 1 && tracks[0].model == track);
+        }
+    }
+
     #[test]
     fn genres() {
         let library = Library::open_memory();
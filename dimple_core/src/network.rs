@@ -0,0 +1,97 @@
+use std::{fmt::Display, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc}};
+
+use crate::notifier::Notifier;
+
+/// The offline switch for everything in Dimple that goes out to the
+/// network: plugin requests through Plugins::get and Plugins::client, and
+/// Sync. Each of those asks for permission with request() before doing any
+/// I/O, and fails fast with an OfflineError when it's off, or serves from
+/// the cache if it can. The Library and Plugins share one, see
+/// Plugins::set_network.
+#[derive(Clone)]
+pub struct Network {
+    offline: Arc<AtomicBool>,
+    requests: Arc<AtomicU64>,
+    pub notifier: Notifier<NetworkEvent>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkEvent {
+    pub offline: bool,
+}
+
+/// Returned instead of making a request while offline. Check for it with
+/// `e.downcast_ref::<OfflineError>()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OfflineError {
+    pub url: String,
+}
+
+impl Display for OfflineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Offline: {}", self.url)
+    }
+}
+
+impl std::error::Error for OfflineError {}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            offline: Default::default(),
+            requests: Default::default(),
+            notifier: Notifier::new(),
+        }
+    }
+}
+
+impl Network {
+    pub fn set_offline(&self, offline: bool) {
+        if self.offline.swap(offline, Ordering::Relaxed) != offline {
+            log::info!("Network {}.", if offline { "offline" } else { "online" });
+            self.notifier.notify(NetworkEvent { offline });
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    /// Call before making a request to url. Fails if offline, otherwise
+    /// the request is counted.
+    pub fn request(&self, url: &str) -> Result<(), OfflineError> {
+        if self.is_offline() {
+            return Err(OfflineError { url: url.to_string() })
+        }
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The number of requests allowed since startup, so tests can check
+    /// that none were made.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Network, NetworkEvent, OfflineError};
+
+    #[test]
+    fn offline() {
+        let network = Network::default();
+        let events = network.notifier.observer();
+        assert!(network.request("https://example.com").is_ok());
+        network.set_offline(true);
+        network.set_offline(true);
+        let e = network.request("https://example.com").unwrap_err();
+        assert!(e == OfflineError { url: "https://example.com".to_string() });
+        assert!(network.requests() == 1);
+        network.set_offline(false);
+        assert!(events.try_iter().collect::<Vec<_>>() == vec![
+            NetworkEvent { offline: true },
+            NetworkEvent { offline: false },
+        ]);
+    }
+}
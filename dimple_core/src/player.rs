@@ -275,6 +275,7 @@ impl Player {
                     &current_track.artist_name(&self.library),
                     &current_track.album_name(&self.library),
                     &current_track.title),
+                ..Default::default()
             });
         }
     }
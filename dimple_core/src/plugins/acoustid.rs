@@ -0,0 +1,210 @@
+use std::{env, sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{fingerprint::Fingerprint, librarian::{ArtistMetadata, TrackMetadata}, library::Library, model::{Artist, Track}};
+
+use super::{plugin::Plugin, plugins::Plugins};
+
+// https://acoustid.org/webservice
+// Identifies tracks by the audio fingerprint of their media files, for files
+// that are untagged or tagged too poorly to match. Only the MusicBrainz
+// recording id, title and artists come back; the rest is filled in by the
+// other plugins once the track has a musicbrainz_id. Fingerprinting is too
+// slow for a metadata lookup, so only tracks whose media files were already
+// fingerprinted, see fingerprint::fingerprint_library, are looked up.
+pub struct AcoustIdPlugin {
+    config: RwLock<AcoustIdPluginConfig>,
+    rate_limit_lock: Arc<Mutex<Instant>>,
+}
+
+impl Default for AcoustIdPlugin {
+    fn default() -> Self {
+        Self {
+            config: RwLock::new(AcoustIdPluginConfig {
+                api_key: env::var("ACOUSTID_API_KEY").unwrap_or_default(),
+                min_score: 0.8,
+            }),
+            rate_limit_lock: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl Plugin for AcoustIdPlugin {
+    fn type_name(&self) -> String {
+        "AcoustIdPlugin".to_string()
+    }
+
+    fn display_name(&self) -> String {
+        "AcoustID".to_string()
+    }
+
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        *self.config.write().unwrap() = serde_json::from_str(config)?;
+        Ok(())
+    }
+
+    fn configuration(&self) -> String {
+        serde_json::to_string(&*self.config.read().unwrap()).unwrap()
+    }
+
+    fn track_metadata(&self, host: &Plugins, library: &Library, track: &Track)
+        -> Result<Option<TrackMetadata>, anyhow::Error> {
+
+        let config = self.config.read().unwrap().clone();
+        if config.api_key.is_empty() || track.musicbrainz_id.is_some() {
+            return Ok(None)
+        }
+        let Some(fingerprint) = track_fingerprint(library, track) else {
+            return Ok(None)
+        };
+        // Fingerprints are a few KB, so they go in a form body rather than
+        // the query string.
+        let url = "https://api.acoustid.org/v2/lookup";
+        self.enforce_rate_limit();
+        let response = host.client(url)?.post(url)
+            .form(&[
+                ("format", "json".to_string()),
+                ("client", config.api_key.clone()),
+                ("meta", "recordings".to_string()),
+                ("duration", (fingerprint.duration_ms / 1000).to_string()),
+                ("fingerprint", fingerprint.encode()),
+            ])
+            .send()?
+            .error_for_status()?
+            .json::<LookupResponse>()?;
+        let recording = response.results.iter()
+            .filter(|result| result.score >= config.min_score)
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+            .and_then(|result| result.recordings.iter().flatten().next());
+        let Some(recording) = recording else {
+            return Ok(None)
+        };
+        Ok(Some(TrackMetadata {
+            track: Track {
+                title: recording.title.clone(),
+                musicbrainz_id: Some(recording.id.clone()),
+                length_ms: recording.duration.map(|s| (s * 1000.) as u64),
+                ..Default::default()
+            },
+            artists: recording.artists.iter().flatten()
+                .map(|artist| ArtistMetadata {
+                    artist: Artist {
+                        name: Some(artist.name.clone()),
+                        musicbrainz_id: Some(artist.id.clone()),
+                        ..Default::default()
+                    },
+                    join_phrase: artist.joinphrase.clone().filter(|s| !s.is_empty()),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }))
+    }
+}
+
+impl AcoustIdPlugin {
+    /// AcoustID allows three requests per second.
+    fn enforce_rate_limit(&self) {
+        let mut last_request_time = self.rate_limit_lock.lock().unwrap();
+        let min_interval = Duration::from_millis(334);
+        if let Some(time_passed) = Instant::now().checked_duration_since(*last_request_time) {
+            if time_passed < min_interval {
+                std::thread::sleep(min_interval - time_passed);
+            }
+        }
+        *last_request_time = Instant::now();
+    }
+}
+
+/// The stored fingerprint of the first fingerprinted media file of the
+/// track.
+fn track_fingerprint(library: &Library, track: &Track) -> Option<Fingerprint> {
+    library.track_sources_for_track(track).iter()
+        .filter_map(|track_source| track_source.media_file(library))
+        .filter_map(|media_file| Fingerprint::from_media_file(&media_file))
+        .find(|fingerprint| !fingerprint.sub_fingerprints.is_empty())
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct AcoustIdPluginConfig {
+    pub api_key: String,
+    pub min_score: f64,
+}
+
+// {
+//   "status": "ok",
+//   "results": [{
+//     "id": "9ff43b6a-4f16-427c-93c2-92307ca505e0",
+//     "score": 0.97,
+//     "recordings": [{
+//       "id": "cd2e7c47-16f5-46c6-a37c-a1eb7bf599ff",
+//       "title": "Tomorrow Never Knows",
+//       "duration": 179,
+//       "artists": [{ "id": "b10bbbfc-cf9e-42e0-be17-e2c3e1d2600d", "name": "The Beatles" }]
+//     }]
+//   }]
+// }
+#[derive(Clone, Debug, Deserialize)]
+struct LookupResponse {
+    #[serde(default)]
+    pub results: Vec<LookupResult>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LookupResult {
+    pub score: f64,
+    pub recordings: Option<Vec<LookupRecording>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LookupRecording {
+    pub id: String,
+    pub title: Option<String>,
+    pub duration: Option<f64>,
+    pub artists: Option<Vec<LookupArtist>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LookupArtist {
+    pub id: String,
+    pub name: String,
+    pub joinphrase: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{library::Library, model::{MediaFile, Track}, plugins::{plugin::Plugin, plugins::Plugins}};
+
+    use super::AcoustIdPlugin;
+
+    #[test]
+    fn it_works() {
+        let _ = env_logger::try_init();
+        let library = Library::open_memory();
+        library.import("tests/data/media_files");
+        let plugin = AcoustIdPlugin::default();
+        let host = Plugins::default();
+        // Pink noise has no recording, and without an API key there is no
+        // request at all.
+        for track in library.list::<Track>() {
+            assert!(plugin.track_metadata(&host, &library, &track).unwrap_or_default().is_none());
+        }
+    }
+
+    #[test]
+    fn only_stored_fingerprints() {
+        let library = Library::open_memory();
+        library.import("tests/data/media_files");
+        let plugin = AcoustIdPlugin::default();
+        plugin.set_configuration(r#"{"api_key":"test","min_score":0.8}"#).unwrap();
+        let host = Plugins::default();
+        host.set_offline(true);
+        // Nothing has been fingerprinted yet, so there is nothing to look
+        // up and the media files are left alone.
+        for track in library.list::<Track>() {
+            assert!(plugin.track_metadata(&host, &library, &track).unwrap().is_none());
+        }
+        assert!(library.list::<MediaFile>().iter().all(|media_file| media_file.fingerprint.is_none()));
+    }
+}
@@ -0,0 +1,364 @@
+use std::{collections::HashSet, fs, io::Write as _, path::Path, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde_json::json;
+use walkdir::WalkDir;
+
+use super::plugins::CachedResponse;
+
+/// How long a plugin's responses stay fresh, see Plugin::cache_policy. Once
+/// stale they're revalidated with the server, using the ETag or
+/// Last-Modified it sent, and they're still served if the server can't be
+/// reached or Dimple is offline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachePolicy {
+    pub ttl: Duration,
+    /// 404s are cached too, so that plugins don't keep asking for things
+    /// that don't exist, but not for as long.
+    pub not_found_ttl: Duration,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            not_found_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl CachePolicy {
+    pub fn is_fresh(&self, response: &CachedResponse) -> bool {
+        let ttl = if response.status() == 404 { self.not_found_ttl } else { self.ttl };
+        now_ms().saturating_sub(response.fetched()) < ttl.as_millis() as u64
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size: u64,
+    pub max_size: u64,
+    /// Counts since startup: responses served fresh from the cache,
+    /// fetched, revalidated with a 304, and served stale.
+    pub hits: u64,
+    pub misses: u64,
+    pub revalidated: u64,
+    pub stale: u64,
+}
+
+/// The HTTP cache behind Plugins::get, stored with cacache, keyed by URL and
+/// tagged with the plugin that fetched each response. When it grows past
+/// max_size the least recently used responses are removed. Caching is off
+/// if dir is empty.
+///
+/// The LRU is approximate: marking a response as used appends to the
+/// index, so it's only done once per TOUCH_INTERVAL_MS, and responses used
+/// within that interval of each other may be removed in either order. The
+/// index is compacted on each gc().
+#[derive(Clone)]
+pub struct HttpCache {
+    dir: String,
+    max_size: Arc<AtomicU64>,
+    writes: Arc<AtomicUsize>,
+    pub(crate) counters: Arc<CacheCounters>,
+}
+
+#[derive(Default)]
+pub(crate) struct CacheCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub revalidated: AtomicU64,
+    pub stale: AtomicU64,
+}
+
+const DEFAULT_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Garbage is collected every this many writes.
+const GC_INTERVAL: usize = 100;
+
+/// Entries are marked used at most this often, since each mark appends to
+/// the index.
+const TOUCH_INTERVAL_MS: u128 = 60 * 60 * 1000;
+
+impl HttpCache {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.to_string(),
+            max_size: Arc::new(AtomicU64::new(DEFAULT_MAX_SIZE)),
+            writes: Default::default(),
+            counters: Default::default(),
+        }
+    }
+
+    pub fn set_max_size(&self, max_size: u64) {
+        self.max_size.store(max_size, Ordering::Relaxed);
+        self.gc();
+    }
+
+    /// The cached response, fresh or stale, which is marked as used.
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        if self.dir.is_empty() {
+            return None
+        }
+        let metadata = cacache::metadata_sync(&self.dir, url).ok()??;
+        let bytes = cacache::read_hash_sync(&self.dir, &metadata.integrity).ok()?;
+        let response = serde_json::from_slice(&bytes).ok()?;
+        if (now_ms() as u128).saturating_sub(metadata.time) > TOUCH_INTERVAL_MS {
+            let opts = cacache::WriteOpts::new()
+                .integrity(metadata.integrity)
+                .size(metadata.size)
+                .metadata(metadata.metadata);
+            let _ = cacache::index::insert(self.dir.as_ref(), url, opts);
+        }
+        Some(response)
+    }
+
+    pub fn put(&self, url: &str, plugin: Option<&str>, response: &CachedResponse) {
+        if self.dir.is_empty() {
+            return
+        }
+        if let Err(e) = self.write(url, plugin, response) {
+            log::error!("Error caching {}: {}", url, e);
+            return
+        }
+        if self.writes.fetch_add(1, Ordering::Relaxed) % GC_INTERVAL == GC_INTERVAL - 1 {
+            self.gc();
+        }
+    }
+
+    fn write(&self, url: &str, plugin: Option<&str>, response: &CachedResponse) -> Result<(), anyhow::Error> {
+        let bytes = serde_json::to_vec(response)?;
+        let mut writer = cacache::WriteOpts::new()
+            .size(bytes.len())
+            .metadata(json!({"plugin": plugin}))
+            .open_sync(&self.dir, url)?;
+        writer.write_all(&bytes)?;
+        writer.commit()?;
+        Ok(())
+    }
+
+    /// Compact the index, and remove the least recently used responses
+    /// until the cache fits in max_size.
+    pub fn gc(&self) {
+        if self.dir.is_empty() {
+            return
+        }
+        self.compact_index();
+        let max_size = self.max_size.load(Ordering::Relaxed);
+        let mut entries = self.entries();
+        let mut size = entries.iter().map(|entry| entry.size as u64).sum::<u64>();
+        if size <= max_size {
+            return
+        }
+        entries.sort_by_key(|entry| entry.time);
+        let removed = entries.into_iter()
+            .take_while(|entry| {
+                let over = size > max_size;
+                size = size.saturating_sub(entry.size as u64);
+                over
+            })
+            .collect::<Vec<_>>();
+        self.remove(&removed);
+    }
+
+    /// Remove the responses fetched by the plugin, or all of them.
+    pub fn purge(&self, plugin: Option<&str>) {
+        let removed = self.entries().into_iter()
+            .filter(|entry| plugin.is_none() || entry.metadata["plugin"].as_str() == plugin)
+            .collect::<Vec<_>>();
+        self.remove(&removed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries();
+        CacheStats {
+            entries: entries.len(),
+            size: entries.iter().map(|entry| entry.size as u64).sum(),
+            max_size: self.max_size.load(Ordering::Relaxed),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            revalidated: self.counters.revalidated.load(Ordering::Relaxed),
+            stale: self.counters.stale.load(Ordering::Relaxed),
+        }
+    }
+
+    fn entries(&self) -> Vec<cacache::Metadata> {
+        if self.dir.is_empty() {
+            return vec![]
+        }
+        cacache::list_sync(&self.dir)
+            .filter_map(|entry| entry.ok())
+            .collect()
+    }
+
+    /// Rewrite each index bucket with only the newest line for each key, and
+    /// without removed keys, since every put, touch and removal appends a
+    /// line. The content of the dropped lines is removed too, unless a live
+    /// entry shares it, since a put of an existing URL leaves the old
+    /// content behind. A put that lands while its bucket is being rewritten
+    /// can be lost, which is only a cache miss later.
+    fn compact_index(&self) {
+        let index = Path::new(&self.dir).join(INDEX_DIR);
+        let buckets = WalkDir::new(index).into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file());
+        let mut dropped = HashSet::new();
+        for bucket in buckets {
+            match compact_bucket(bucket.path()) {
+                Ok(integrities) => dropped.extend(integrities),
+                Err(e) => log::warn!("Error compacting cache index {:?}: {}", bucket.path(), e),
+            }
+        }
+        if dropped.is_empty() {
+            return
+        }
+        for entry in self.entries() {
+            dropped.remove(&entry.integrity.to_string());
+        }
+        for integrity in dropped {
+            if let Ok(integrity) = integrity.parse::<cacache::Integrity>() {
+                let _ = cacache::remove_hash_sync(&self.dir, &integrity);
+            }
+        }
+    }
+
+    /// Remove the entries, and their content unless other entries share it.
+    fn remove(&self, removed: &[cacache::Metadata]) {
+        for entry in removed {
+            if let Err(e) = cacache::index::delete(self.dir.as_ref(), &entry.key) {
+                log::warn!("Error removing {} from the cache: {}", entry.key, e);
+            }
+        }
+        let shared = self.entries().into_iter()
+            .map(|entry| entry.integrity.to_string())
+            .collect::<HashSet<_>>();
+        for entry in removed {
+            if !shared.contains(&entry.integrity.to_string()) {
+                let _ = cacache::remove_hash_sync(&self.dir, &entry.integrity);
+            }
+        }
+    }
+}
+
+/// cacache's index directory. Buckets hold one line per write, each a hash
+/// of the entry, a tab, and the entry as JSON.
+const INDEX_DIR: &str = "index-v5";
+
+/// Returns the integrities of every line, dropped or not, since content
+/// can be shared between buckets.
+fn compact_bucket(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let content = fs::read_to_string(path)?;
+    let mut newest: Vec<(String, &str)> = vec![];
+    let mut integrities = vec![];
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let Some((_, entry)) = line.split_once('\t') else {
+            continue
+        };
+        let Ok(entry) = serde_json::from_str::<serde_json::Value>(entry) else {
+            continue
+        };
+        let key = entry["key"].as_str().unwrap_or_default().to_string();
+        newest.retain(|(k, _)| *k != key);
+        // Removed keys have no integrity.
+        if let Some(integrity) = entry["integrity"].as_str() {
+            integrities.push(integrity.to_string());
+            newest.push((key, line));
+        }
+    }
+    if newest.is_empty() {
+        fs::remove_file(path)?;
+        return Ok(integrities)
+    }
+    let compacted = newest.iter().map(|(_, line)| format!("\n{}", line)).collect::<String>();
+    if compacted.len() < content.len() {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, compacted)?;
+        fs::rename(&tmp, path)?;
+    }
+    Ok(integrities)
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use walkdir::WalkDir;
+
+    use crate::plugins::plugins::CachedResponse;
+
+    use super::{CachePolicy, HttpCache, INDEX_DIR};
+
+    #[test]
+    fn cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(dir.path().to_str().unwrap());
+        let response = CachedResponse::new(vec![0; 1000], false, 200);
+        cache.put("https://example.com/a", Some("APlugin"), &response);
+        thread::sleep(Duration::from_millis(5));
+        cache.put("https://example.com/b", Some("BPlugin"), &response);
+        assert!(cache.get("https://example.com/a").unwrap().bytes().unwrap().len() == 1000);
+        let stats = cache.stats();
+        assert!(stats.entries == 2);
+
+        // Freshness.
+        let policy = CachePolicy::default();
+        assert!(policy.is_fresh(&response));
+        assert!(policy.is_fresh(&CachedResponse::new(vec![], false, 404)));
+        let policy = CachePolicy { not_found_ttl: Duration::ZERO, ..policy };
+        assert!(policy.is_fresh(&response));
+        assert!(!policy.is_fresh(&CachedResponse::new(vec![], false, 404)));
+
+        // Least recently used first. Reading a doesn't count as a use yet,
+        // see TOUCH_INTERVAL_MS.
+        thread::sleep(Duration::from_millis(5));
+        cache.put("https://example.com/c", None, &response);
+        cache.set_max_size(stats.size);
+        assert!(cache.get("https://example.com/a").is_none());
+        assert!(cache.get("https://example.com/b").is_some());
+        assert!(cache.get("https://example.com/c").is_some());
+
+        cache.purge(Some("BPlugin"));
+        assert!(cache.get("https://example.com/b").is_none());
+        assert!(cache.stats().entries == 1);
+        cache.purge(None);
+        assert!(cache.stats().entries == 0);
+    }
+
+    #[test]
+    fn compact_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(dir.path().to_str().unwrap());
+        let index_size = || WalkDir::new(dir.path().join(INDEX_DIR)).into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum::<u64>();
+        for i in 0..10 {
+            cache.put("https://example.com/a", None, &CachedResponse::new(vec![i; 100], false, 200));
+            cache.put("https://example.com/b", None, &CachedResponse::new(vec![i + 10; 100], false, 200));
+        }
+        let before = index_size();
+        cache.gc();
+        assert!(index_size() < before / 5);
+        assert!(cache.get("https://example.com/a").unwrap().bytes().unwrap() == vec![9; 100]);
+        assert!(cache.stats().entries == 2);
+
+        // And the content they replaced.
+        let content_size = || WalkDir::new(dir.path().join("content-v2")).into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum::<u64>();
+        assert!(content_size() == cache.stats().size);
+
+        // Removed keys are dropped from the index.
+        cache.purge(None);
+        cache.gc();
+        assert!(index_size() == 0);
+        assert!(cache.get("https://example.com/b").is_none());
+    }
+}
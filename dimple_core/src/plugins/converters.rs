@@ -2,7 +2,7 @@ use musicbrainz_rs::entity::relations::RelationContent;
 
 use crate::{
     librarian::{ArtistMetadata, ReleaseMetadata, TrackMetadata},
-    model::{Artist, Link, Release, Track},
+    model::{artist_ref::is_featuring_join_phrase, Alias, Artist, ArtistRole, Link, Medium, Release, ReleaseGroup, Track},
 };
 
 // Note that in the converters below ..Default should never be used. If a Default
@@ -35,6 +35,7 @@ impl From<ArtistConverter> for ArtistMetadata {
                 key: None,
                 musicbrainz_id: Some(value.0.id.clone()),
                 name: none_if_empty(value.0.name),
+                sort_name: none_if_empty(value.0.sort_name),
                 summary: None,
                 ..Default::default()
             },
@@ -64,11 +65,68 @@ impl From<ArtistConverter> for ArtistMetadata {
                     url: s,
                 })
                 .collect(),
+            aliases: aliases(value.0.aliases),
             ..Default::default()
         }
     }
 }
 
+pub struct ArtistCreditConverter(musicbrainz_rs::entity::artist_credit::ArtistCredit);
+
+impl From<musicbrainz_rs::entity::artist_credit::ArtistCredit> for ArtistCreditConverter {
+    fn from(value: musicbrainz_rs::entity::artist_credit::ArtistCredit) -> Self {
+        ArtistCreditConverter(value)
+    }
+}
+
+impl From<ArtistCreditConverter> for ArtistMetadata {
+    fn from(value: ArtistCreditConverter) -> Self {
+        let artist: ArtistMetadata = ArtistConverter::from(value.0.artist).into();
+        let credited_name = none_if_empty(value.0.name)
+            .filter(|name| Some(name) != artist.artist.name.as_ref());
+        Self {
+            credited_name,
+            join_phrase: value.0.joinphrase.and_then(none_if_empty),
+            ..artist
+        }
+    }
+}
+
+/// Convert a MusicBrainz artist credit, marking the artists that follow a
+/// "feat." join phrase as Featured.
+fn artist_credits(credits: Option<Vec<musicbrainz_rs::entity::artist_credit::ArtistCredit>>) -> Vec<ArtistMetadata> {
+    let mut role = ArtistRole::Primary;
+    credits.into_iter().flatten()
+        .map(|credit| {
+            let artist: ArtistMetadata = ArtistCreditConverter::from(credit).into();
+            let artist = ArtistMetadata {
+                role: role.clone(),
+                ..artist
+            };
+            if artist.join_phrase.as_deref().is_some_and(is_featuring_join_phrase) {
+                role = ArtistRole::Featured;
+            }
+            artist
+        })
+        .collect()
+}
+
+/// The model and model_key are filled in by the librarian when the aliases
+/// are attached.
+fn aliases(aliases: Option<Vec<musicbrainz_rs::entity::alias::Alias>>) -> Vec<Alias> {
+    aliases.into_iter().flatten()
+        .filter_map(|alias| none_if_empty(alias.name))
+        .map(|name| Alias {
+            key: None,
+            model: String::new(),
+            model_key: String::new(),
+            name: Some(name),
+            musicbrainz_id: None,
+            source: Some("musicbrainz".to_string()),
+        })
+        .collect()
+}
+
 pub struct ReleaseConverter(musicbrainz_rs::entity::release::Release);
 
 impl From<musicbrainz_rs::entity::release::Release> for ReleaseConverter {
@@ -80,18 +138,45 @@ impl From<musicbrainz_rs::entity::release::Release> for ReleaseConverter {
 impl From<ReleaseConverter> for ReleaseMetadata {
     fn from(value: ReleaseConverter) -> Self {
         Self {
+            artists: artist_credits(value.0.artist_credit.clone()),
+            media: value.0.media.iter().flatten()
+                .map(|media| Medium {
+                    key: None,
+                    release_key: None,
+                    position: media.position,
+                    format: media.format.clone(),
+                    title: media.title.clone().and_then(none_if_empty),
+                    track_count: Some(media.track_count),
+                })
+                .collect(),
+            release_group: value.0.release_group.clone()
+                .map(|release_group| ReleaseGroup {
+                    key: None,
+                    title: none_if_empty(release_group.title),
+                    disambiguation: none_if_empty(release_group.disambiguation),
+                    primary_type: release_group.primary_type.map(|pt| format!("{:?}", pt)),
+                    first_release_date: release_group.first_release_date.map(|date| date.to_string()),
+                    musicbrainz_id: Some(release_group.id),
+                }),
             release: Release {
                 barcode: value.0.barcode,
                 country: value.0.country,
                 date: value.0.date.map(|f| f.to_string()),
+                original_date: value.0.release_group.as_ref()
+                    .and_then(|rg| rg.first_release_date.as_ref())
+                    .map(|date| date.to_string()),
+                label: value.0.label_info.iter().flatten()
+                    .find_map(|label_info| label_info.label.as_ref())
+                    .and_then(|label| none_if_empty(label.name.clone())),
+                catalog_number: value.0.label_info.iter().flatten()
+                    .find_map(|label_info| label_info.catalog_number.clone())
+                    .and_then(none_if_empty),
                 disambiguation: value.0.disambiguation,
                 key: None,
                 musicbrainz_id: Some(value.0.id.clone()),
                 title: none_if_empty(value.0.title),
                 packaging: value.0.packaging.map(|f| format!("{:?}", f)),
                 release_group_type: value.0.release_group.clone().and_then(|rg| rg.primary_type).and_then(|pt| Some(format!("{:?}", pt))),
-                // release_group: value.0.release_group
-                //     .map(|f| ReleaseGroup::from(ReleaseGroupConverter::from(f.to_owned()))).unwrap(),
                 status: value.0.status.map(|f| format!("{:?}", f)),
                 quality: value.0.quality.map(|f| format!("{:?}", f)),
                 summary: None,
@@ -126,6 +211,7 @@ impl From<ReleaseConverter> for ReleaseMetadata {
                     url: s,
                 })
                 .collect(),
+            aliases: aliases(value.0.aliases),
             ..Default::default()
         }
     }
@@ -142,6 +228,7 @@ impl From<musicbrainz_rs::entity::release::Track> for TrackConverter {
 impl From<TrackConverter> for TrackMetadata {
     fn from(value: TrackConverter) -> Self {
         Self {
+            artists: artist_credits(value.0.recording.artist_credit.clone()),
             track: Track {
                 // artist_credits: value.0.recording.artist_credit.iter().flatten()
                 //     .map(|artist_credit| ArtistCredit::from(ArtistCreditConverter::from(artist_credit.to_owned())))
@@ -200,3 +287,19 @@ impl From<GenreConverter> for crate::model::Genre {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::librarian::ReleaseMetadata;
+
+    use super::ReleaseConverter;
+
+    #[test]
+    fn release_label_info() {
+        let json = std::fs::read_to_string("tests/data/musicbrainz/release-label-info.json").unwrap();
+        let release: musicbrainz_rs::entity::release::Release = serde_json::from_str(&json).unwrap();
+        let metadata = ReleaseMetadata::from(ReleaseConverter::from(release));
+        assert!(metadata.release.label == Some("Parlophone".to_string()));
+        assert!(metadata.release.catalog_number == Some("CDR6663".to_string()));
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::RwLock;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{librarian::{TrackMetadata}, library::Library, model::{Model, Track}};
@@ -6,7 +8,7 @@ use super::{plugin::Plugin, plugins::Plugins};
 
 #[derive(Default)]
 pub struct ExamplePlugin {
-    config: ExamplePluginConfig,
+    config: RwLock<ExamplePluginConfig>,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
@@ -26,12 +28,13 @@ impl Plugin for ExamplePlugin {
         "ExamplePlugin".to_string()
     }
 
-    fn set_configuration(&mut self, config: &str) {
-        self.config = serde_json::from_str(config).unwrap();
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        *self.config.write().unwrap() = serde_json::from_str(config)?;
+        Ok(())
     }
 
     fn configuration(&self) -> String {
-        serde_json::to_string(&self.config).unwrap()
+        serde_json::to_string(&*self.config.read().unwrap()).unwrap()
     }
 
     fn track_metadata(&self, _host: &Plugins, _library: &Library, _artist: &Track) -> Result<Option<TrackMetadata>, anyhow::Error> {
@@ -1,12 +1,17 @@
+pub mod cache;
 pub mod example;
 pub mod plugin;
 pub mod plugins;
+pub mod process;
+pub mod runner;
+pub mod wasm;
 pub mod converters;
 
 pub mod lrclib;
 pub mod musicbrainz;
 pub mod wikidata;
 pub mod fanart_tv;
+pub mod acoustid;
 
 pub const USER_AGENT: &str = "Dimple/0.0.1 +https://github.com/vonnieda/dimple +jason@vonnieda.org";
 
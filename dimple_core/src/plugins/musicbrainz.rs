@@ -1,13 +1,13 @@
-use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+use std::{sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{librarian::{ArtistMetadata, ReleaseMetadata, SearchResults, TrackMetadata}, library::Library, model::{Artist, Model, Release, Track}, plugins::converters::ReleaseConverter};
+use crate::{librarian::{ArtistMetadata, Candidates, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Model, Release, Track}, plugins::converters::ReleaseConverter};
 
-use super::{converters::{ArtistConverter, TrackConverter}, plugin::Plugin, plugins::Plugins};
+use super::{cache::CachePolicy, converters::{ArtistConverter, TrackConverter}, plugin::Plugin, plugins::Plugins};
 
 pub struct MusicBrainzPlugin {
-    config: MusicBrainzPluginConfig,
+    config: RwLock<MusicBrainzPluginConfig>,
     rate_limit_lock: Arc<Mutex<Instant>>,
 }
 
@@ -29,12 +29,21 @@ impl Plugin for MusicBrainzPlugin {
         "MusicBrainz".to_string()
     }
 
-    fn set_configuration(&mut self, config: &str) {
-        self.config = serde_json::from_str(config).unwrap();
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        *self.config.write().unwrap() = serde_json::from_str(config)?;
+        Ok(())
     }
 
     fn configuration(&self) -> String {
-        serde_json::to_string(&self.config).unwrap()
+        serde_json::to_string(&*self.config.read().unwrap()).unwrap()
+    }
+
+    /// MusicBrainz is edited constantly, so check for changes daily.
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            not_found_ttl: Duration::from_secs(60 * 60),
+        }
     }
 
     fn artist_metadata(&self, host: &Plugins, _library: &Library, artist: &Artist) 
@@ -55,7 +64,7 @@ impl Plugin for MusicBrainzPlugin {
         -> Result<Option<ReleaseMetadata>, anyhow::Error> {
 
         if let Some(mbid) = release.musicbrainz_id.clone() {
-            let url = format!("https://musicbrainz.org/ws/2/release/{}?fmt=json&inc=aliases+annotation+artists+genres+media+ratings+recordings+release-groups+tags+url-rels", mbid);
+            let url = format!("https://musicbrainz.org/ws/2/release/{}?fmt=json&inc=aliases+annotation+artists+genres+labels+media+ratings+recordings+release-groups+tags+url-rels", mbid);
             self.enforce_rate_limit();
             let response = host.get(&url)?;
             let mb_release = response.json::<musicbrainz_rs::entity::release::Release>()?;
@@ -78,7 +87,7 @@ impl Plugin for MusicBrainzPlugin {
     }
     
     fn search(&self, host: &Plugins, library: &Library, query: &str) 
-        -> Result<crate::librarian::SearchResults, anyhow::Error> {
+        -> Result<Candidates, anyhow::Error> {
         
         // http://musicbrainz.org/ws/2/artist/?query=artist:klok
         let url = format!("https://musicbrainz.org/ws/2/artist/?fmt=json&query={}", query);
@@ -95,9 +104,9 @@ impl Plugin for MusicBrainzPlugin {
         let mb_results = response.json::<musicbrainz_rs::entity::search::SearchResult<musicbrainz_rs::entity::release::Release>>()?;
         let releases: Vec<ReleaseMetadata> = mb_results.entities.into_iter().map(|e| ReleaseConverter::from(e).into()).collect();
 
-        Ok(SearchResults {
-            artists: artists.into_iter().map(|e| e.artist).collect(),
-            releases: releases.into_iter().map(|e| e.release).collect(),
+        Ok(Candidates {
+            artists: artists.into_iter().map(Into::into).collect(),
+            releases: releases.into_iter().map(Into::into).collect(),
             ..Default::default()
         })
     }
@@ -1,8 +1,8 @@
 use image::DynamicImage;
 
-use crate::{librarian::{ArtistMetadata, ReleaseMetadata, SearchResults, TrackMetadata}, library::Library, model::{Artist, Dimage, Model, Release, Track}};
+use crate::{librarian::{ArtistMetadata, Candidates, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Dimage, Model, Release, Track}};
 
-use super::plugins::Plugins;
+use super::{cache::CachePolicy, plugins::Plugins};
 
 pub trait Plugin: Send + Sync {
     fn display_name(&self) -> String { 
@@ -15,8 +15,23 @@ pub trait Plugin: Send + Sync {
         "".to_string() 
     }
     
-    fn set_configuration(&mut self, _config: &str) { 
+    /// A short description of what the plugin is doing, for the settings
+    /// page. Plugins::status adds latency and error stats.
+    fn status(&self) -> String {
+        "".to_string()
+    }
+
+    /// How long responses to this plugin's requests through Plugins::get
+    /// are cached for.
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy::default()
+    }
 
+    /// Apply the configuration, in the same format as configuration(). Called
+    /// while the plugin is in use, so plugins keep their configuration
+    /// behind a lock.
+    fn set_configuration(&self, _config: &str) -> Result<(), anyhow::Error> { 
+        Ok(())
     }
 
     // fn metadata(&self, _host: &PluginHost, _library: &Library, _model: &dyn Model) 
@@ -40,8 +55,10 @@ pub trait Plugin: Send + Sync {
     //     Ok(None)
     // }    
 
-    fn search(&self, _host: &Plugins, _library: &Library, _query: &str) -> Result<SearchResults, anyhow::Error> {
-        Ok(SearchResults::default())
+    /// Find things matching the query. The results are kept out of the
+    /// library, see Candidate.
+    fn search(&self, _host: &Plugins, _library: &Library, _query: &str) -> Result<Candidates, anyhow::Error> {
+        Ok(Candidates::default())
     }
 
     fn image(&self, _host: &Plugins, _library: &Library, _model: &dyn Model) -> Result<Option<Dimage>, anyhow::Error> {
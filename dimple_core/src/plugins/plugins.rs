@@ -1,126 +1,646 @@
-use std::{num::NonZero, sync::{Arc, Mutex, RwLock}};
+use std::{collections::HashMap, fmt::Display, num::NonZero, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, RecvTimeoutError}, Arc, LazyLock, Mutex, RwLock}, time::{Duration, Instant}};
 
 use lru::LruCache;
-use reqwest::blocking::Client;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use reqwest::{blocking::Client, header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED}};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{librarian::{ArtistMetadata, ReleaseMetadata, SearchResults, TrackMetadata}, library::Library, merge::CrdtRules, model::{Artist, Dimage, Model, Release, Track}};
+use crate::{librarian::{ArtistMetadata, Candidates, ReleaseMetadata, TrackMetadata}, library::Library, merge::CrdtRules, model::{Artist, Dimage, Genre, Model, Playlist, Release, ReleaseGroup, Track}, network::Network};
 
-use super::{plugin::Plugin, USER_AGENT};
+use super::{cache::{now_ms, CachePolicy, CacheStats, HttpCache}, plugin::Plugin, USER_AGENT};
 
+/// The registry of plugins, keyed by type_name and ordered by priority,
+/// highest first. Results from all the enabled plugins are returned in that
+/// order. If opened with a settings path, the order, enabled state and
+/// configuration of each plugin are saved there whenever they change, and
+/// applied when the plugin is added.
+///
+/// Calls fan out to the plugins concurrently, on a pool of PLUGIN_THREADS
+/// threads shared by all calls, and return whatever came back by the
+/// deadline. Plugins still working after that are cancelled: their requests
+/// through get() fail from then on, and they can check is_cancelled() on
+/// the Plugins they were called with.
+///
+/// Plugins make HTTP requests with get(), which caches responses according
+/// to the calling plugin's CachePolicy, see HttpCache.
 #[derive(Clone)]
 pub struct Plugins {
-    plugins: Arc<RwLock<Vec<Arc<dyn Plugin>>>>,
-    cache_dir: String,
+    plugins: Arc<RwLock<Vec<RegisteredPlugin>>>,
+    cache: HttpCache,
+    /// While offline, get() only serves from the cache, stale or not.
+    network: Network,
+    settings_path: Option<PathBuf>,
+    /// Saved settings, including for plugins that haven't been added.
+    settings: Arc<RwLock<Vec<PluginSettings>>>,
+    stats: Arc<RwLock<HashMap<String, PluginStats>>>,
+    /// How long each fan-out waits for the plugins.
+    timeout: Arc<RwLock<Duration>>,
+    /// Set on the copies of Plugins handed to plugins during a call.
+    deadline: Option<Instant>,
+    cancellation: Cancellation,
+    /// The type_name of the plugin making the call, on those same copies.
+    caller: Option<String>,
+}
+
+/// Cancels the plugin calls made through a Plugins, see
+/// Plugins::with_cancellation. For example, a page can cancel its lookups
+/// when the user navigates away.
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Box<Cancellation>>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// A Cancellation that is also cancelled when this one is.
+    fn child(&self) -> Cancellation {
+        Cancellation {
+            cancelled: Default::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PluginStats {
+    /// Calls that finished before their deadline.
+    pub calls: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub total_latency: Duration,
+    pub last_latency: Option<Duration>,
+    pub last_error: Option<String>,
+}
+
+impl PluginStats {
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.calls > 0).then(|| self.total_latency / self.calls as u32)
+    }
+}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Plugin calls mostly wait on the network, so there are more threads than
+/// cores, but a bounded number, however many calls are in flight.
+const PLUGIN_THREADS: usize = 16;
+
+static PLUGIN_POOL: LazyLock<ThreadPool> = LazyLock::new(|| ThreadPoolBuilder::new()
+    .num_threads(PLUGIN_THREADS)
+    .thread_name(|i| format!("plugin-{}", i))
+    .build()
+    .unwrap());
+
+/// See Plugins::fan_out.
+struct FanOutOutcome {
+    finished: Vec<bool>,
+    closed: bool,
+}
+
+#[derive(Clone)]
+struct RegisteredPlugin {
+    plugin: Arc<dyn Plugin>,
+    enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginSettings {
+    pub type_name: String,
+    pub display_name: String,
+    pub enabled: bool,
+    /// The plugin's configuration, see Plugin::configuration.
+    pub config: String,
 }
 
 impl Default for Plugins {
     fn default() -> Self {
-        Self { plugins: Default::default(), cache_dir: Default::default() }
+        Self { 
+            plugins: Default::default(), 
+            cache: HttpCache::new(""),
+            network: Default::default(),
+            settings_path: None,
+            settings: Default::default(),
+            stats: Default::default(),
+            timeout: Arc::new(RwLock::new(DEFAULT_TIMEOUT)),
+            deadline: None,
+            cancellation: Default::default(),
+            caller: None,
+        }
     }
 }
 
 impl Plugins {
     pub fn new(cache_dir: &str) -> Self {
         Self {
-            plugins: Arc::new(RwLock::new(Vec::new())),
-            cache_dir: cache_dir.to_string(),
+            cache: HttpCache::new(cache_dir),
+            ..Default::default()
+        }
+    }
+
+    /// Plugins with settings loaded from and saved to the JSON file at
+    /// settings_path. A missing or unreadable file is treated as empty.
+    pub fn open(cache_dir: &str, settings_path: &Path) -> Self {
+        let settings = std::fs::read(settings_path).ok()
+            .and_then(|bytes| serde_json::from_slice::<Vec<PluginSettings>>(&bytes)
+                .inspect_err(|e| log::error!("Invalid plugin settings {:?}: {}", settings_path, e))
+                .ok())
+            .unwrap_or_default();
+        Self {
+            cache: HttpCache::new(cache_dir),
+            settings_path: Some(settings_path.to_path_buf()),
+            settings: Arc::new(RwLock::new(settings)),
+            ..Default::default()
         }
     }
 
+    /// Add the plugin, or replace the one with the same type_name. Saved
+    /// settings for it are applied, and it's placed in its saved position.
+    /// New plugins are enabled and go last.
     pub fn add_plugin(&self, plugin: Arc<dyn Plugin>) {
-        self.plugins.write().unwrap().push(plugin);
+        let type_name = plugin.type_name();
+        let saved = {
+            let settings = self.settings.read().unwrap();
+            settings.iter()
+                .position(|settings| settings.type_name == type_name)
+                .map(|i| (i, settings[i].clone()))
+        };
+        let mut enabled = true;
+        if let Some((_, settings)) = &saved {
+            enabled = settings.enabled;
+            if !settings.config.is_empty() {
+                if let Err(e) = plugin.set_configuration(&settings.config) {
+                    log::error!("Invalid configuration for {}: {}", type_name, e);
+                }
+            }
+        }
+        {
+            let mut plugins = self.plugins.write().unwrap();
+            plugins.retain(|registered| registered.plugin.type_name() != type_name);
+            // After the plugins that were saved before this one.
+            let index = match saved {
+                Some((saved_index, _)) => {
+                    let settings = self.settings.read().unwrap();
+                    plugins.iter()
+                        .take_while(|registered| settings.iter()
+                            .position(|s| s.type_name == registered.plugin.type_name())
+                            .is_some_and(|i| i < saved_index))
+                        .count()
+                },
+                None => plugins.len(),
+            };
+            plugins.insert(index, RegisteredPlugin { plugin, enabled });
+        }
     }
 
-    pub fn artist_metadata(&self, library: &Library, artist: &Artist) -> Vec<ArtistMetadata> {
-        let mut results = vec![];
-        for plugin in self.plugins.read().unwrap().iter() {
-            if let Ok(Some(metadata)) = plugin.artist_metadata(self, library, artist) {
-                results.push(metadata);
+    pub fn remove_plugin(&self, type_name: &str) {
+        self.plugins.write().unwrap()
+            .retain(|registered| registered.plugin.type_name() != type_name);
+        self.settings.write().unwrap()
+            .retain(|settings| settings.type_name != type_name);
+        self.save_settings();
+    }
+
+    /// The plugin with the type_name, enabled or not.
+    pub fn plugin(&self, type_name: &str) -> Option<Arc<dyn Plugin>> {
+        self.plugins.read().unwrap().iter()
+            .find(|registered| registered.plugin.type_name() == type_name)
+            .map(|registered| registered.plugin.clone())
+    }
+
+    /// The settings of every plugin, in priority order.
+    pub fn list(&self) -> Vec<PluginSettings> {
+        self.plugins.read().unwrap().iter()
+            .map(|registered| PluginSettings {
+                type_name: registered.plugin.type_name(),
+                display_name: registered.plugin.display_name(),
+                enabled: registered.enabled,
+                config: registered.plugin.configuration(),
+            })
+            .collect()
+    }
+
+    pub fn set_enabled(&self, type_name: &str, enabled: bool) {
+        for registered in self.plugins.write().unwrap().iter_mut() {
+            if registered.plugin.type_name() == type_name {
+                registered.enabled = enabled;
             }
         }
-        results
+        self.save_settings();
     }
 
-    pub fn image(&self, library: &Library, model: &dyn Model) -> Vec<Dimage> {
-        let mut results = vec![];
-        for plugin in self.plugins.read().unwrap().iter() {
-            if let Ok(Some(image)) = plugin.image(self, library, model) {
-                results.push(image);
+    /// Apply the configuration to the plugin while it's running, and save
+    /// it if the plugin accepts it.
+    pub fn set_configuration(&self, type_name: &str, config: &str) -> Result<(), anyhow::Error> {
+        let plugin = self.plugin(type_name)
+            .ok_or_else(|| anyhow::anyhow!("No plugin {}", type_name))?;
+        plugin.set_configuration(config)?;
+        self.save_settings();
+        Ok(())
+    }
+
+    /// Move the plugin to index in the priority order, where 0 is first.
+    pub fn set_priority(&self, type_name: &str, index: usize) {
+        {
+            let mut plugins = self.plugins.write().unwrap();
+            if let Some(i) = plugins.iter().position(|registered| registered.plugin.type_name() == type_name) {
+                let registered = plugins.remove(i);
+                let index = index.min(plugins.len());
+                plugins.insert(index, registered);
             }
         }
-        results
+        self.save_settings();
     }
 
-    pub fn release_metadata(&self, library: &Library, release: &Release) -> Vec<ReleaseMetadata> {
-        let mut results = vec![];
-        for plugin in self.plugins.read().unwrap().iter() {
-            if let Ok(Some(metadata)) = plugin.release_metadata(self, library, release) {
-                results.push(metadata);
+    /// The enabled plugins, in priority order. Cloned, so that the
+    /// registry isn't locked while plugins are working.
+    fn enabled(&self) -> Vec<Arc<dyn Plugin>> {
+        self.plugins.read().unwrap().iter()
+            .filter(|registered| registered.enabled)
+            .map(|registered| registered.plugin.clone())
+            .collect()
+    }
+
+    /// Save the settings of the current plugins, and keep the saved
+    /// settings of any that weren't added, after them. Only called for
+    /// changes by the user, so that adding plugins at startup, in any
+    /// order, doesn't lose the saved order.
+    fn save_settings(&self) {
+        let current = self.list();
+        let mut settings = self.settings.write().unwrap();
+        let missing = settings.iter()
+            .filter(|saved| !current.iter().any(|s| s.type_name == saved.type_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        *settings = current.into_iter().chain(missing).collect();
+        if let Some(path) = &self.settings_path {
+            let json = serde_json::to_vec_pretty(&*settings).unwrap();
+            if let Err(e) = std::fs::write(path, json) {
+                log::error!("Error saving plugin settings {:?}: {}", path, e);
             }
         }
-        results
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    /// A Plugins that shares this one's plugins and settings, but whose
+    /// calls are cancelled along with the cancellation.
+    pub fn with_cancellation(&self, cancellation: &Cancellation) -> Plugins {
+        Plugins {
+            cancellation: cancellation.clone(),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// The time left before the deadline of the current call, if it has
+    /// one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// The Cancellation of the current call, for plugins to hand to work
+    /// that should stop along with the call. It isn't cancelled by the
+    /// deadline until the fan-out returns, so check is_cancelled() too.
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    pub fn stats(&self, type_name: &str) -> PluginStats {
+        self.stats.read().unwrap().get(type_name).cloned().unwrap_or_default()
+    }
+
+    /// The plugin's own status, followed by its stats, e.g. "Ready. 12
+    /// calls, 250ms average, 1 error: 503 Service Unavailable".
+    pub fn status(&self, type_name: &str) -> String {
+        let Some(plugin) = self.plugin(type_name) else {
+            return String::new()
+        };
+        let stats = self.stats(type_name);
+        let mut parts = vec![];
+        let status = plugin.status();
+        if !status.is_empty() {
+            parts.push(status.trim_end_matches('.').to_string());
+        }
+        if stats.calls > 0 {
+            parts.push(format!("{} calls, {}ms average",
+                stats.calls, stats.average_latency().unwrap_or_default().as_millis()));
+        }
+        if stats.timeouts > 0 {
+            parts.push(format!("{} timed out", stats.timeouts));
+        }
+        if stats.errors > 0 {
+            parts.push(format!("{} errors, last: {}", stats.errors, stats.last_error.unwrap_or_default()));
+        }
+        parts.join(". ")
+    }
+
+    pub fn artist_metadata(&self, library: &Library, artist: &Artist) -> Vec<ArtistMetadata> {
+        let (library, artist) = (library.clone(), artist.clone());
+        self.fan_out(move |plugin, host| plugin.artist_metadata(host, &library, &artist))
+    }
+
+    pub fn image(&self, library: &Library, model: &dyn Model) -> Vec<Dimage> {
+        let Some(model) = owned_model(model) else {
+            log::warn!("No plugin images for {}", model.type_name());
+            return vec![]
+        };
+        let library = library.clone();
+        self.fan_out(move |plugin, host| plugin.image(host, &library, model.as_ref()))
+    }
+
+    pub fn release_metadata(&self, library: &Library, release: &Release) -> Vec<ReleaseMetadata> {
+        let (library, release) = (library.clone(), release.clone());
+        self.fan_out(move |plugin, host| plugin.release_metadata(host, &library, &release))
     }
 
     pub fn track_metadata(&self, library: &Library, track: &Track) -> Vec<TrackMetadata> {
-        let mut results = vec![];
-        for plugin in self.plugins.read().unwrap().iter() {
-            if let Ok(Some(metadata)) = plugin.track_metadata(self, library, track) {
-                results.push(metadata);
-            }
+        let (library, track) = (library.clone(), track.clone());
+        self.fan_out(move |plugin, host| plugin.track_metadata(host, &library, &track))
+    }
+
+    pub fn search(&self, library: &Library, query: &str) -> Candidates {
+        let (library, query) = (library.clone(), query.to_string());
+        let mut results = Candidates::default();
+        let found = self.fan_out(move |plugin, host| plugin.search(host, &library, &query)
+            .map(|candidates| Some((plugin.type_name(), candidates))));
+        for (type_name, candidates) in found {
+            results.extend(&type_name, candidates);
         }
         results
     }
 
-    pub fn search(&self, library: &Library, query: &str) -> Vec<SearchResults> {
+    /// Call every enabled plugin at once, and return the results that come
+    /// back by the deadline, in priority order. Errors are logged and
+    /// recorded in the plugin's stats.
+    fn fan_out<T: Send + 'static>(&self, 
+        call: impl Fn(&dyn Plugin, &Plugins) -> Result<Option<T>, anyhow::Error> + Send + Sync + 'static) -> Vec<T> {
+
+        if self.cancellation.is_cancelled() {
+            return vec![]
+        }
+        let plugins = self.enabled();
+        let cancellation = self.cancellation.child();
+        let deadline = Instant::now() + *self.timeout.read().unwrap();
+        let deadline = self.deadline.map_or(deadline, |d| d.min(deadline));
+        let host = Plugins {
+            deadline: Some(deadline),
+            cancellation: cancellation.clone(),
+            ..self.clone()
+        };
+        let call = Arc::new(call);
+        // Which calls finished, and whether the fan-out is over. A call
+        // counts as finished or timed out depending on which side gets
+        // here first, so it's never counted as both.
+        let outcome = Arc::new(Mutex::new(FanOutOutcome {
+            finished: vec![false; plugins.len()],
+            closed: false,
+        }));
+        let (tx, rx) = channel();
+        for (i, plugin) in plugins.iter().cloned().enumerate() {
+            let (call, tx, outcome) = (call.clone(), tx.clone(), outcome.clone());
+            let host = Plugins {
+                caller: Some(plugin.type_name()),
+                ..host.clone()
+            };
+            PLUGIN_POOL.spawn(move || {
+                // Calls that waited in the queue past the deadline don't
+                // start at all.
+                if host.is_cancelled() {
+                    return
+                }
+                let start = Instant::now();
+                let result = call(plugin.as_ref(), &host);
+                let mut outcome = outcome.lock().unwrap();
+                if outcome.closed {
+                    return
+                }
+                outcome.finished[i] = true;
+                host.record(&plugin.type_name(), start.elapsed(), result.as_ref().err());
+                let _ = tx.send((i, result));
+            });
+        }
+        drop(tx);
+
+        let mut pending = plugins.len();
         let mut results = vec![];
-        for plugin in self.plugins.read().unwrap().iter() {
-            if let Ok(sr) = plugin.search(self, library, query) {
-                results.push(sr);
+        while pending > 0 && !self.cancellation.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                break
+            }
+            // Wake up now and then to notice cancellation.
+            match rx.recv_timeout((deadline - now).min(Duration::from_millis(100))) {
+                Ok((i, result)) => {
+                    pending -= 1;
+                    if let Ok(Some(result)) = result {
+                        results.push((i, result));
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
             }
         }
-        results
+        cancellation.cancel();
+        {
+            let mut outcome = outcome.lock().unwrap();
+            outcome.closed = true;
+            if !self.cancellation.is_cancelled() {
+                let timed_out = plugins.iter().zip(&outcome.finished)
+                    .filter(|(_, finished)| !**finished);
+                for (plugin, _) in timed_out {
+                    let type_name = plugin.type_name();
+                    log::warn!("{} timed out", type_name);
+                    self.stats.write().unwrap().entry(type_name).or_default().timeouts += 1;
+                }
+            }
+        }
+        // Calls that finished after the loop gave up, but before closing,
+        // were counted, so their results are returned too.
+        for (i, result) in rx.try_iter() {
+            if let Ok(Some(result)) = result {
+                results.push((i, result));
+            }
+        }
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    fn record(&self, type_name: &str, latency: Duration, error: Option<&anyhow::Error>) {
+        let mut stats = self.stats.write().unwrap();
+        let stats = stats.entry(type_name.to_string()).or_default();
+        stats.calls += 1;
+        stats.total_latency += latency;
+        stats.last_latency = Some(latency);
+        if let Some(error) = error {
+            log::warn!("{} failed: {}", type_name, error);
+            stats.errors += 1;
+            stats.last_error = Some(error.to_string());
+        }
+    }
+
+    /// An HTTP client for a request to url, other than a GET, which gives
+    /// up at the deadline of the current call. Fails while offline, and
+    /// otherwise counts the request, see Network::request. Use get() where
+    /// possible, which caches.
+    pub fn client(&self, url: &str) -> Result<Client, anyhow::Error> {
+        self.network.request(url)?;
+        self.build_client()
     }
 
-    pub fn client(&self) -> Result<Client, anyhow::Error> {
-        Ok(Client::builder()
-            .user_agent(USER_AGENT)
-            .build()?)
+    fn build_client(&self) -> Result<Client, anyhow::Error> {
+        let mut builder = Client::builder().user_agent(USER_AGENT);
+        if let Some(remaining) = self.remaining() {
+            builder = builder.timeout(remaining);
+        }
+        Ok(builder.build()?)
     }
 
+    /// The response from the cache if it's fresh, or from the server.
+    /// Stale responses are revalidated, and served if the server can't be
+    /// reached or answers with a 5xx. Successful and 404 responses are
+    /// cached.
     pub fn get(&self, url: &str) -> Result<CachedResponse, anyhow::Error> {
-        if let Some(mut cached) = self.cache_get(url) {
-            log::info!("CACHED  [{:?}] {:?} {}", cached.status, cached.response.len(), url);
-            cached.cached = true;
-            return Ok(cached)
+        let counters = &self.cache.counters;
+        let cached = self.cache.get(url);
+        if let Some(cached) = &cached {
+            if self.cache_policy().is_fresh(cached) || self.is_offline() {
+                log::info!("CACHED  [{:?}] {:?} {}", cached.status, cached.response.len(), url);
+                counters.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(CachedResponse { cached: true, ..cached.clone() })
+            }
+        }
+        if self.is_cancelled() {
+            return Err(anyhow::anyhow!("Cancelled: {}", url))
         }
-        let client = self.client()?;
-        let response = client.get(url).send()?;
+        self.network.request(url)?;
+        let stale = |cached: CachedResponse, reason: &dyn Display| -> Result<CachedResponse, anyhow::Error> {
+            log::warn!("STALE   [{:?}] {:?} {}: {}", cached.status, cached.response.len(), url, reason);
+            counters.stale.fetch_add(1, Ordering::Relaxed);
+            Ok(CachedResponse { cached: true, ..cached })
+        };
+        match (self.fetch(url, cached.as_ref()), cached) {
+            (Ok(response), Some(cached)) if response.status() >= 500 => stale(cached, &response.status()),
+            (Ok(response), _) => Ok(response),
+            (Err(e), Some(cached)) => stale(cached, &e),
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    fn fetch(&self, url: &str, stale: Option<&CachedResponse>) -> Result<CachedResponse, anyhow::Error> {
+        let mut request = self.build_client()?.get(url);
+        if let Some(etag) = stale.and_then(|stale| stale.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = stale.and_then(|stale| stale.last_modified.as_ref()) {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send()?;
         log::info!("FETCHED [{:?}] {:?} {}", 
             response.status().as_u16(), 
             response.content_length().unwrap_or_default(),
             url);
-        let success = response.status().is_success();
         let status = response.status().as_u16();
-        let bytes = response.bytes()?;
-        let cached = CachedResponse::new(bytes.to_vec(), false, status);
+        if let (304, Some(stale)) = (status, stale) {
+            let revalidated = CachedResponse {
+                fetched: now_ms(),
+                ..stale.clone()
+            };
+            self.cache_put(url, &revalidated);
+            self.cache.counters.revalidated.fetch_add(1, Ordering::Relaxed);
+            return Ok(CachedResponse { cached: true, ..revalidated })
+        }
+        let header = |name: HeaderName| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let success = response.status().is_success();
+        let fetched = CachedResponse {
+            etag,
+            last_modified,
+            ..CachedResponse::new(response.bytes()?.to_vec(), false, status)
+        };
         if success || status == 404 {
-            self.cache_put(url, &cached);
+            self.cache_put(url, &fetched);
         }
-        Ok(cached)
+        self.cache.counters.misses.fetch_add(1, Ordering::Relaxed);
+        Ok(fetched)
     }
 
+    /// The calling plugin's policy, or the default.
+    fn cache_policy(&self) -> CachePolicy {
+        self.caller.as_deref()
+            .and_then(|type_name| self.plugin(type_name))
+            .map(|plugin| plugin.cache_policy())
+            .unwrap_or_default()
+    }
+
+    /// The cached response for the URL, fresh or stale.
     pub fn cache_get(&self, url: &str) -> Option<CachedResponse> {
-        let bytes = cacache::read_sync(self.cache_dir.clone(), url).ok()?;
-        Some(serde_json::from_slice(&bytes).ok()?)
+        self.cache.get(url)
     }
 
     pub fn cache_put(&self, url: &str, response: &CachedResponse) {
-        let bytes = serde_json::to_vec(response).unwrap();
-        cacache::write_sync(self.cache_dir.clone(), url, &bytes).unwrap();
+        self.cache.put(url, self.caller.as_deref(), response);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Remove the plugin's responses from the cache, or all of them.
+    pub fn purge_cache(&self, type_name: Option<&str>) {
+        self.cache.purge(type_name);
+    }
+
+    pub fn set_cache_max_size(&self, max_size: u64) {
+        self.cache.set_max_size(max_size);
+    }
+
+    /// Share the network, and so the offline switch, with the Library.
+    pub fn set_network(&mut self, network: &Network) {
+        self.network = network.clone();
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    pub fn set_offline(&self, offline: bool) {
+        self.network.set_offline(offline);
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.network.is_offline()
     }
 }
 
+/// An owned copy of the model, so that it can be sent to plugin threads.
+fn owned_model(model: &dyn Model) -> Option<Arc<dyn Model + Sync>> {
+    let any = model.as_any();
+    let owned: Arc<dyn Model + Sync> = if let Some(m) = any.downcast_ref::<Artist>() { Arc::new(m.clone()) }
+        else if let Some(m) = any.downcast_ref::<Release>() { Arc::new(m.clone()) }
+        else if let Some(m) = any.downcast_ref::<Track>() { Arc::new(m.clone()) }
+        else if let Some(m) = any.downcast_ref::<Genre>() { Arc::new(m.clone()) }
+        else if let Some(m) = any.downcast_ref::<ReleaseGroup>() { Arc::new(m.clone()) }
+        else if let Some(m) = any.downcast_ref::<Playlist>() { Arc::new(m.clone()) }
+        else { return None };
+    Some(owned)
+}
+
 pub fn nempty(s: &String) -> Option<String> {
     if s.is_empty() {
         None
@@ -135,6 +655,15 @@ pub struct CachedResponse {
     response: Vec<u8>,
     cached: bool,
     status: u16,
+    /// Validators for revalidating a stale response.
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    /// When it was fetched or last revalidated, in unix milliseconds.
+    /// Responses cached before this was added are stale.
+    #[serde(default)]
+    fetched: u64,
 }
 
 impl CachedResponse {
@@ -143,6 +672,9 @@ impl CachedResponse {
             response,
             cached,
             status,
+            etag: None,
+            last_modified: None,
+            fetched: now_ms(),
         }
     }
 
@@ -150,6 +682,14 @@ impl CachedResponse {
         self.cached
     }
 
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+
     pub fn json<T: DeserializeOwned>(&self) -> Result<T, anyhow::Error> {
         Ok(serde_json::from_slice(&self.response)?)
     }    
@@ -161,14 +701,267 @@ impl CachedResponse {
 
 #[cfg(test)]
 mod tests { 
-    use std::sync::Arc;
+    use std::{io::{Read as _, Write as _}, net::TcpListener, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread, time::Duration};
 
     use crate::{
+        librarian::ArtistMetadata,
         library::Library,
+        network::OfflineError,
+        plugins::{cache::CachePolicy, plugin::Plugin},
         model::{Artist, ArtistRef, Track}, plugins::{example::ExamplePlugin, fanart_tv::FanartTvPlugin, lrclib::LrclibPlugin, musicbrainz::MusicBrainzPlugin, wikidata::WikidataPlugin},
     };
 
-    use super::Plugins;
+    use super::{CachedResponse, Cancellation, Plugins};
+
+    #[test]
+    fn registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plugins.json");
+        let plugins = Plugins::open("", &path);
+        plugins.add_plugin(Arc::new(ExamplePlugin::default()));
+        plugins.add_plugin(Arc::new(LrclibPlugin::default()));
+        plugins.add_plugin(Arc::new(MusicBrainzPlugin::default()));
+        plugins.set_priority("MusicBrainzPlugin", 0);
+        plugins.set_enabled("LrclibPlugin", false);
+        let config = r#"{"url":"https://example.com","username":"u","password":"p","use_tls":true}"#;
+        plugins.set_configuration("ExamplePlugin", config).unwrap();
+        assert!(plugins.set_configuration("ExamplePlugin", "nope").is_err());
+        assert!(plugins.set_configuration("NoSuchPlugin", "{}").is_err());
+        assert!(plugins.enabled().len() == 2);
+
+        // Settings apply to plugins as they're added, in any order.
+        let plugins = Plugins::open("", &path);
+        plugins.add_plugin(Arc::new(LrclibPlugin::default()));
+        plugins.add_plugin(Arc::new(ExamplePlugin::default()));
+        plugins.add_plugin(Arc::new(MusicBrainzPlugin::default()));
+        let list = plugins.list();
+        let type_names = list.iter().map(|p| p.type_name.as_str()).collect::<Vec<_>>();
+        assert!(type_names == vec!["MusicBrainzPlugin", "ExamplePlugin", "LrclibPlugin"]);
+        assert!(!list[2].enabled);
+        assert!(list[1].config == config);
+        assert!(plugins.plugin("ExamplePlugin").unwrap().configuration() == config);
+    }
+
+    struct TestPlugin {
+        name: &'static str,
+        /// If set, the call blocks until the test sends on the channel, and
+        /// then reports whether it was cancelled by then.
+        gate: Option<(Mutex<Receiver<()>>, Mutex<Sender<bool>>)>,
+        fail: bool,
+    }
+
+    impl TestPlugin {
+        fn new(name: &'static str, fail: bool) -> Self {
+            Self { name, gate: None, fail }
+        }
+    }
+
+    impl Plugin for TestPlugin {
+        fn type_name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn artist_metadata(&self, host: &Plugins, _library: &Library, artist: &Artist) -> Result<Option<ArtistMetadata>, anyhow::Error> {
+            if let Some((open, cancelled)) = &self.gate {
+                open.lock().unwrap().recv().unwrap();
+                cancelled.lock().unwrap().send(host.is_cancelled()).unwrap();
+            }
+            if host.is_cancelled() {
+                return Err(anyhow::anyhow!("cancelled"))
+            }
+            if self.fail {
+                return Err(anyhow::anyhow!("503 Service Unavailable"))
+            }
+            Ok(Some(ArtistMetadata {
+                artist: Artist {
+                    disambiguation: Some(self.name.to_string()),
+                    ..artist.clone()
+                },
+                ..Default::default()
+            }))
+        }
+    }
+
+    #[test]
+    fn fan_out() {
+        let library = Library::open_memory();
+        let artist = Artist::default();
+        let plugins = Plugins::default();
+        plugins.set_timeout(Duration::from_millis(500));
+        let (open, gate) = channel();
+        let (cancelled_tx, cancelled) = channel();
+        plugins.add_plugin(Arc::new(TestPlugin {
+            name: "blocked",
+            gate: Some((Mutex::new(gate), Mutex::new(cancelled_tx))),
+            fail: false,
+        }));
+        plugins.add_plugin(Arc::new(TestPlugin::new("second", false)));
+        plugins.add_plugin(Arc::new(TestPlugin::new("failing", true)));
+        plugins.add_plugin(Arc::new(TestPlugin::new("first", false)));
+        plugins.set_priority("first", 0);
+
+        // Concurrent, so the blocked plugin only holds things up until the
+        // deadline, and results are in priority order.
+        let results = plugins.artist_metadata(&library, &artist);
+        let names = results.iter().map(|m| m.artist.disambiguation.clone().unwrap()).collect::<Vec<_>>();
+        assert!(names == vec!["first", "second"]);
+
+        assert!(plugins.stats("blocked").timeouts == 1);
+        assert!(plugins.stats("blocked").calls == 0);
+        assert!(plugins.stats("second").calls == 1);
+        assert!(plugins.stats("second").timeouts == 0);
+        assert!(plugins.stats("failing").errors == 1);
+        assert!(plugins.status("failing").contains("503 Service Unavailable"));
+
+        // The blocked call sees that it was cancelled once it gets going
+        // again, and isn't counted as a call.
+        open.send(()).unwrap();
+        assert!(cancelled.recv().unwrap());
+        assert!(plugins.stats("blocked").calls == 0);
+
+        // Cancelled calls don't call the plugins at all.
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        assert!(plugins.with_cancellation(&cancellation).artist_metadata(&library, &artist).is_empty());
+        assert!(plugins.stats("first").calls == 1);
+        assert!(plugins.stats("blocked").timeouts == 1);
+    }
+
+    #[test]
+    fn offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = Library::open_memory();
+        let artist = library.save(&Artist {
+            musicbrainz_id: Some("6821bf3f-5d5b-4b0f-8fa4-79d2ab2d9219".to_string()),
+            ..Default::default()
+        });
+        let mut plugins = Plugins::new(dir.path().to_str().unwrap());
+        plugins.set_network(&library.network);
+        plugins.add_plugin(Arc::new(MusicBrainzPlugin::default()));
+        plugins.add_plugin(Arc::new(WikidataPlugin::default()));
+        plugins.add_plugin(Arc::new(FanartTvPlugin::default()));
+        plugins.cache_put("https://example.com/cached", &CachedResponse::new(vec![1, 2, 3], false, 200));
+        library.network.set_offline(true);
+        assert!(plugins.is_offline());
+
+        // Served from the cache, or not at all.
+        assert!(plugins.get("https://example.com/cached").unwrap().cached());
+        let e = plugins.get("https://example.com/missing").unwrap_err();
+        assert!(e.downcast_ref::<OfflineError>().is_some());
+        assert!(plugins.client("https://example.com/post").is_err());
+        assert!(plugins.artist_metadata(&library, &artist).is_empty());
+        assert!(plugins.image(&library, &artist).is_empty());
+        assert!(library.network.requests() == 0);
+
+        // Requests made with client() are counted too.
+        library.network.set_offline(false);
+        assert!(plugins.client("https://example.com/post").is_ok());
+        assert!(library.network.requests() == 1);
+    }
+
+    /// Serves the responses in order, one per connection, then stops
+    /// listening. The head of each request is sent back, lowercased.
+    fn serve(responses: Vec<&'static str>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let len = stream.read(&mut buf).unwrap();
+                    if len == 0 {
+                        break
+                    }
+                    request.extend_from_slice(&buf[..len]);
+                }
+                tx.send(String::from_utf8_lossy(&request).to_lowercase()).unwrap();
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            drop(listener);
+        });
+        (url, rx)
+    }
+
+    /// Its responses are always stale, except for 404s.
+    struct StalePlugin;
+
+    impl Plugin for StalePlugin {
+        fn type_name(&self) -> String {
+            "StalePlugin".to_string()
+        }
+
+        fn cache_policy(&self) -> CachePolicy {
+            CachePolicy {
+                ttl: Duration::ZERO,
+                not_found_ttl: Duration::from_secs(60 * 60),
+            }
+        }
+    }
+
+    #[test]
+    fn get() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, requests) = serve(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nContent-Length: 3\r\nConnection: close\r\n\r\none",
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nContent-Length: 3\r\nConnection: close\r\n\r\ntwo",
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndown",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let plugins = Plugins::new(dir.path().to_str().unwrap());
+        plugins.add_plugin(Arc::new(StalePlugin));
+        // As if called from the plugin.
+        let plugins = Plugins {
+            caller: Some("StalePlugin".to_string()),
+            ..plugins
+        };
+        let (a, b, missing) = (format!("{}/a", url), format!("{}/b", url), format!("{}/missing", url));
+
+        let response = plugins.get(&a).unwrap();
+        assert!(!response.cached() && response.bytes().unwrap() == b"one");
+        requests.recv().unwrap();
+
+        // Revalidated with the ETag and Last-Modified.
+        let response = plugins.get(&a).unwrap();
+        assert!(response.cached() && response.bytes().unwrap() == b"one");
+        let request = requests.recv().unwrap();
+        assert!(request.contains("if-none-match: \"v1\""));
+        assert!(request.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"));
+
+        // Or with only Last-Modified.
+        assert!(plugins.get(&b).unwrap().bytes().unwrap() == b"two");
+        requests.recv().unwrap();
+        assert!(plugins.get(&b).unwrap().cached());
+        let request = requests.recv().unwrap();
+        assert!(request.contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"));
+        assert!(!request.contains("if-none-match"));
+
+        // Served stale when the server fails.
+        let response = plugins.get(&a).unwrap();
+        assert!(response.cached() && response.bytes().unwrap() == b"one");
+        requests.recv().unwrap();
+
+        // 404s are cached, for not_found_ttl.
+        assert!(plugins.get(&missing).unwrap().status() == 404);
+        requests.recv().unwrap();
+        let response = plugins.get(&missing).unwrap();
+        assert!(response.cached() && response.status() == 404);
+
+        // And served stale when the server can't be reached.
+        assert!(requests.recv().is_err());
+        let response = plugins.get(&a).unwrap();
+        assert!(response.cached() && response.bytes().unwrap() == b"one");
+        assert!(plugins.get(&format!("{}/c", url)).is_err());
+
+        let stats = plugins.cache_stats();
+        assert!(stats.hits == 1);
+        assert!(stats.revalidated == 2);
+        assert!(stats.stale == 2);
+    }
 
     #[test]
     fn it_works() {
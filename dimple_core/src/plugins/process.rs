@@ -0,0 +1,456 @@
+use std::{io::{BufRead as _, BufReader, Write as _}, path::{Path, PathBuf}, process::{Child, ChildStdin, Command, Stdio}, sync::{mpsc::{channel, Receiver, RecvTimeoutError}, Arc}, thread, time::{Duration, Instant}};
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{librarian::{ArtistMetadata, Candidates, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Dimage, DimageKind, Genre, Model, Release, Track}};
+
+use super::{plugin::Plugin, plugins::Plugins, runner::{Instance, Runner}, wasm::WasmPlugin};
+
+/// Plugins that run as separate programs, so that they can be written in
+/// any language and installed without rebuilding Dimple. Each one lives in
+/// its own directory with a plugin.json Manifest, see discover().
+///
+/// Dimple talks to the program with JSON-RPC 2.0 over its stdin and
+/// stdout, one message per line. Anything it writes to stderr is logged.
+/// The first request is `initialize`, `{"protocol": 1, "user_agent": "...",
+/// "config": "..."}`, which the plugin answers with `{"protocol": 1}`. After
+/// that requests are sent one at a time, for the methods the plugin lists
+/// in its capabilities:
+///
+/// - `artist_metadata`, `{"artist": Artist}`, returns ArtistMetadata.
+/// - `release_metadata`, `{"release": Release}`, returns ReleaseMetadata.
+/// - `track_metadata`, `{"track": Track}`, returns TrackMetadata.
+/// - `image`, `{"type_name": "Artist", "model": Artist}`, returns
+///   `{"url": "...", "kind": "MusicArtistThumb"}`, which Dimple downloads.
+/// - `search`, `{"query": "..."}`, returns Candidates.
+///
+/// Models are JSON objects with the same field names as in Dimple, and
+/// fields that are left out are empty. A null result means the plugin has
+/// nothing. While handling a request the plugin may call back to Dimple
+/// with `get`, `{"url": "..."}`, which is answered with `{"status": 200,
+/// "body": "..."}` through Plugins::get, so plugins share the HTTP cache.
+/// It may also send a `log` notification, `{"level": "info", "message":
+/// "..."}`.
+///
+/// A plugin that exits, or doesn't answer within its timeout, is killed
+/// and restarted on a later call, waiting longer after each failure, so a
+/// broken plugin can't take the rest of Dimple down with it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The methods a plugin can list in its capabilities.
+pub const CAPABILITIES: [&str; 5] = ["artist_metadata", "release_metadata", "track_metadata", "image", "search"];
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Describes a plugin, read from plugin.json in the plugin's directory.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub type_name: String,
+    pub display_name: String,
+    pub version: String,
+    /// The protocol version the plugin speaks.
+    pub protocol: u32,
+    /// The program to run, relative to the plugin's directory or on the
+    /// PATH, e.g. "python3", and its arguments.
+    pub command: String,
+    pub args: Vec<String>,
+    pub capabilities: Vec<String>,
+    /// The WebAssembly module, relative to the plugin's directory. Plugins
+    /// with one run in a sandbox instead of as a program, see WasmPlugin.
+    pub wasm: String,
+    /// How long to wait for an answer before killing the plugin. Defaults
+    /// to 30 seconds.
+    pub timeout_ms: Option<u64>,
+}
+
+pub struct ProcessPlugin {
+    dir: PathBuf,
+    runner: Runner<Process>,
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    next_id: u64,
+    timeout: Duration,
+    /// The configuration the plugin was started with. It's restarted when
+    /// this changes.
+    config: String,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    id: Option<Value>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImageResult {
+    url: String,
+    kind: Option<DimageKind>,
+}
+
+/// The plugins in the subdirectories of dir, either programs or
+/// WebAssembly modules. Invalid plugins are logged and skipped.
+pub fn discover(dir: &Path) -> Vec<Arc<dyn Plugin>> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![]
+    };
+    let mut plugins = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("plugin.json"))
+        .filter(|path| path.exists())
+        .filter_map(|path| open(&path)
+            .inspect_err(|e| log::error!("Invalid plugin {:?}: {}", path, e))
+            .ok())
+        .collect::<Vec<_>>();
+    plugins.sort_by_key(|plugin| plugin.type_name());
+    plugins
+}
+
+fn open(manifest_path: &Path) -> Result<Arc<dyn Plugin>, anyhow::Error> {
+    let manifest = Manifest::open(manifest_path)?;
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    if manifest.wasm.is_empty() {
+        Ok(Arc::new(ProcessPlugin::new(manifest, dir)?))
+    }
+    else {
+        Ok(Arc::new(WasmPlugin::new(manifest, dir)?))
+    }
+}
+
+impl Manifest {
+    pub fn open(path: &Path) -> Result<Manifest, anyhow::Error> {
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(path)?)?;
+        if manifest.type_name.is_empty() {
+            return Err(anyhow!("type_name is required"))
+        }
+        if manifest.protocol != PROTOCOL_VERSION {
+            return Err(anyhow!("Protocol {} is not supported, only {}", manifest.protocol, PROTOCOL_VERSION))
+        }
+        if let Some(capability) = manifest.capabilities.iter().find(|c| !CAPABILITIES.contains(&c.as_str())) {
+            return Err(anyhow!("Unknown capability {}", capability))
+        }
+        Ok(manifest)
+    }
+
+    pub fn has_capability(&self, method: &str) -> bool {
+        self.capabilities.iter().any(|capability| capability == method)
+    }
+
+    pub fn display_name(&self) -> String {
+        if self.display_name.is_empty() {
+            return self.type_name.clone()
+        }
+        self.display_name.clone()
+    }
+}
+
+impl ProcessPlugin {
+    pub fn new(manifest: Manifest, dir: &Path) -> Result<ProcessPlugin, anyhow::Error> {
+        if manifest.command.is_empty() {
+            return Err(anyhow!("command is required"))
+        }
+        Ok(ProcessPlugin {
+            dir: dir.to_path_buf(),
+            runner: Runner::new(manifest, true),
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        self.runner.manifest()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.manifest().timeout_ms.map_or(DEFAULT_TIMEOUT, Duration::from_millis)
+    }
+
+    /// Send the request, starting the plugin first if needed.
+    fn call<T: DeserializeOwned>(&self, host: &Plugins, method: &str, params: Value) -> Result<Option<T>, anyhow::Error> {
+        self.runner.call(host, method, params, |config| self.start(config))
+    }
+
+    fn start(&self, config: &str) -> Result<Process, anyhow::Error> {
+        let manifest = self.manifest();
+        let program = self.dir.join(&manifest.command);
+        let program = if program.is_file() { program } else { PathBuf::from(&manifest.command) };
+        let mut child = Command::new(program)
+            .args(&manifest.args)
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (sender, lines) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break
+                }
+            }
+        });
+        let type_name = self.type_name();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                log::info!("{}: {}", type_name, line);
+            }
+        });
+
+        Ok(Process {
+            child,
+            stdin,
+            lines,
+            next_id: 0,
+            timeout: self.timeout(),
+            config: config.to_string(),
+        })
+    }
+}
+
+impl Instance for Process {
+    fn config(&self) -> &str {
+        &self.config
+    }
+
+    fn request(&mut self, host: &Plugins, method: &str, params: Value)
+        -> Result<Result<Value, anyhow::Error>, anyhow::Error> {
+
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))?;
+        let timeout_at = Instant::now() + self.timeout;
+        loop {
+            if Instant::now() >= timeout_at {
+                return Err(anyhow!("No answer to {} after {}ms", method, self.timeout.as_millis()))
+            }
+            // Give up on the answer without killing the plugin. It's ignored
+            // when it comes.
+            if host.is_cancelled() {
+                return Ok(Err(anyhow!("Cancelled: {}", method)))
+            }
+            let line = match self.lines.recv_timeout(Duration::from_millis(100)) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = self.child.try_wait().ok().flatten()
+                        .map_or("closed stdout".to_string(), |status| status.to_string());
+                    return Err(anyhow!("Plugin exited: {}", status))
+                },
+            };
+            if line.trim().is_empty() {
+                continue
+            }
+            let message: Message = serde_json::from_str(&line)
+                .map_err(|e| anyhow!("Invalid message {:?}: {}", line, e))?;
+            if let Some(method) = &message.method {
+                self.handle(host, message.id, method, &message.params)?;
+            }
+            else if message.id == Some(Value::from(id)) {
+                if let Some(error) = message.error {
+                    return Ok(Err(anyhow!("{} failed: {}", method, error.message)))
+                }
+                return Ok(Ok(message.result.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+impl Process {
+    /// Answer a request from the plugin.
+    fn handle(&mut self, host: &Plugins, id: Option<Value>, method: &str, params: &Value) -> Result<(), anyhow::Error> {
+        let result = match method {
+            "get" => params["url"].as_str()
+                .ok_or_else(|| anyhow!("get requires a url"))
+                .and_then(|url| get(host, url)),
+            "log" => {
+                let level = params["level"].as_str()
+                    .and_then(|level| level.parse().ok())
+                    .unwrap_or(log::Level::Info);
+                log::log!(level, "{}", params["message"].as_str().unwrap_or_default());
+                Ok(Value::Null)
+            },
+            _ => Err(anyhow!("Unknown method {}", method)),
+        };
+        // Notifications don't get an answer.
+        let Some(id) = id else {
+            return Ok(())
+        };
+        match result {
+            Ok(result) => self.send(&json!({"jsonrpc": "2.0", "id": id, "result": result})),
+            Err(e) => self.send(&json!({"jsonrpc": "2.0", "id": id,
+                "error": RpcError { code: -32000, message: e.to_string() }})),
+        }
+    }
+
+    fn send(&mut self, message: &Value) -> Result<(), anyhow::Error> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+}
+
+/// The answer to a plugin's `get`.
+pub(crate) fn get(host: &Plugins, url: &str) -> Result<Value, anyhow::Error> {
+    let response = host.get(url)?;
+    Ok(json!({
+        "status": response.status(),
+        "body": String::from_utf8_lossy(&response.bytes()?),
+    }))
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Plugin for ProcessPlugin {
+    fn display_name(&self) -> String {
+        self.manifest().display_name()
+    }
+
+    fn type_name(&self) -> String {
+        self.manifest().type_name.clone()
+    }
+
+    fn configuration(&self) -> String {
+        self.runner.configuration()
+    }
+
+    /// The plugin is restarted with the new configuration on the next call.
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        self.runner.set_configuration(config);
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        self.runner.status()
+    }
+
+    fn artist_metadata(&self, host: &Plugins, _library: &Library, artist: &Artist) -> Result<Option<ArtistMetadata>, anyhow::Error> {
+        self.call(host, "artist_metadata", json!({"artist": artist}))
+    }
+
+    fn release_metadata(&self, host: &Plugins, _library: &Library, release: &Release) -> Result<Option<ReleaseMetadata>, anyhow::Error> {
+        self.call(host, "release_metadata", json!({"release": release}))
+    }
+
+    fn track_metadata(&self, host: &Plugins, _library: &Library, track: &Track) -> Result<Option<TrackMetadata>, anyhow::Error> {
+        self.call(host, "track_metadata", json!({"track": track}))
+    }
+
+    fn search(&self, host: &Plugins, _library: &Library, query: &str) -> Result<Candidates, anyhow::Error> {
+        Ok(self.call(host, "search", json!({"query": query}))?.unwrap_or_default())
+    }
+
+    fn image(&self, host: &Plugins, _library: &Library, model: &dyn Model) -> Result<Option<Dimage>, anyhow::Error> {
+        let Some(params) = image_params(model) else {
+            return Ok(None)
+        };
+        match self.call(host, "image", params)? {
+            Some(result) => Ok(Some(download_image(host, result)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The params of an `image` request, for the models that have images.
+pub(crate) fn image_params(model: &dyn Model) -> Option<Value> {
+    let any = model.as_any();
+    let model_json = if let Some(m) = any.downcast_ref::<Artist>() { json!(m) }
+        else if let Some(m) = any.downcast_ref::<Release>() { json!(m) }
+        else if let Some(m) = any.downcast_ref::<Track>() { json!(m) }
+        else if let Some(m) = any.downcast_ref::<Genre>() { json!(m) }
+        else { return None };
+    Some(json!({"type_name": model.type_name(), "model": model_json}))
+}
+
+pub(crate) fn download_image(host: &Plugins, result: ImageResult) -> Result<Dimage, anyhow::Error> {
+    let bytes = host.get(&result.url)?.bytes()?;
+    let mut dimage = Dimage::new(&image::load_from_memory(&bytes)?);
+    dimage.kind = result.kind;
+    Ok(dimage)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{library::Library, model::{Artist, Release, Track}, plugins::plugins::Plugins};
+
+    use super::{discover, Manifest};
+
+    /// Answers by matching the method name in the request, echoing the id.
+    const SCRIPT: &str = r#"
+while read -r line; do
+    id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+    case "$line" in
+        *'"initialize"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"protocol\":1}}" ;;
+        *'"artist_metadata"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"artist\":{\"name\":\"Echo\"}}}" ;;
+        *'"release_metadata"'*) sleep 2 ;;
+        *'"track_metadata"'*) echo "crashing" >&2; exit 1 ;;
+    esac
+done
+"#;
+
+    #[test]
+    fn process_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin_dir = dir.path().join("echo");
+        std::fs::create_dir(&plugin_dir).unwrap();
+        std::fs::write(plugin_dir.join("echo.sh"), SCRIPT).unwrap();
+        std::fs::write(plugin_dir.join("plugin.json"), r#"{
+            "type_name": "EchoPlugin",
+            "protocol": 1,
+            "command": "sh",
+            "args": ["echo.sh"],
+            "capabilities": ["artist_metadata", "release_metadata", "track_metadata"],
+            "timeout_ms": 500
+        }"#).unwrap();
+        std::fs::create_dir(dir.path().join("broken")).unwrap();
+        std::fs::write(dir.path().join("broken/plugin.json"), r#"{"type_name": "Broken", "protocol": 99}"#).unwrap();
+
+        let plugins = discover(dir.path());
+        assert!(plugins.len() == 1);
+        let plugin = &plugins[0];
+        let host = Plugins::default();
+        let library = Library::open_memory();
+
+        let metadata = plugin.artist_metadata(&host, &library, &Artist::default()).unwrap().unwrap();
+        assert!(metadata.artist.name == Some("Echo".to_string()));
+        assert!(plugin.status() == "Running");
+        // Not in the capabilities.
+        assert!(plugin.search(&host, &library, "echo").unwrap().is_empty());
+
+        // Crashes and timeouts stop the plugin, and it's restarted later.
+        assert!(plugin.track_metadata(&host, &library, &Track::default()).is_err());
+        assert!(plugin.status().starts_with("Stopped"));
+        assert!(plugin.artist_metadata(&host, &library, &Artist::default()).is_err());
+        thread::sleep(Duration::from_millis(1100));
+        assert!(plugin.artist_metadata(&host, &library, &Artist::default()).unwrap().is_some());
+        assert!(plugin.release_metadata(&host, &library, &Release::default()).is_err());
+        assert!(plugin.status().starts_with("Stopped"));
+
+        assert!(Manifest::open(&dir.path().join("broken/plugin.json")).is_err());
+    }
+}
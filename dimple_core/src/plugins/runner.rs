@@ -0,0 +1,158 @@
+use std::{sync::{Mutex, RwLock}, time::{Duration, Instant}};
+
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::{plugins::Plugins, process::{Manifest, PROTOCOL_VERSION}, USER_AGENT};
+
+/// A started plugin, either a running program or an instantiated module,
+/// that answers the requests described in process.rs.
+pub(crate) trait Instance {
+    /// The configuration it was started with.
+    fn config(&self) -> &str;
+
+    /// The outer error means the instance is broken and must be thrown
+    /// away: it exited, trapped, stopped answering or broke the protocol.
+    /// The inner error is an error returned by the plugin.
+    fn request(&mut self, host: &Plugins, method: &str, params: Value)
+        -> Result<Result<Value, anyhow::Error>, anyhow::Error>;
+}
+
+/// Runs the Instance of a ProcessPlugin or WasmPlugin: starts it on the
+/// first call that needs it, starts it again when the configuration
+/// changes or it breaks, and keeps the plugin's status up to date.
+pub(crate) struct Runner<I> {
+    manifest: Manifest,
+    config: RwLock<String>,
+    state: Mutex<State<I>>,
+    status: RwLock<String>,
+    /// Whether to wait longer before each restart after a failure, instead
+    /// of restarting on the next call.
+    backoff: bool,
+}
+
+struct State<I> {
+    instance: Option<I>,
+    failures: u32,
+    restart_at: Option<Instant>,
+}
+
+impl<I: Instance> Runner<I> {
+    pub fn new(manifest: Manifest, backoff: bool) -> Self {
+        Self {
+            manifest,
+            config: Default::default(),
+            state: Mutex::new(State {
+                instance: None,
+                failures: 0,
+                restart_at: None,
+            }),
+            status: RwLock::new("Not started".to_string()),
+            backoff,
+        }
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn configuration(&self) -> String {
+        self.config.read().unwrap().clone()
+    }
+
+    /// The instance is started again with the new configuration on the
+    /// next call.
+    pub fn set_configuration(&self, config: &str) {
+        *self.config.write().unwrap() = config.to_string();
+    }
+
+    pub fn status(&self) -> String {
+        self.status.read().unwrap().clone()
+    }
+
+    fn set_status(&self, status: &str) {
+        *self.status.write().unwrap() = status.to_string();
+    }
+
+    /// Send the request, starting an instance with start, and initializing
+    /// it, first if needed. Returns Ok(None) without asking if the plugin
+    /// doesn't have the capability.
+    pub fn call<T: DeserializeOwned>(&self, host: &Plugins, method: &str, params: Value,
+        start: impl FnOnce(&str) -> Result<I, anyhow::Error>) -> Result<Option<T>, anyhow::Error> {
+
+        if !self.manifest.has_capability(method) {
+            return Ok(None)
+        }
+        let mut state = self.state.lock().unwrap();
+        let config = self.config.read().unwrap().clone();
+        if state.instance.as_ref().is_some_and(|instance| instance.config() != config) {
+            state.instance = None;
+        }
+        if state.instance.is_none() {
+            if state.restart_at.is_some_and(|restart_at| Instant::now() < restart_at) {
+                return Err(anyhow!("{} is waiting to restart", self.manifest.type_name))
+            }
+            let started = start(&config).and_then(|mut instance| {
+                initialize(&mut instance, host, &config)?;
+                Ok(instance)
+            });
+            match started {
+                Ok(instance) => {
+                    state.instance = Some(instance);
+                    self.set_status("Running");
+                },
+                Err(e) => {
+                    self.failed(&mut state, &e);
+                    return Err(e)
+                },
+            }
+        }
+        let instance = state.instance.as_mut().unwrap();
+        match instance.request(host, method, params) {
+            Ok(Ok(result)) => {
+                state.failures = 0;
+                match result {
+                    Value::Null => Ok(None),
+                    result => Ok(Some(serde_json::from_value(result)?)),
+                }
+            },
+            Ok(Err(e)) => Err(e),
+            Err(e) => {
+                self.failed(&mut state, &e);
+                Err(e)
+            },
+        }
+    }
+
+    /// Throw the instance away. With backoff, wait longer before each
+    /// restart: 1s, 2s, 4s and so on, up to five minutes.
+    fn failed(&self, state: &mut State<I>, error: &anyhow::Error) {
+        state.instance = None;
+        log::warn!("{} stopped: {}", self.manifest.type_name, error);
+        if !self.backoff {
+            self.set_status(&format!("Stopped: {}", error));
+            return
+        }
+        state.failures += 1;
+        let backoff = Duration::from_secs((1 << (state.failures - 1).min(9)).min(300));
+        state.restart_at = Some(Instant::now() + backoff);
+        self.set_status(&format!("Stopped: {}. Restarting in {}s", error, backoff.as_secs()));
+    }
+}
+
+/// Send `initialize`, and check that the plugin speaks our protocol.
+fn initialize(instance: &mut impl Instance, host: &Plugins, config: &str) -> Result<(), anyhow::Error> {
+    let params = json!({
+        "protocol": PROTOCOL_VERSION,
+        "user_agent": USER_AGENT,
+        "config": config,
+    });
+    let result = instance.request(host, "initialize", params)?
+        .map_err(|e| anyhow!("initialize failed: {}", e))?;
+    let protocol = result["protocol"].as_u64();
+    if protocol != Some(PROTOCOL_VERSION as u64) {
+        return Err(anyhow!("Plugin speaks protocol {:?}, not {}", protocol, PROTOCOL_VERSION))
+    }
+    Ok(())
+}
@@ -1,10 +1,12 @@
+use std::sync::RwLock;
+
 use serde::{Deserialize, Serialize};
 
 use super::plugin::Plugin;
 
 #[derive(Default)]
 pub struct S3ApiSyncPlugin {
-    config: S3ApiSyncConfig,
+    config: RwLock<S3ApiSyncConfig>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Default)]
@@ -26,12 +28,13 @@ impl Plugin for S3ApiSyncPlugin {
         "S3ApiSyncPlugin".to_string()
     }
 
-    fn set_configuration(&mut self, config: &str) {
-        self.config = serde_json::from_str(config).unwrap();
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        *self.config.write().unwrap() = serde_json::from_str(config)?;
+        Ok(())
     }
 
     fn configuration(&self) -> String {
-        serde_json::to_string(&self.config).unwrap()
+        serde_json::to_string(&*self.config.read().unwrap()).unwrap()
     }
 
     fn status(&self) -> String {
@@ -0,0 +1,434 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use wasmi::{AsContext, AsContextMut, Caller, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::{librarian::{ArtistMetadata, Candidates, ReleaseMetadata, TrackMetadata}, library::Library, model::{Artist, Dimage, Model, Release, Track}};
+
+use super::{plugin::Plugin, plugins::Plugins, process::{self, Manifest}, runner::{Instance, Runner}};
+
+/// Plugins compiled to WebAssembly, run in a sandbox so that users can
+/// install them without trusting them the way they'd trust a program. The
+/// module can't touch the filesystem or the network: the only things it
+/// can import are the host functions below, and it's limited in how much
+/// memory it can use and how long it can run for each call: its fuel, and
+/// the deadline of the call, see FUEL_PER_MS.
+///
+/// Plugins are described by the same plugin.json Manifest as process
+/// plugins, with `wasm` naming the module, and the methods and JSON
+/// messages are the same too. The module exports:
+///
+/// - `memory`.
+/// - `dimple_alloc(len: i32) -> i32`, which allocates len bytes for the
+///   host to write a request into.
+/// - `dimple_call(method: i32, method_len: i32, params: i32, params_len: i32) -> i64`,
+///   which handles a request, starting with `initialize`, and returns
+///   `{"result": ...}` or `{"error": "..."}`.
+///
+/// And may import, from the "dimple" module:
+///
+/// - `get(url: i32, url_len: i32) -> i64`, which fetches the URL through
+///   Plugins::get, so that the cache and offline mode apply, and returns
+///   `{"result": {"status": 200, "body": "..."}}` or `{"error": "..."}`.
+/// - `log(level: i32, message: i32, message_len: i32)`, with levels 1
+///   (error) to 5 (trace).
+///
+/// Strings are UTF-8, passed as a pointer and length. Replies are packed
+/// into an i64 with the pointer in the high 32 bits and the length in the
+/// low 32 bits, and are read as soon as the call returns, so the module can
+/// reuse their memory on the next call.
+///
+/// A module that traps, or runs out of fuel, is thrown away and
+/// instantiated again on the next call.
+pub struct WasmPlugin {
+    engine: Engine,
+    module: Module,
+    linker: Linker<HostState>,
+    limits: Limits,
+    runner: Runner<Sandbox>,
+}
+
+/// Roughly how much fuel wasmi burns in a millisecond. A call is given no
+/// more fuel than it can burn before its deadline, because the fan-out
+/// can't interrupt it, and the plugin couldn't be called again until it
+/// ran out.
+const FUEL_PER_MS: u64 = 100_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Roughly the number of instructions each call may run, or fewer if
+    /// the deadline is closer.
+    pub fuel: u64,
+    /// The most memory the module may grow to, in bytes.
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            fuel: 1_000_000_000,
+            memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+struct HostState {
+    limits: StoreLimits,
+    /// The Plugins of the current call.
+    host: Plugins,
+}
+
+struct Sandbox {
+    store: Store<HostState>,
+    instance: wasmi::Instance,
+    fuel: u64,
+    /// The configuration it was initialized with. It's instantiated again
+    /// when this changes.
+    config: String,
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    result: Option<Value>,
+    error: Option<String>,
+}
+
+impl WasmPlugin {
+    pub fn new(manifest: Manifest, dir: &Path) -> Result<WasmPlugin, anyhow::Error> {
+        let wasm = std::fs::read(dir.join(&manifest.wasm))?;
+        Self::from_wasm(manifest, &wasm)
+    }
+
+    /// A plugin for the module, in the binary format.
+    pub fn from_wasm(manifest: Manifest, wasm: &[u8]) -> Result<WasmPlugin, anyhow::Error> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        for export in ["memory", "dimple_alloc", "dimple_call"] {
+            if module.get_export(export).is_none() {
+                return Err(anyhow!("The module doesn't export {}", export))
+            }
+        }
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("dimple", "get", host_get)?;
+        linker.func_wrap("dimple", "log", host_log)?;
+        Ok(WasmPlugin {
+            engine,
+            module,
+            linker,
+            limits: Limits::default(),
+            runner: Runner::new(manifest, false),
+        })
+    }
+
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self {
+            limits,
+            ..self
+        }
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        self.runner.manifest()
+    }
+
+    /// Send the request, instantiating the module first if needed.
+    fn call<T: DeserializeOwned>(&self, host: &Plugins, method: &str, params: Value) -> Result<Option<T>, anyhow::Error> {
+        self.runner.call(host, method, params, |config| self.instantiate(host, config))
+    }
+
+    fn instantiate(&self, host: &Plugins, config: &str) -> Result<Sandbox, anyhow::Error> {
+        let state = HostState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.memory)
+                .instances(1)
+                .trap_on_grow_failure(true)
+                .build(),
+            host: host.clone(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel).map_err(|e| anyhow!("{}", e))?;
+        let instance = self.linker.instantiate(&mut store, &self.module)?.start(&mut store)?;
+        Ok(Sandbox {
+            store,
+            instance,
+            fuel: self.limits.fuel,
+            config: config.to_string(),
+        })
+    }
+}
+
+impl Instance for Sandbox {
+    fn config(&self) -> &str {
+        &self.config
+    }
+
+    fn request(&mut self, host: &Plugins, method: &str, params: Value)
+        -> Result<Result<Value, anyhow::Error>, anyhow::Error> {
+
+        if host.is_cancelled() {
+            return Ok(Err(anyhow!("Cancelled: {}", method)))
+        }
+        let fuel = host.remaining()
+            .map_or(self.fuel, |remaining| self.fuel.min(remaining.as_millis() as u64 * FUEL_PER_MS));
+        self.store.data_mut().host = host.clone();
+        self.store.set_fuel(fuel).map_err(|e| anyhow!("{}", e))?;
+        let memory = self.instance.get_memory(&self.store, "memory")
+            .ok_or_else(|| anyhow!("No memory"))?;
+        let alloc = self.instance.get_typed_func::<i32, i32>(&self.store, "dimple_alloc")?;
+        let call = self.instance.get_typed_func::<(i32, i32, i32, i32), i64>(&self.store, "dimple_call")?;
+        let params = serde_json::to_vec(&params)?;
+        let method_ptr = write(&mut self.store, alloc, memory, method.as_bytes())?;
+        let params_ptr = write(&mut self.store, alloc, memory, &params)?;
+        let reply = call.call(&mut self.store,
+            (method_ptr, method.len() as i32, params_ptr, params.len() as i32))
+            .map_err(|e| if host.is_cancelled() {
+                anyhow!("{} ran past the deadline: {}", method, e)
+            } else {
+                e.into()
+            })?;
+        let reply: Reply = serde_json::from_slice(&read(&self.store, memory, reply)?)?;
+        if let Some(error) = reply.error {
+            return Ok(Err(anyhow!("{} failed: {}", method, error)))
+        }
+        Ok(Ok(reply.result.unwrap_or_default()))
+    }
+}
+
+/// Copy the bytes into memory allocated by the module, and return the
+/// pointer.
+fn write(mut ctx: impl AsContextMut, alloc: TypedFunc<i32, i32>, memory: Memory, bytes: &[u8]) -> Result<i32, anyhow::Error> {
+    let ptr = alloc.call(&mut ctx, bytes.len() as i32)?;
+    memory.write(&mut ctx, ptr as u32 as usize, bytes).map_err(|e| anyhow!("{}", e))?;
+    Ok(ptr)
+}
+
+/// Copy the bytes out of the module's memory, given a packed pointer and
+/// length.
+fn read(ctx: impl AsContext, memory: Memory, packed: i64) -> Result<Vec<u8>, anyhow::Error> {
+    let (ptr, len) = ((packed as u64 >> 32) as usize, packed as u32 as usize);
+    memory.data(&ctx).get(ptr..ptr + len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("Out of bounds"))
+}
+
+fn pack(ptr: i32, len: usize) -> i64 {
+    ((ptr as u32 as u64) << 32 | len as u64) as i64
+}
+
+fn exports(caller: &Caller<'_, HostState>) -> Result<(Memory, TypedFunc<i32, i32>), anyhow::Error> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow!("No memory"))?;
+    let alloc = caller.get_export("dimple_alloc").and_then(Extern::into_func)
+        .ok_or_else(|| anyhow!("No dimple_alloc"))?
+        .typed::<i32, i32>(caller)?;
+    Ok((memory, alloc))
+}
+
+fn host_get(mut caller: Caller<'_, HostState>, url: i32, url_len: i32) -> Result<i64, wasmi::Error> {
+    get(&mut caller, url, url_len).map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+fn get(caller: &mut Caller<'_, HostState>, url: i32, url_len: i32) -> Result<i64, anyhow::Error> {
+    let (memory, alloc) = exports(caller)?;
+    let url = String::from_utf8(read(&*caller, memory, pack(url, url_len as u32 as usize))?)?;
+    let host = caller.data().host.clone();
+    // Trap, so that the call stops as soon as it's cancelled.
+    if host.is_cancelled() {
+        return Err(anyhow!("Cancelled"))
+    }
+    let reply = match process::get(&host, &url) {
+        Ok(result) => json!({"result": result}),
+        Err(e) => json!({"error": e.to_string()}),
+    };
+    let reply = serde_json::to_vec(&reply)?;
+    let ptr = write(caller, alloc, memory, &reply)?;
+    Ok(pack(ptr, reply.len()))
+}
+
+fn host_log(caller: Caller<'_, HostState>, level: i32, message: i32, message_len: i32) -> Result<(), wasmi::Error> {
+    let (memory, _) = exports(&caller).map_err(|e| wasmi::Error::new(e.to_string()))?;
+    let message = read(&caller, memory, pack(message, message_len as u32 as usize))
+        .map_err(|e| wasmi::Error::new(e.to_string()))?;
+    let level = match level {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        4 => log::Level::Debug,
+        5 => log::Level::Trace,
+        _ => log::Level::Info,
+    };
+    log::log!(level, "{}", String::from_utf8_lossy(&message));
+    Ok(())
+}
+
+impl Plugin for WasmPlugin {
+    fn display_name(&self) -> String {
+        self.manifest().display_name()
+    }
+
+    fn type_name(&self) -> String {
+        self.manifest().type_name.clone()
+    }
+
+    fn configuration(&self) -> String {
+        self.runner.configuration()
+    }
+
+    /// The module is instantiated again with the new configuration on the
+    /// next call.
+    fn set_configuration(&self, config: &str) -> Result<(), anyhow::Error> {
+        self.runner.set_configuration(config);
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        self.runner.status()
+    }
+
+    fn artist_metadata(&self, host: &Plugins, _library: &Library, artist: &Artist) -> Result<Option<ArtistMetadata>, anyhow::Error> {
+        self.call(host, "artist_metadata", json!({"artist": artist}))
+    }
+
+    fn release_metadata(&self, host: &Plugins, _library: &Library, release: &Release) -> Result<Option<ReleaseMetadata>, anyhow::Error> {
+        self.call(host, "release_metadata", json!({"release": release}))
+    }
+
+    fn track_metadata(&self, host: &Plugins, _library: &Library, track: &Track) -> Result<Option<TrackMetadata>, anyhow::Error> {
+        self.call(host, "track_metadata", json!({"track": track}))
+    }
+
+    fn search(&self, host: &Plugins, _library: &Library, query: &str) -> Result<Candidates, anyhow::Error> {
+        Ok(self.call(host, "search", json!({"query": query}))?.unwrap_or_default())
+    }
+
+    fn image(&self, host: &Plugins, _library: &Library, model: &dyn Model) -> Result<Option<Dimage>, anyhow::Error> {
+        let Some(params) = process::image_params(model) else {
+            return Ok(None)
+        };
+        match self.call(host, "image", params)? {
+            Some(result) => Ok(Some(process::download_image(host, result)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::{Duration, Instant}};
+
+    use crate::{library::Library, model::{Artist, Release, Track}, plugins::{plugin::Plugin, plugins::{CachedResponse, Plugins}, process::Manifest}};
+
+    use super::{Limits, WasmPlugin};
+
+    /// Echoes artist_metadata params back, fetches a URL for
+    /// track_metadata, loops forever for release_metadata and asks for too
+    /// much memory for search. Methods are told apart by their length.
+    const WAT: &str = r#"
+(module
+  (import "dimple" "get" (func $get (param i32 i32) (result i64)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+  (data (i32.const 0) "{\"result\":{\"protocol\":1}}")
+  (data (i32.const 64) "{\"error\":\"nope\"}")
+  (data (i32.const 128) "https://example.com/echo")
+  (data (i32.const 192) "{\"result\":")
+  (func $alloc (export "dimple_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.add (global.get $heap) (local.get $len)))
+    (local.get $ptr))
+  (func $pack (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len))))
+  (func (export "dimple_call") (param $method i32) (param $method_len i32)
+      (param $params i32) (param $params_len i32) (result i64)
+    (local $ptr i32)
+    (if (i32.eq (local.get $method_len) (i32.const 10))
+      (then (return (call $pack (i32.const 0) (i32.const 25)))))
+    (if (i32.eq (local.get $method_len) (i32.const 15))
+      (then
+        (local.set $ptr (call $alloc (i32.add (local.get $params_len) (i32.const 11))))
+        (memory.copy (local.get $ptr) (i32.const 192) (i32.const 10))
+        (memory.copy (i32.add (local.get $ptr) (i32.const 10)) (local.get $params) (local.get $params_len))
+        (i32.store8 (i32.add (i32.add (local.get $ptr) (i32.const 10)) (local.get $params_len)) (i32.const 125))
+        (return (call $pack (local.get $ptr) (i32.add (local.get $params_len) (i32.const 11))))))
+    (if (i32.eq (local.get $method_len) (i32.const 14))
+      (then (return (call $get (i32.const 128) (i32.const 24)))))
+    (if (i32.eq (local.get $method_len) (i32.const 16))
+      (then (loop $forever (br $forever))))
+    (if (i32.eq (local.get $method_len) (i32.const 6))
+      (then (drop (memory.grow (i32.const 2000)))))
+    (call $pack (i32.const 64) (i32.const 16))))
+"#;
+
+    #[test]
+    fn sandbox() {
+        let manifest = Manifest {
+            type_name: "EchoPlugin".to_string(),
+            protocol: 1,
+            capabilities: vec!["artist_metadata".to_string(), "release_metadata".to_string(),
+                "track_metadata".to_string(), "search".to_string()],
+            ..Default::default()
+        };
+        let wasm = wat::parse_str(WAT).unwrap();
+        let plugin = WasmPlugin::from_wasm(manifest, &wasm).unwrap().with_limits(Limits {
+            fuel: 1_000_000,
+            ..Default::default()
+        });
+        let cache_dir = tempfile::tempdir().unwrap();
+        let host = Plugins::new(cache_dir.path().to_str().unwrap());
+        host.cache_put("https://example.com/echo", &CachedResponse::new(b"echo".to_vec(), false, 200));
+        let library = Library::open_memory();
+
+        let artist = Artist {
+            name: Some("Echo".to_string()),
+            ..Default::default()
+        };
+        let metadata = plugin.artist_metadata(&host, &library, &artist).unwrap().unwrap();
+        assert!(metadata.artist == artist);
+        assert!(plugin.status() == "Running");
+        // HTTP through the host's cache.
+        assert!(plugin.track_metadata(&host, &library, &Track::default()).unwrap().is_some());
+
+        // Out of fuel, and out of memory.
+        assert!(plugin.release_metadata(&host, &library, &Release::default()).is_err());
+        assert!(plugin.status().starts_with("Stopped"));
+        assert!(plugin.artist_metadata(&host, &library, &artist).unwrap().is_some());
+        assert!(plugin.search(&host, &library, "echo").is_err());
+
+        assert!(WasmPlugin::from_wasm(Manifest::default(), &wat::parse_str("(module)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn deadline() {
+        let manifest = Manifest {
+            type_name: "LoopPlugin".to_string(),
+            protocol: 1,
+            capabilities: vec!["artist_metadata".to_string(), "release_metadata".to_string()],
+            ..Default::default()
+        };
+        let wasm = wat::parse_str(WAT).unwrap();
+        // Enough fuel to loop for minutes.
+        let plugin = Arc::new(WasmPlugin::from_wasm(manifest, &wasm).unwrap().with_limits(Limits {
+            fuel: 1_000_000_000_000,
+            ..Default::default()
+        }));
+        let cache_dir = tempfile::tempdir().unwrap();
+        let host = Plugins::new(cache_dir.path().to_str().unwrap());
+        host.add_plugin(plugin.clone());
+        host.set_timeout(Duration::from_millis(50));
+        let library = Library::open_memory();
+
+        let start = Instant::now();
+        assert!(host.release_metadata(&library, &Release::default()).is_empty());
+        // Waits for the looping call, which gets only as much fuel as fits
+        // in the deadline.
+        assert!(plugin.artist_metadata(&Plugins::default(), &library, &Artist::default()).unwrap().is_some());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
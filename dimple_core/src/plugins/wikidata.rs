@@ -1,11 +1,11 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
 use crate::{librarian::ArtistMetadata, library::Library, model::Artist};
 
-use super::{plugin::Plugin, plugins::{nempty, Plugins}};
+use super::{cache::CachePolicy, plugin::Plugin, plugins::{nempty, Plugins}};
 
 impl Plugin for WikidataPlugin {
     fn type_name(&self) -> String {
@@ -15,6 +15,14 @@ impl Plugin for WikidataPlugin {
     fn display_name(&self) -> String {
         "Wikidata".to_string()
     }
+
+    /// Links and identifiers rarely change.
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy {
+            ttl: Duration::from_secs(30 * 24 * 60 * 60),
+            ..Default::default()
+        }
+    }
     
     fn artist_metadata(&self, host: &Plugins, library: &Library, artist: &crate::model::Artist) -> Result<Option<crate::librarian::ArtistMetadata>, anyhow::Error> {
         let client = WikidataClient::default();
@@ -0,0 +1,777 @@
+//! A small query language for filtering tracks, for example
+//! `artist:"Boards of Canada" year:1995..2000 genre:ambient plays>10 -live`.
+//!
+//! - Bare words and quoted phrases match the title, artist or album. So do
+//!   words like `Re:Stacks` that start with something other than a field.
+//! - `field:value` matches a field. Text fields match if they contain the
+//!   value, and `field=value` matches exactly.
+//! - Numbers, lengths and dates compare with `>`, `>=`, `<`, `<=` and `=`,
+//!   and ranges such as `year:1995..2000`, `length:..3:00` or `bpm:120..`.
+//! - `played` takes a date or an age such as `30d`, which is the time that
+//!   long ago: `played>30d` is played in the last 30 days.
+//! - Terms are all required, unless joined with `OR`. `-` or `NOT` negates
+//!   a term, and parentheses group them.
+//!
+//! Queries parse into an Expr, which compiles to a parameterized WHERE
+//! clause over `Track t`.
+
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params_from_iter, types::Value as SqlValue};
+
+use crate::{library::Library, model::Track};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Composer,
+    Label,
+    Work,
+    Mood,
+    Key,
+    Isrc,
+    Comment,
+    Lyrics,
+    /// The file extension of any media file of the track, e.g. flac.
+    Format,
+    Year,
+    /// The release date.
+    Date,
+    Length,
+    Bpm,
+    /// The highest bitrate of the media files of the track, in kbps.
+    Bitrate,
+    Plays,
+    LastPlayed,
+    Liked,
+    /// Marked to be downloaded and kept offline.
+    Saved,
+}
+
+const FIELDS: [(&str, Field); 23] = [
+    ("title", Field::Title),
+    ("artist", Field::Artist),
+    ("album", Field::Album),
+    ("genre", Field::Genre),
+    ("composer", Field::Composer),
+    ("label", Field::Label),
+    ("work", Field::Work),
+    ("mood", Field::Mood),
+    ("key", Field::Key),
+    ("isrc", Field::Isrc),
+    ("comment", Field::Comment),
+    ("lyrics", Field::Lyrics),
+    ("format", Field::Format),
+    ("year", Field::Year),
+    ("date", Field::Date),
+    ("length", Field::Length),
+    ("bpm", Field::Bpm),
+    ("bitrate", Field::Bitrate),
+    ("plays", Field::Plays),
+    ("played", Field::LastPlayed),
+    ("last_played", Field::LastPlayed),
+    ("liked", Field::Liked),
+    ("saved", Field::Saved),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Number,
+    Duration,
+    Date,
+    Bool,
+}
+
+impl Field {
+    pub fn from_name(name: &str) -> Option<Field> {
+        let name = name.to_lowercase();
+        FIELDS.iter().find(|(n, _)| *n == name).map(|(_, field)| *field)
+    }
+
+    pub fn name(&self) -> &'static str {
+        FIELDS.iter().find(|(_, field)| field == self).map(|(name, _)| *name).unwrap()
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            Field::Year | Field::Bpm | Field::Bitrate | Field::Plays => Kind::Number,
+            Field::Length => Kind::Duration,
+            Field::Date | Field::LastPlayed => Kind::Date,
+            Field::Liked | Field::Saved => Kind::Bool,
+            _ => Kind::Text,
+        }
+    }
+
+    /// The SQL for the field, relative to `Track t`.
+    fn column(&self) -> Column {
+        let release = |column: &str| Column::Scalar(format!("(SELECT r.{} FROM Release r WHERE r.key = t.release_key)", column));
+        let credit = |role: &str| Column::Any(format!("
+            FROM ArtistRef ar JOIN Artist a ON (a.key = ar.artist_key)
+            WHERE ar.model_key = t.key AND ar.role IN ({})", role), "a.name".to_string());
+        let media_files = "
+            FROM TrackSource ts JOIN MediaFile mf ON (mf.key = ts.media_file_key)
+            WHERE ts.track_key = t.key";
+        match self {
+            Field::Title => Column::Scalar("t.title".to_string()),
+            Field::Artist => credit("'primary', 'featured'"),
+            Field::Album => release("title"),
+            Field::Genre => Column::Any("
+                FROM GenreRef gr JOIN Genre g ON (g.key = gr.genre_key)
+                WHERE gr.model_key IN (t.key, t.release_key)".to_string(), "g.name".to_string()),
+            Field::Composer => credit("'composer'"),
+            Field::Label => release("label"),
+            Field::Work => Column::Scalar("t.work".to_string()),
+            Field::Mood => Column::Scalar("t.mood".to_string()),
+            Field::Key => Column::Scalar("t.initial_key".to_string()),
+            Field::Isrc => Column::Scalar("t.isrc".to_string()),
+            Field::Comment => Column::Scalar("t.comment".to_string()),
+            Field::Lyrics => Column::Scalar("t.lyrics".to_string()),
+            Field::Format => Column::Any(media_files.to_string(), "lower(mf.file_path)".to_string()),
+            Field::Year => Column::Scalar("CAST(substr((SELECT r.date FROM Release r WHERE r.key = t.release_key), 1, 4) AS INTEGER)".to_string()),
+            Field::Date => release("date"),
+            Field::Length => Column::Scalar("t.length_ms".to_string()),
+            Field::Bpm => Column::Scalar("t.bpm".to_string()),
+            Field::Bitrate => Column::Scalar(format!("(SELECT max(mf.bitrate) {})", media_files)),
+            Field::Plays => Column::Scalar(format!("(SELECT count(*) {})", PLAYS)),
+            Field::LastPlayed => Column::Scalar(format!("(SELECT max(e.timestamp) {})", PLAYS)),
+            Field::Liked => Column::Scalar("t.save".to_string()),
+            Field::Saved => Column::Scalar("t.download".to_string()),
+        }
+    }
+
+    /// The expression to ORDER BY for the field. For fields with more than
+    /// one value per track, such as artist, that's the first value.
+    pub fn order_by(&self) -> String {
+        match self.column() {
+            Column::Scalar(column) => column,
+            Column::Any(from, column) => format!("(SELECT {} {} LIMIT 1)", column, from),
+        }
+    }
+}
+
+/// Listening history refers to tracks by name, or by the name of a track
+/// that was merged into this one, see duplicates::merge_tracks.
+const PLAYS: &str = "
+    FROM Event e
+    WHERE e.event_type IN ('track_played', 'track_restarted')
+        AND (e.title = t.title OR e.title IN (SELECT al.name FROM Alias al
+            WHERE al.model = 'Track' AND al.model_key = t.key))
+        AND e.artist IN (SELECT a.name FROM ArtistRef ar
+            JOIN Artist a ON (a.key = ar.artist_key) WHERE ar.model_key = t.key)";
+
+enum Column {
+    /// An expression with one value per track.
+    Scalar(String),
+    /// FROM ... WHERE clauses of a subquery with any number of values per
+    /// track, and the column to test. The test passes if any value passes.
+    Any(String, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `:` contains for text, and equals otherwise.
+    Has,
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+    /// A length in milliseconds.
+    Duration(u64),
+    /// A date or the start of one, e.g. "1995" or "1995-06".
+    Date(String),
+    /// The time this many milliseconds ago.
+    Ago(u64),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// Matches the title, artist or album.
+    Text(String),
+    Compare { field: Field, op: Op, value: Value },
+    /// Inclusive. At least one of from and to is set.
+    Range { field: Field, from: Option<Value>, to: Option<Value> },
+    Not(Box<Expr>),
+    /// Matches everything when empty.
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryError {
+    /// The byte offset in the query where the error was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+pub struct CompiledQuery {
+    /// A WHERE clause over `Track t`, with a `?` for each param.
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+}
+
+pub fn parse(query: &str) -> Result<Expr, QueryError> {
+    let mut parser = Parser { input: query, pos: 0 };
+    parser.skip_whitespace();
+    if parser.peek().is_none() {
+        return Ok(Expr::And(vec![]))
+    }
+    let expr = parser.parse_or()?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return parser.error(parser.pos, "Unexpected ')'")
+    }
+    Ok(expr)
+}
+
+pub fn compile(expr: &Expr) -> CompiledQuery {
+    let mut compiler = Compiler {
+        params: vec![],
+        now: Utc::now(),
+    };
+    let sql = compiler.expr(expr);
+    CompiledQuery {
+        sql,
+        params: compiler.params,
+    }
+}
+
+/// Parse a sort rule such as "plays desc, title" into an ORDER BY clause.
+/// Fields sort ascending unless followed by desc, and `random` shuffles.
+/// Error positions are offsets into the sort rule.
+pub fn order_by(sort: &str) -> Result<String, QueryError> {
+    let mut terms = vec![];
+    let mut position = 0;
+    for term in sort.split(',') {
+        let start = position + term.len() - term.trim_start().len();
+        position += term.len() + 1;
+        let words = term.split_whitespace().collect::<Vec<_>>();
+        let direction = match words.get(1).map(|word| word.to_lowercase()).as_deref() {
+            None | Some("asc") => "ASC",
+            Some("desc") => "DESC",
+            Some(_) => return Err(QueryError {
+                position: start + words[0].len()
+                    + term.trim_start()[words[0].len()..].find(|c: char| !c.is_whitespace()).unwrap_or_default(),
+                message: "Expected asc or desc".to_string(),
+            }),
+        };
+        match words.first() {
+            Some(word) if word.eq_ignore_ascii_case("random") => terms.push("random()".to_string()),
+            Some(word) => {
+                let Some(field) = Field::from_name(word) else {
+                    return Err(QueryError {
+                        position: start,
+                        message: format!("Unknown field '{}'", word),
+                    })
+                };
+                let collate = if field.kind() == Kind::Text { " COLLATE UNICODE" } else { "" };
+                terms.push(format!("{}{} {}", field.order_by(), collate, direction));
+            },
+            None => return Err(QueryError {
+                position: start,
+                message: "Expected a field to sort by".to_string(),
+            }),
+        }
+        if words.len() > 2 {
+            return Err(QueryError {
+                position: start,
+                message: "Expected a field followed by asc or desc".to_string(),
+            })
+        }
+    }
+    Ok(terms.join(", "))
+}
+
+/// The tracks matching the query, in library order.
+pub fn tracks(library: &Library, query: &str) -> Result<Vec<Track>, QueryError> {
+    select(library, query, None, None)
+}
+
+/// The tracks matching the query, ordered by the sort rule, see order_by,
+/// and at most limit of them.
+pub fn select(library: &Library, query: &str, sort: Option<&str>, limit: Option<u32>) -> Result<Vec<Track>, QueryError> {
+    let compiled = compile(&parse(query)?);
+    let mut sql = format!("SELECT t.* FROM Track t WHERE {}", compiled.sql);
+    if let Some(sort) = sort.filter(|sort| !sort.trim().is_empty()) {
+        sql.push_str(&format!(" ORDER BY {}, t.rowid ASC", order_by(sort)?));
+    }
+    if let Some(limit) = limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+    Ok(library.query(&sql, params_from_iter(compiled.params)))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn error<T>(&self, position: usize, message: impl Into<String>) -> Result<T, QueryError> {
+        Err(QueryError {
+            position,
+            message: message.into(),
+        })
+    }
+
+    /// True if the keyword is next, as a whole word.
+    fn at_keyword(&self, keyword: &str) -> bool {
+        let rest = &self.input[self.pos..];
+        rest.starts_with(keyword) && rest[keyword.len()..].chars().next()
+            .is_none_or(|c| c.is_whitespace() || c == '(' || c == '"' || c == '-')
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += keyword.len();
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut terms = vec![self.parse_and()?];
+        loop {
+            self.skip_whitespace();
+            if !self.keyword("OR") {
+                break
+            }
+            terms.push(self.parse_and()?);
+        }
+        Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Expr::Or(terms) })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut terms = vec![];
+        loop {
+            self.skip_whitespace();
+            if matches!(self.peek(), None | Some(')')) || self.at_keyword("OR") {
+                break
+            }
+            if self.keyword("AND") {
+                continue
+            }
+            terms.push(self.parse_unary()?);
+        }
+        match terms.len() {
+            0 => self.error(self.pos, "Expected a search term"),
+            1 => Ok(terms.pop().unwrap()),
+            _ => Ok(Expr::And(terms)),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        let start = self.pos;
+        match self.peek() {
+            Some('(') => {
+                self.bump();
+                let expr = self.parse_or()?;
+                self.skip_whitespace();
+                if self.bump() != Some(')') {
+                    return self.error(start, "Unclosed '('")
+                }
+                Ok(expr)
+            },
+            Some('-') => {
+                self.bump();
+                if self.peek().is_none_or(char::is_whitespace) {
+                    return self.error(start, "Expected a term after '-'")
+                }
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            },
+            Some('"') => Ok(Expr::Text(self.quoted()?)),
+            _ if self.keyword("NOT") => {
+                self.skip_whitespace();
+                if self.peek().is_none() {
+                    return self.error(start, "Expected a term after NOT")
+                }
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            },
+            _ => self.field_or_word(),
+        }
+    }
+
+    fn field_or_word(&mut self) -> Result<Expr, QueryError> {
+        let start = self.pos;
+        let input = self.input;
+        let rest = &input[start..];
+        let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        let is_field = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && rest[name_len..].starts_with([':', '<', '>', '=']);
+        if !is_field {
+            return Ok(Expr::Text(self.bare()))
+        }
+        let Some(field) = Field::from_name(name) else {
+            // Titles such as "Re:Stacks" look like fields, so an unknown
+            // field before ':' is taken as text. Comparisons are errors.
+            if rest[name_len..].starts_with(':') {
+                return Ok(Expr::Text(self.bare()))
+            }
+            return self.error(start, format!("Unknown field '{}'", name))
+        };
+        self.pos += name_len;
+        let op = self.op();
+        let value_start = self.pos;
+        let (raw, quoted) = if self.peek() == Some('"') {
+            (self.quoted()?, true)
+        }
+        else {
+            (self.bare(), false)
+        };
+        if raw.is_empty() {
+            return self.error(value_start, format!("Expected a value for {}", field.name()))
+        }
+        if !quoted && matches!(op, Op::Has | Op::Eq) && field.kind() != Kind::Text {
+            if let Some(i) = raw.find("..") {
+                let (from, to) = (&raw[..i], &raw[i + 2..]);
+                if from.is_empty() && to.is_empty() {
+                    return self.error(value_start, "Expected a range such as 1995..2000")
+                }
+                let from = (!from.is_empty()).then(|| self.value(field, from, value_start)).transpose()?;
+                let to = (!to.is_empty()).then(|| self.value(field, to, value_start + i + 2)).transpose()?;
+                return Ok(Expr::Range { field, from, to })
+            }
+        }
+        Ok(Expr::Compare { field, op, value: self.value(field, &raw, value_start)? })
+    }
+
+    fn op(&mut self) -> Op {
+        let op = match self.bump() {
+            Some('=') => return Op::Eq,
+            Some('<') => Op::Lt,
+            Some('>') => Op::Gt,
+            _ => return Op::Has,
+        };
+        if self.peek() != Some('=') {
+            return op
+        }
+        self.bump();
+        if op == Op::Lt { Op::Le } else { Op::Ge }
+    }
+
+    /// A word, up to whitespace or a parenthesis.
+    fn bare(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace() && c != '(' && c != ')') {
+            self.bump();
+        }
+        self.input[start..self.pos].to_string()
+    }
+
+    fn quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.pos;
+        self.bump();
+        let Some(len) = self.input[self.pos..].find('"') else {
+            return self.error(start, "Unclosed '\"'")
+        };
+        let s = self.input[self.pos..self.pos + len].to_string();
+        self.pos += len + 1;
+        Ok(s)
+    }
+
+    fn value(&self, field: Field, raw: &str, position: usize) -> Result<Value, QueryError> {
+        let value = match field.kind() {
+            Kind::Text => Some(Value::Text(raw.to_string())),
+            Kind::Number => raw.parse::<f64>().ok().filter(|n| n.is_finite()).map(Value::Number),
+            Kind::Duration => parse_duration(raw).map(Value::Duration),
+            Kind::Date => parse_age(raw).map(Value::Ago)
+                .or_else(|| is_date(raw).then(|| Value::Date(raw.to_string()))),
+            Kind::Bool => match raw.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(Value::Bool(true)),
+                "false" | "no" | "0" => Some(Value::Bool(false)),
+                _ => None,
+            },
+        };
+        let expected = match field.kind() {
+            Kind::Text => "text",
+            Kind::Number => "a number",
+            Kind::Duration => "a length such as 3:30 or 90s",
+            Kind::Date => "a date such as 1995-06-01, or an age such as 30d",
+            Kind::Bool => "true or false",
+        };
+        value.map_or_else(|| self.error(position, format!("Expected {} for {}", expected, field.name())), Ok)
+    }
+}
+
+/// "3:30", "1:02:03", "90s", "5m", "1h", or plain seconds.
+fn parse_duration(s: &str) -> Option<u64> {
+    if s.contains(':') {
+        let mut seconds = 0;
+        for part in s.split(':') {
+            seconds = seconds * 60 + part.parse::<u64>().ok()?;
+        }
+        return Some(seconds * 1000)
+    }
+    let (number, unit) = split_unit(s);
+    let multiplier = match unit {
+        "" | "s" => 1_000.,
+        "m" => 60_000.,
+        "h" => 3_600_000.,
+        _ => return None,
+    };
+    number.parse::<f64>().ok().filter(|n| *n >= 0.).map(|n| (n * multiplier) as u64)
+}
+
+/// "12h", "30d", "2w", "6m" (months) or "1y".
+fn parse_age(s: &str) -> Option<u64> {
+    let (number, unit) = split_unit(s);
+    let hours = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        "m" => 24 * 30,
+        "y" => 24 * 365,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().map(|n| n * hours * 3_600_000)
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let i = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    (&s[..i], &s[i..])
+}
+
+/// YYYY, YYYY-MM or YYYY-MM-DD.
+fn is_date(s: &str) -> bool {
+    let parts = s.split('-').collect::<Vec<_>>();
+    parts.len() <= 3
+        && parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        && parts[0].len() == 4
+        && parts[1..].iter().all(|part| part.len() == 2)
+}
+
+struct Compiler {
+    params: Vec<SqlValue>,
+    now: DateTime<Utc>,
+}
+
+impl Compiler {
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::And(exprs) if exprs.is_empty() => "1".to_string(),
+            Expr::And(exprs) => self.join(exprs, " AND "),
+            Expr::Or(exprs) => self.join(exprs, " OR "),
+            Expr::Not(expr) => format!("NOT {}", self.expr(expr)),
+            Expr::Text(s) => {
+                let value = Value::Text(s.clone());
+                format!("({} OR {} OR {})",
+                    self.condition(Field::Title, Op::Has, &value),
+                    self.condition(Field::Artist, Op::Has, &value),
+                    self.condition(Field::Album, Op::Has, &value))
+            },
+            Expr::Compare { field, op, value } => self.condition(*field, *op, value),
+            Expr::Range { field, from, to } => {
+                let mut conditions = vec![];
+                if let Some(from) = from {
+                    conditions.push(self.condition(*field, Op::Ge, from));
+                }
+                if let Some(to) = to {
+                    conditions.push(self.condition(*field, Op::Le, to));
+                }
+                format!("({})", conditions.join(" AND "))
+            },
+        }
+    }
+
+    fn join(&mut self, exprs: &[Expr], separator: &str) -> String {
+        let exprs = exprs.iter().map(|expr| self.expr(expr)).collect::<Vec<_>>();
+        format!("({})", exprs.join(separator))
+    }
+
+    /// Comparisons with NULL are false rather than NULL, so that negating
+    /// them works as expected.
+    fn condition(&mut self, field: Field, op: Op, value: &Value) -> String {
+        match field.column() {
+            Column::Scalar(column) => format!("IFNULL({}, 0)", self.test(field, &column, op, value)),
+            Column::Any(from, column) => format!("EXISTS (SELECT 1 {} AND {})", from, self.test(field, &column, op, value)),
+        }
+    }
+
+    fn test(&mut self, field: Field, column: &str, op: Op, value: &Value) -> String {
+        let sql_op = match op {
+            Op::Has | Op::Eq => "=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        };
+        match value {
+            Value::Text(s) if field == Field::Format => {
+                self.params.push(SqlValue::Text(format!("%.{}", escape_like(&s.trim_start_matches('.').to_lowercase()))));
+                format!("{} LIKE ? ESCAPE '\\'", column)
+            },
+            Value::Text(s) if op == Op::Has => {
+                self.params.push(SqlValue::Text(format!("%{}%", escape_like(s))));
+                format!("{} LIKE ? ESCAPE '\\'", column)
+            },
+            Value::Text(s) => {
+                self.params.push(SqlValue::Text(s.clone()));
+                format!("{} {} ? COLLATE NOCASE", column, sql_op)
+            },
+            Value::Number(n) => {
+                self.params.push(SqlValue::Real(*n));
+                format!("{} {} ?", column, sql_op)
+            },
+            Value::Duration(ms) => {
+                self.params.push(SqlValue::Integer(*ms as i64));
+                format!("{} {} ?", column, sql_op)
+            },
+            Value::Bool(b) => {
+                self.params.push(SqlValue::Integer(*b as i64));
+                format!("{} {} ?", column, sql_op)
+            },
+            // Dates are stored as text, so compare the same number of
+            // characters, e.g. "1995" matches all of 1995.
+            Value::Date(date) => {
+                self.params.push(SqlValue::Text(date.clone()));
+                format!("substr({}, 1, {}) {} ?", column, date.len(), sql_op)
+            },
+            // Matching an age means on or after that long ago.
+            Value::Ago(ms) => {
+                let instant = self.now - chrono::Duration::milliseconds(*ms as i64);
+                self.params.push(SqlValue::Text(instant.format("%F %T%.f%:z").to_string()));
+                let sql_op = if matches!(op, Op::Has | Op::Eq) { ">=" } else { sql_op };
+                format!("{} {} ?", column, sql_op)
+            },
+        }
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use crate::{library::Library, model::{Artist, ArtistRef, Event, Genre, GenreRef, ModelBasics as _, Release, Track}};
+
+    use super::{order_by, parse, tracks, Expr, Field, Op, Value};
+
+    #[test]
+    fn parse_query() {
+        let expr = parse(r#"artist:"Boards of Canada" year:1995..2000 genre:ambient plays>10 -live"#).unwrap();
+        assert!(expr == Expr::And(vec![
+            Expr::Compare { field: Field::Artist, op: Op::Has, value: Value::Text("Boards of Canada".to_string()) },
+            Expr::Range { field: Field::Year, from: Some(Value::Number(1995.)), to: Some(Value::Number(2000.)) },
+            Expr::Compare { field: Field::Genre, op: Op::Has, value: Value::Text("ambient".to_string()) },
+            Expr::Compare { field: Field::Plays, op: Op::Gt, value: Value::Number(10.) },
+            Expr::Not(Box::new(Expr::Text("live".to_string()))),
+        ]));
+        assert!(parse("a OR (b c)").unwrap() == Expr::Or(vec![
+            Expr::Text("a".to_string()),
+            Expr::And(vec![Expr::Text("b".to_string()), Expr::Text("c".to_string())]),
+        ]));
+        assert!(parse("length:..3:30").unwrap() == Expr::Range { field: Field::Length, from: None, to: Some(Value::Duration(210_000)) });
+        assert!(parse("played>=30d").unwrap() == Expr::Compare { field: Field::LastPlayed, op: Op::Ge, value: Value::Ago(30 * 24 * 3_600_000) });
+        assert!(parse("  ").unwrap() == Expr::And(vec![]));
+        assert!(parse("Re:Stacks").unwrap() == Expr::Text("Re:Stacks".to_string()));
+        assert!(parse("title:Re:Stacks").unwrap() == Expr::Compare { field: Field::Title, op: Op::Has, value: Value::Text("Re:Stacks".to_string()) });
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query: &str| parse(query).unwrap_err();
+        assert!(error("title:x colour=red").position == 8);
+        assert!(error("title:x colour=red").message == "Unknown field 'colour'");
+        assert!(error("plays>lots").position == 6);
+        assert!(error("year:1995..abc").position == 11);
+        assert!(error("(a b").position == 0);
+        assert!(error("a b)").position == 3);
+        assert!(error("artist:\"Boards").position == 7);
+        assert!(error("a -").position == 2);
+        assert!(order_by("plays desc, title").is_ok());
+        assert!(order_by("plays desc, colour").unwrap_err().position == 12);
+        assert!(order_by("plays down").unwrap_err().position == 6);
+    }
+
+    #[test]
+    fn query_tracks() {
+        let library = Library::open_memory();
+        let boc = Artist {
+            name: Some("Boards of Canada".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let release = Release {
+            title: Some("Music Has the Right to Children".to_string()),
+            date: Some("1998-04-20".to_string()),
+            ..Default::default()
+        }.save(&library);
+        GenreRef::attach(&library, &Genre::new("ambient").save(&library), &release);
+        let roygbiv = Track {
+            title: Some("Roygbiv".to_string()),
+            release_key: release.key.clone(),
+            length_ms: Some(151_000),
+            save: true,
+            ..Default::default()
+        }.save(&library);
+        let live = Track {
+            title: Some("Roygbiv (Live)".to_string()),
+            release_key: release.key.clone(),
+            length_ms: Some(240_000),
+            ..Default::default()
+        }.save(&library);
+        for track in [&roygbiv, &live] {
+            ArtistRef::attach(&library, &boc, track);
+        }
+        for i in 0..11 {
+            Event {
+                timestamp: Utc::now() - Duration::days(100 + i),
+                event_type: "track_played".to_string(),
+                artist: boc.name.clone(),
+                title: roygbiv.title.clone(),
+                source_type: "test".to_string(),
+                source: i.to_string(),
+                ..Default::default()
+            }.save(&library);
+        }
+
+        let keys = |query: &str| tracks(&library, query).unwrap().into_iter().map(|t| t.key).collect::<Vec<_>>();
+        assert!(keys(r#"artist:"Boards of Canada" year:1995..2000 genre:ambient plays>10 -live"#) == vec![roygbiv.key.clone()]);
+        assert!(keys("roygbiv").len() == 2);
+        assert!(keys("length<3:00 OR liked=false").len() == 2);
+        assert!(keys("date:1998-04 length>3m") == vec![live.key.clone()]);
+        assert!(keys("liked:yes -played>90d") == vec![roygbiv.key.clone()]);
+        assert!(keys("played<90d").len() == 1);
+        assert!(keys("genre:jazz").is_empty());
+        assert!(keys("").len() == 2);
+    }
+}
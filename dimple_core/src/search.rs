@@ -0,0 +1,299 @@
+//! Full text search of the library. Each of Artist, Release, Track and
+//! Genre has an FTS5 table that triggers keep up to date, see the
+//! 12-search migration. Queries match on word prefixes, so results come
+//! back while the user is still typing, and are ranked with bm25, weighting
+//! names above credits, albums, genres and text, in that order.
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization as _};
+
+use crate::{library::Library, model::{Artist, Genre, LibraryModel, Release, Track}};
+
+/// The models searched, which are also the FTS5 table name prefixes.
+const MODELS: [&str; 4] = ["Artist", "Release", "Track", "Genre"];
+
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// The most hits returned for each model.
+    pub limit: usize,
+    /// Inserted before and after matched terms in highlights and snippets.
+    pub highlight_start: String,
+    pub highlight_end: String,
+    /// If nothing matches, search again with each term replaced by the
+    /// closest indexed term, to forgive typos.
+    pub fuzzy: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 25,
+            highlight_start: "[".to_string(),
+            highlight_end: "]".to_string(),
+            fuzzy: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit<T> {
+    pub model: T,
+    /// The bm25 rank. Lower is better.
+    pub score: f64,
+    /// The name or title, with matched terms highlighted.
+    pub highlight: String,
+    /// A fragment of the best matching text, such as a line of lyrics,
+    /// with matched terms highlighted.
+    pub snippet: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchHits {
+    pub artists: Vec<SearchHit<Artist>>,
+    pub releases: Vec<SearchHit<Release>>,
+    pub tracks: Vec<SearchHit<Track>>,
+    pub genres: Vec<SearchHit<Genre>>,
+    /// Set to the query that was searched if typos were corrected.
+    pub corrected_query: Option<String>,
+}
+
+impl SearchHits {
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty() && self.releases.is_empty()
+            && self.tracks.is_empty() && self.genres.is_empty()
+    }
+}
+
+pub fn search(library: &Library, query: &str, options: &SearchOptions) -> SearchHits {
+    let terms = terms(query);
+    if terms.is_empty() {
+        return SearchHits::default()
+    }
+    let hits = search_terms(library, &terms, options);
+    if !hits.is_empty() || !options.fuzzy {
+        return hits
+    }
+    let corrected = terms.iter()
+        .map(|term| correct_term(library, term).unwrap_or(term.clone()))
+        .collect::<Vec<_>>();
+    if corrected == terms {
+        return hits
+    }
+    SearchHits {
+        corrected_query: Some(corrected.join(" ")),
+        ..search_terms(library, &corrected, options)
+    }
+}
+
+/// Repopulate the search tables from scratch. The triggers keep them up to
+/// date, so this is only needed if the tables get out of step with the
+/// models, e.g. if a VACUUM renumbered the rowids.
+pub fn rebuild(library: &Library) {
+    let conn = library.conn();
+    for model in MODELS {
+        conn.execute_batch(&format!("
+            DELETE FROM {0}Search;
+            INSERT INTO {0}Search (rowid, key, name, credits, album, genres, text)
+                SELECT id, key, name, credits, album, genres, text FROM {0}SearchDocument;
+        ", model)).unwrap();
+    }
+}
+
+fn search_terms(library: &Library, terms: &[String], options: &SearchOptions) -> SearchHits {
+    // Every term must match, as a prefix of a word.
+    let query = terms.iter()
+        .map(|term| format!("\"{}\"*", term))
+        .collect::<Vec<_>>()
+        .join(" ");
+    SearchHits {
+        artists: search_model(library, &query, options),
+        releases: search_model(library, &query, options),
+        tracks: search_model(library, &query, options),
+        genres: search_model(library, &query, options),
+        corrected_query: None,
+    }
+}
+
+fn search_model<T: LibraryModel>(library: &Library, query: &str, options: &SearchOptions) -> Vec<SearchHit<T>> {
+    let sql = format!("
+        SELECT m.*,
+            bm25({0}Search, 0.0, 10.0, 5.0, 3.0, 2.0, 1.0) AS search_score,
+            highlight({0}Search, 1, ?2, ?3) AS search_highlight,
+            snippet({0}Search, -1, ?2, ?3, '…', 12) AS search_snippet
+        FROM {0}Search
+        JOIN {0} m ON (m.key = {0}Search.key)
+        WHERE {0}Search MATCH ?1
+        ORDER BY search_score ASC
+        LIMIT ?4
+    ", T::default().type_name());
+    let conn = library.conn();
+    let mut stmt = conn.prepare(&sql).unwrap();
+    let params = (query, &options.highlight_start, &options.highlight_end, options.limit as i64);
+    let hits = stmt.query_map(params, |row| {
+            Ok(SearchHit {
+                model: T::from_row(row),
+                score: row.get("search_score")?,
+                highlight: row.get::<_, Option<String>>("search_highlight")?.unwrap_or_default(),
+                snippet: row.get::<_, Option<String>>("search_snippet")?.unwrap_or_default(),
+            })
+        })
+        .unwrap()
+        .map(|hit| hit.unwrap())
+        .collect();
+    hits
+}
+
+/// Split a highlight or snippet into runs of text, each true if it's a
+/// matched term, for display without the markers.
+pub fn highlight_runs(text: &str, options: &SearchOptions) -> Vec<(String, bool)> {
+    let mut runs = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let Some(start) = rest.find(&options.highlight_start) else {
+            runs.push((rest.to_string(), false));
+            break
+        };
+        if start > 0 {
+            runs.push((rest[..start].to_string(), false));
+        }
+        rest = &rest[start + options.highlight_start.len()..];
+        let end = rest.find(&options.highlight_end).unwrap_or(rest.len());
+        runs.push((rest[..end].to_string(), true));
+        rest = &rest[(end + options.highlight_end.len()).min(rest.len())..];
+    }
+    runs
+}
+
+/// Split the query into words the way the FTS5 unicode61 tokenizer does,
+/// lowercased and without diacritics.
+fn terms(query: &str) -> Vec<String> {
+    query.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The indexed term closest to `term`, if it isn't indexed itself. Only
+/// terms starting with the same character are considered, and short terms
+/// may only be one edit away. Ties go to the term in the most documents.
+fn correct_term(library: &Library, term: &str) -> Option<String> {
+    let first = term.chars().next()?;
+    let next = char::from_u32(first as u32 + 1)?;
+    let max_distance = if term.chars().count() <= 4 { 1 } else { 2 };
+    let sql = MODELS.iter()
+        .map(|model| format!("SELECT term, doc FROM {}SearchVocab WHERE term >= ?1 AND term < ?2", model))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    let conn = library.conn();
+    let mut stmt = conn.prepare(&sql).unwrap();
+    let candidates = stmt.query_map((first.to_string(), next.to_string()),
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))
+        .unwrap()
+        .map(|candidate| candidate.unwrap())
+        .collect::<Vec<_>>();
+    if candidates.iter().any(|(candidate, _)| candidate.starts_with(term)) {
+        return None
+    }
+    candidates.into_iter()
+        .map(|(candidate, docs)| (edit_distance(term, &candidate), -docs, candidate))
+        .filter(|(distance, _, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, _, candidate)| candidate)
+}
+
+/// Levenshtein distance, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + if ca == *cb { 0 } else { 1 });
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{library::Library, model::{Artist, ArtistRef, Genre, GenreRef, ModelBasics as _, Release, Track}};
+
+    use super::{edit_distance, highlight_runs, search, terms, SearchOptions};
+
+    #[test]
+    fn ranked_and_highlighted() {
+        let library = Library::open_memory();
+        let bjork = Artist {
+            name: Some("Björk".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let release = Release {
+            title: Some("Homogenic".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let joga = Track {
+            title: Some("Jóga".to_string()),
+            release_key: release.key.clone(),
+            lyrics: Some("All these accidents that happen, follow the dot".to_string()),
+            ..Default::default()
+        }.save(&library);
+        let bachelorette = Track {
+            title: Some("Bachelorette".to_string()),
+            release_key: release.key.clone(),
+            ..Default::default()
+        }.save(&library);
+        ArtistRef::attach(&library, &bjork, &joga);
+        ArtistRef::attach(&library, &bjork, &bachelorette);
+        GenreRef::attach(&library, &Genre::new("art pop").save(&library), &release);
+        let options = SearchOptions::default();
+
+        // Diacritics and prefixes.
+        let hits = search(&library, "bjo", &options);
+        assert!(hits.artists.len() == 1);
+        assert!(hits.artists[0].highlight == "[Björk]");
+        assert!(highlight_runs(&hits.artists[0].highlight, &options) == vec![("Björk".to_string(), true)]);
+        assert!(hits.tracks.len() == 2);
+
+        // Names rank above credits and lyrics.
+        let hits = search(&library, "joga", &options);
+        assert!(hits.tracks[0].model.key == joga.key);
+
+        // Lyrics, album and genre.
+        let hits = search(&library, "accidents", &options);
+        assert!(hits.tracks.len() == 1 && hits.tracks[0].snippet.contains("[accidents]"));
+        let runs = highlight_runs(&hits.tracks[0].snippet, &options);
+        assert!(runs.iter().any(|run| *run == ("accidents".to_string(), true)));
+        assert!(runs.iter().all(|(text, _)| !text.contains('[') && !text.contains(']')));
+        assert!(search(&library, "homogenic bachelor", &options).tracks.len() == 1);
+        assert!(search(&library, "art pop", &options).releases.len() == 1);
+
+        // The index follows renames.
+        let mut release = release;
+        release.title = Some("Homogenic (Live)".to_string());
+        release.save(&library);
+        assert!(search(&library, "live", &options).tracks.len() == 2);
+        library.conn().execute("DELETE FROM ArtistRef WHERE model_key = ?1", (&bachelorette.key,)).unwrap();
+        assert!(search(&library, "bjork", &options).tracks.len() == 1);
+
+        // Typos.
+        let hits = search(&library, "bachelorete", &options);
+        assert!(hits.corrected_query == Some("bachelorette".to_string()));
+        assert!(hits.tracks.len() == 1);
+    }
+
+    #[test]
+    fn tokenize() {
+        assert!(terms("Sigur Rós - Hoppípolla!") == vec!["sigur", "ros", "hoppipolla"]);
+        assert!(terms("  ").is_empty());
+        assert!(edit_distance("kitten", "sitting") == 3);
+        assert!(edit_distance("", "abc") == 3);
+    }
+}
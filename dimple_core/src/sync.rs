@@ -31,7 +31,7 @@ use storage::Storage;
 use tempfile::tempdir;
 use uuid::Uuid;
 
-use crate::{library::Library, model::{Blob, ChangeLog, Diff, Model, ModelBasics, Track, TrackSource}};
+use crate::{duplicates, entities, library::Library, model::{Blob, ChangeLog, Diff, Model, ModelBasics, Track, TrackSource}, network::OfflineError};
 
 pub struct Sync {
     storage: Box<dyn Storage>,
@@ -72,7 +72,9 @@ impl Sync {
     /// 
     /// I think this is actually going to reflect the layout on local disk too.
     /// 
-    pub fn sync(&self, library: &Library) {
+    /// Fails without touching storage if the library's network is offline.
+    pub fn sync(&self, library: &Library) -> Result<(), OfflineError> {
+        library.network.request(&self.path)?;
         info!("Synchronizing {}.", library.id());
         let temp_dir = tempdir().unwrap();
 
@@ -151,10 +153,12 @@ impl Sync {
         // TODO also pull down new blobs that are marked for offline.
 
         info!("Sync complete.");
+        Ok(())
     }
 
-    pub fn load_blob_content(&self, blob: &Blob) -> Option<Vec<u8>> {
+    pub fn load_blob_content(&self, library: &Library, blob: &Blob) -> Option<Vec<u8>> {
         let path = format!("{}/blobs/{}.blob", self.path, blob.sha256);
+        library.network.request(&path).ok()?;
         self.storage.get_object(&path)
     }
 
@@ -168,6 +172,26 @@ impl Sync {
         if actor == library.id() {
             return
         }
+        if model == "Track" && op == "merge" {
+            let into: Option<Track> = changelog.value.clone().and_then(|key| library.get(&key));
+            let duplicate: Option<Track> = library.get(&model_key);
+            if let (Some(into), Some(duplicate)) = (into, duplicate) {
+                duplicates::merge_track_rows(library, &into, &duplicate);
+            }
+            return
+        }
+        if op == "merge" {
+            if let Some(into_key) = changelog.value.clone() {
+                entities::apply_merge(library, &model, &model_key, &into_key);
+            }
+            return
+        }
+        if op == "split" {
+            if let Some(value) = changelog.value.clone() {
+                entities::apply_split(library, &model, &model_key, &value);
+            }
+            return
+        }
         // TODO generify
         if model == "Track" {
             // TODO duplicated check of set in apply_diff
@@ -219,9 +243,62 @@ impl Sync {
 
 #[cfg(test)]
 mod tests {
-    use crate::{library::Library, model::Track};
+    use crate::{entities, library::Library, model::{ChangeLog, ModelBasics as _, Release, Track}};
+
+    use super::{memory_storage::MemoryStorage, storage::Storage as _, Sync};
+
+    #[test]
+    fn apply_split() {
+        let library1 = Library::open_memory();
+        let library2 = Library::open_memory();
+        let release = Release {
+            key: Some("eb7cd1a1-2d4b-4c1f-a0ca-1d1bde6b22a0".to_string()),
+            title: Some("Greatest Hits".to_string()),
+            ..Default::default()
+        };
+        let tracks = (0..4).map(|i| Track {
+            key: Some(format!("track-{}", i)),
+            release_key: release.key.clone(),
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        for library in [&library1, &library2] {
+            library.insert(&release);
+            for track in &tracks {
+                library.insert(track);
+            }
+        }
+        let split = entities::split_entity(&library1, &release, &Release {
+            title: Some("Greatest Hits".to_string()),
+            disambiguation: Some("Queen".to_string()),
+            ..Default::default()
+        }, &[tracks[2].key.clone().unwrap(), tracks[3].key.clone().unwrap()]);
+
+        let changelog = ChangeLog::list(&library1).into_iter()
+            .find(|changelog| changelog.op == "split")
+            .unwrap();
+        Sync::apply_changelog(&library2, &changelog);
+        // Replaying twice does nothing more.
+        Sync::apply_changelog(&library2, &changelog);
+        let split2 = Release::get(&library2, &split.key.clone().unwrap()).unwrap();
+        assert!(split2.disambiguation == Some("Queen".to_string()));
+        assert!(split2.tracks(&library2).len() == 2);
+        assert!(release.tracks(&library2).len() == 2);
+    }
 
-    use super::{memory_storage::MemoryStorage, Sync};
+    #[test]
+    fn offline() {
+        let storage = MemoryStorage::default();
+        let library = Library::open_memory();
+        library.add_sync(Sync::new(Box::new(storage.clone()), "offline"));
+        library.save(&Track { 
+            title: Some("One Thing".to_string()), 
+            ..Default::default() 
+        });
+        library.network.set_offline(true);
+        assert!(library.sync().is_err());
+        assert!(storage.list_objects("").is_empty());
+        assert!(library.network.requests() == 0);
+    }
 
     // #[test]
     // fn it_works() {
@@ -0,0 +1,455 @@
+//! Writes metadata from the library back into the tags of the media files,
+//! so that corrections made in Dimple are seen by other players. Nothing is
+//! written unless a caller asks for it, and then only the selected fields.
+//!
+//! Only the selected items are touched. The file's own tag (ID3v2, Vorbis
+//! comments or MP4 ilst) is split into a generic lofty Tag, which is
+//! edited, and the frames lofty can't map to it, such as PRIV, GEOB and
+//! custom fields, which are merged back in unchanged before saving. Fields
+//! that are empty in the library are left alone rather than removed from
+//! the file.
+//!
+//! Artist and AlbumArtist are written as the display credit, such as
+//! "Gorillaz feat. De La Soul". The track's performing artists are also
+//! written to the multi-valued ARTISTS when there's more than one, or the
+//! file already has it, which is how they're read back on import.
+//!
+//! Media files inside archives are skipped, since they can't be written in
+//! place.
+
+use std::{fs::File, path::Path};
+
+use anyhow::anyhow;
+use lofty::{config::{ParseOptions, WriteOptions}, file::{AudioFile as _, FileType}, flac::FlacFile, id3::v2::Id3v2Tag, mp4::{Ilst, Mp4File}, mpeg::MpegFile, ogg::{OpusFile, VorbisComments, VorbisFile}, picture::{MimeType, Picture, PictureType}, probe::Probe, tag::{ItemKey, ItemValue, MergeTag as _, SplitTag, Tag, TagExt, TagItem}};
+
+use crate::{library::Library, model::{dimage::DimageKind, Artist, Dimage, MediaFile, ModelBasics as _, Release, Track}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    TrackNumber,
+    TrackTotal,
+    DiscNumber,
+    Date,
+    Barcode,
+    Lyrics,
+    MusicBrainzTrackId,
+    MusicBrainzReleaseId,
+    MusicBrainzArtistId,
+    MusicBrainzReleaseArtistId,
+    CoverArt,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 15] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::TrackNumber,
+        TagField::TrackTotal,
+        TagField::DiscNumber,
+        TagField::Date,
+        TagField::Barcode,
+        TagField::Lyrics,
+        TagField::MusicBrainzTrackId,
+        TagField::MusicBrainzReleaseId,
+        TagField::MusicBrainzArtistId,
+        TagField::MusicBrainzReleaseArtistId,
+        TagField::CoverArt,
+    ];
+
+    fn item_key(&self) -> Option<ItemKey> {
+        match self {
+            TagField::Title => Some(ItemKey::TrackTitle),
+            TagField::Artist => Some(ItemKey::TrackArtist),
+            TagField::Album => Some(ItemKey::AlbumTitle),
+            TagField::AlbumArtist => Some(ItemKey::AlbumArtist),
+            TagField::TrackNumber => Some(ItemKey::TrackNumber),
+            TagField::TrackTotal => Some(ItemKey::TrackTotal),
+            TagField::DiscNumber => Some(ItemKey::DiscNumber),
+            TagField::Date => Some(ItemKey::RecordingDate),
+            TagField::Barcode => Some(ItemKey::Barcode),
+            TagField::Lyrics => Some(ItemKey::Lyrics),
+            TagField::MusicBrainzTrackId => Some(ItemKey::MusicBrainzTrackId),
+            TagField::MusicBrainzReleaseId => Some(ItemKey::MusicBrainzReleaseId),
+            TagField::MusicBrainzArtistId => Some(ItemKey::MusicBrainzArtistId),
+            TagField::MusicBrainzReleaseArtistId => Some(ItemKey::MusicBrainzReleaseArtistId),
+            TagField::CoverArt => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TagWriteOptions {
+    pub fields: Vec<TagField>,
+    /// Compute the changes but don't write anything.
+    pub dry_run: bool,
+}
+
+/// A single field that differs between the file and the library. For
+/// CoverArt the values are the sha256 of the image, as computed by Dimage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagChange {
+    pub field: TagField,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagWriteResult {
+    pub media_file: MediaFile,
+    pub changes: Vec<TagChange>,
+    pub written: bool,
+    /// Set if the file was not considered, with the reason.
+    pub skipped: Option<String>,
+    /// Set if the file couldn't be read or written, such as when it has
+    /// been moved. The other files are still written.
+    pub error: Option<String>,
+}
+
+/// Write the selected fields of the track, its release and artists to every
+/// MediaFile linked to the track.
+pub fn write_track_tags(library: &Library, track: &Track, options: &TagWriteOptions) -> Result<Vec<TagWriteResult>, anyhow::Error> {
+    let values = TagValues::new(library, track);
+    let mut results = vec![];
+    for track_source in library.track_sources_for_track(track) {
+        let Some(media_file) = track_source.media_file(library) else {
+            continue
+        };
+        if media_file.archive_path.is_some() {
+            results.push(TagWriteResult {
+                media_file,
+                changes: vec![],
+                written: false,
+                skipped: Some("File is in an archive.".to_string()),
+                error: None,
+            });
+            continue
+        }
+        match write_media_file_tags(library, &media_file, &values, options) {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                log::error!("Error writing tags to {}: {}", media_file.file_path, e);
+                results.push(TagWriteResult {
+                    media_file,
+                    changes: vec![],
+                    written: false,
+                    skipped: None,
+                    error: Some(e.to_string()),
+                });
+            },
+        }
+    }
+    Ok(results)
+}
+
+/// Write the selected fields for every track on the release.
+pub fn write_release_tags(library: &Library, release: &Release, options: &TagWriteOptions) -> Result<Vec<TagWriteResult>, anyhow::Error> {
+    let mut results = vec![];
+    for track in release.tracks(library) {
+        results.extend(write_track_tags(library, &track, options)?);
+    }
+    Ok(results)
+}
+
+/// Write the selected fields for every track on the artist's releases.
+pub fn write_artist_tags(library: &Library, artist: &Artist, options: &TagWriteOptions) -> Result<Vec<TagWriteResult>, anyhow::Error> {
+    let mut results = vec![];
+    for release in artist.releases(library) {
+        results.extend(write_release_tags(library, &release, options)?);
+    }
+    Ok(results)
+}
+
+/// The values the library has for each field of a track.
+struct TagValues {
+    texts: Vec<(TagField, Option<String>)>,
+    /// The names of the performing track artists, for ARTISTS.
+    artists: Vec<String>,
+    cover: Option<Dimage>,
+}
+
+impl TagValues {
+    fn new(library: &Library, track: &Track) -> Self {
+        let release = track.release(library);
+        let artists = track.artists(library);
+        let release_artists = release.iter()
+            .flat_map(|release| release.artists(library))
+            .collect::<Vec<_>>();
+        let texts = vec![
+            (TagField::Title, track.title.clone()),
+            (TagField::Artist, track.artist_credit(library)),
+            (TagField::Album, release.as_ref().and_then(|r| r.title.clone())),
+            (TagField::AlbumArtist, release.as_ref().and_then(|r| r.artist_credit(library))),
+            (TagField::TrackNumber, track.position.map(|p| p.to_string())),
+            (TagField::TrackTotal, track.media_track_count.map(|c| c.to_string())),
+            (TagField::DiscNumber, track.media_position.map(|p| p.to_string())),
+            (TagField::Date, release.as_ref().and_then(|r| r.date.clone())),
+            (TagField::Barcode, release.as_ref().and_then(|r| r.barcode.clone())),
+            (TagField::Lyrics, track.lyrics.clone()),
+            (TagField::MusicBrainzTrackId, track.musicbrainz_id.clone()),
+            (TagField::MusicBrainzReleaseId, release.as_ref().and_then(|r| r.musicbrainz_id.clone())),
+            (TagField::MusicBrainzArtistId, artists.first().and_then(|a| a.musicbrainz_id.clone())),
+            (TagField::MusicBrainzReleaseArtistId, release_artists.first().and_then(|a| a.musicbrainz_id.clone())),
+        ];
+        let cover = release.iter()
+            .flat_map(|release| release.images(library))
+            .find(|dimage| dimage.kind.is_none() || dimage.kind == Some(DimageKind::MusicAlbumCover));
+        let artists = track.artist_credits(library).iter()
+            .filter(|credit| credit.role.is_performer())
+            .filter_map(|credit| credit.name())
+            .collect();
+        Self {
+            texts,
+            artists,
+            cover,
+        }
+    }
+}
+
+fn write_media_file_tags(library: &Library, media_file: &MediaFile, values: &TagValues, options: &TagWriteOptions) -> Result<TagWriteResult, anyhow::Error> {
+    let path = Path::new(&media_file.file_path);
+    let file_type = Probe::open(path)?.guess_file_type()?.file_type();
+    let mut reader = File::open(path)?;
+    let parse_options = ParseOptions::new().read_properties(false);
+    let edit = |tag: &mut Tag| edit_tag(tag, values, options);
+    let changes = match file_type {
+        Some(FileType::Mpeg) => {
+            let file = MpegFile::read_from(&mut reader, parse_options)?;
+            write_tag::<Id3v2Tag>(path, file.id3v2().cloned().unwrap_or_default(), options, edit)?
+        },
+        Some(FileType::Flac) => {
+            let file = FlacFile::read_from(&mut reader, parse_options)?;
+            write_tag::<VorbisComments>(path, file.vorbis_comments().cloned().unwrap_or_default(), options, edit)?
+        },
+        Some(FileType::Vorbis) => {
+            let file = VorbisFile::read_from(&mut reader, parse_options)?;
+            write_tag::<VorbisComments>(path, file.vorbis_comments().clone(), options, edit)?
+        },
+        Some(FileType::Opus) => {
+            let file = OpusFile::read_from(&mut reader, parse_options)?;
+            write_tag::<VorbisComments>(path, file.vorbis_comments().clone(), options, edit)?
+        },
+        Some(FileType::Mp4) => {
+            let file = Mp4File::read_from(&mut reader, parse_options)?;
+            write_tag::<Ilst>(path, file.ilst().cloned().unwrap_or_default(), options, edit)?
+        },
+        _ => return Err(anyhow!("Writing tags to {:?} files isn't supported: {:?}", file_type, path)),
+    };
+    let written = !options.dry_run && !changes.is_empty();
+
+    // Keep the MediaFile current so the next import doesn't treat the file
+    // as changed by someone else.
+    let media_file = if written {
+        let mut media_file = media_file.clone();
+        media_file.last_modified = path.metadata()?.modified()?.into();
+        media_file.save(library)
+    }
+    else {
+        media_file.clone()
+    };
+
+    Ok(TagWriteResult {
+        media_file,
+        changes,
+        written,
+        skipped: None,
+        error: None,
+    })
+}
+
+/// Split the file's tag, edit the generic part, merge the rest back in and
+/// save it, unless it's a dry run or nothing changed.
+fn write_tag<T>(path: &Path, native: T, options: &TagWriteOptions, 
+    edit: impl FnOnce(&mut Tag) -> Vec<TagChange>) -> Result<Vec<TagChange>, anyhow::Error>
+where T: SplitTag + TagExt, T::Err: std::error::Error + Send + Sync + 'static {
+    let (remainder, mut tag) = native.split_tag();
+    let changes = edit(&mut tag);
+    if !options.dry_run && !changes.is_empty() {
+        log::info!("Writing {} tag changes to {:?}", changes.len(), path);
+        remainder.merge_tag(tag).save_to_path(path, WriteOptions::default())?;
+    }
+    Ok(changes)
+}
+
+fn edit_tag(tag: &mut Tag, values: &TagValues, options: &TagWriteOptions) -> Vec<TagChange> {
+    let mut changes = vec![];
+    for (field, new) in &values.texts {
+        if !options.fields.contains(field) {
+            continue
+        }
+        let Some(new) = new else {
+            continue
+        };
+        let key = field.item_key().unwrap();
+        let old = tag.get_string(&key).map(String::from);
+        if old.as_deref().is_some_and(|old| same_value(old, new)) {
+            continue
+        }
+        tag.insert_text(key, new.clone());
+        changes.push(TagChange {
+            field: *field,
+            old,
+            new: Some(new.clone()),
+        });
+    }
+
+    if options.fields.contains(&TagField::Artist) {
+        let old = tag.get_strings(&ItemKey::TrackArtists).map(String::from).collect::<Vec<_>>();
+        if (values.artists.len() > 1 || !old.is_empty()) && old != values.artists {
+            tag.remove_key(&ItemKey::TrackArtists);
+            for name in &values.artists {
+                tag.push(TagItem::new(ItemKey::TrackArtists, ItemValue::Text(name.clone())));
+            }
+            changes.push(TagChange {
+                field: TagField::Artist,
+                old: Some(old.join("; ")).filter(|old| !old.is_empty()),
+                new: Some(values.artists.join("; ")),
+            });
+        }
+    }
+
+    if options.fields.contains(&TagField::CoverArt) {
+        if let Some(cover) = &values.cover {
+            let old = tag.pictures().iter()
+                .find(|pic| pic.pic_type() == PictureType::CoverFront)
+                .and_then(|pic| image::load_from_memory(pic.data()).ok())
+                .map(|image| Dimage::new(&image).sha256);
+            if old.as_ref() != Some(&cover.sha256) {
+                tag.remove_picture_type(PictureType::CoverFront);
+                tag.push_picture(Picture::new_unchecked(PictureType::CoverFront,
+                    Some(MimeType::Png), None, cover.png_data.clone()));
+                changes.push(TagChange {
+                    field: TagField::CoverArt,
+                    old,
+                    new: Some(cover.sha256.clone()),
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Numbers are compared numerically so "03" and "3" don't cause a write.
+fn same_value(old: &str, new: &str) -> bool {
+    let old = old.trim();
+    let new = new.trim();
+    match (old.parse::<u32>(), new.parse::<u32>()) {
+        (Ok(old), Ok(new)) => old == new,
+        _ => old == new,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path};
+
+    use lofty::{config::{ParseOptions, WriteOptions}, file::{AudioFile as _, TaggedFileExt as _}, id3::v2::{Frame, Id3v2Tag, PrivateFrame}, mpeg::MpegFile, tag::{Accessor as _, ItemKey, TagExt as _}};
+
+    use crate::{library::Library, model::{ModelBasics as _, Track}};
+
+    use super::{write_track_tags, TagField, TagWriteOptions};
+
+    #[test]
+    fn write_and_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pink-noise.mp3");
+        std::fs::copy("tests/data/media_files/pink-noise-1s-192kbit.mp3", &path).unwrap();
+
+        // A comment, which the writer doesn't know about and must keep.
+        let mut tagged_file = lofty::read_from_path(&path).unwrap();
+        let tag = tagged_file.primary_tag_mut().unwrap();
+        tag.insert_text(ItemKey::Comment, "keep me".to_string());
+        tag.save_to_path(&path, WriteOptions::default()).unwrap();
+
+        let library = Library::open_memory();
+        library.import(dir.path().to_str().unwrap());
+        let mut track = Track::list(&library)[0].clone();
+        track.title = Some("Fixed Title".to_string());
+        let track = track.save(&library);
+
+        let options = TagWriteOptions {
+            fields: vec![TagField::Title],
+            dry_run: true,
+        };
+        let results = write_track_tags(&library, &track, &options).unwrap();
+        assert!(results.len() == 1);
+        assert!(results[0].changes.len() == 1);
+        assert!(!results[0].written);
+        let tagged_file = lofty::read_from_path(&path).unwrap();
+        assert!(tagged_file.primary_tag().unwrap().title().as_deref() != Some("Fixed Title"));
+
+        let options = TagWriteOptions {
+            dry_run: false,
+            ..options
+        };
+        let results = write_track_tags(&library, &track, &options).unwrap();
+        assert!(results[0].written);
+        let tagged_file = lofty::read_from_path(&path).unwrap();
+        let tag = tagged_file.primary_tag().unwrap();
+        assert!(tag.title().as_deref() == Some("Fixed Title"));
+        assert!(tag.get_string(&ItemKey::Comment) == Some("keep me"));
+
+        // Nothing left to change.
+        let results = write_track_tags(&library, &track, &options).unwrap();
+        assert!(results[0].changes.is_empty());
+    }
+
+    #[test]
+    fn keeps_unknown_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pink-noise.mp3");
+        std::fs::copy("tests/data/media_files/pink-noise-1s-192kbit.mp3", &path).unwrap();
+
+        // A private frame and a custom TXXX, which have no ItemKey.
+        let mut tag = read_id3v2(&path);
+        tag.insert(Frame::Private(PrivateFrame::new("dimple", vec![1, 2, 3])));
+        tag.insert_user_text("DIMPLE_KEEP".to_string(), "keep me".to_string());
+        tag.save_to_path(&path, WriteOptions::default()).unwrap();
+
+        let library = Library::open_memory();
+        library.import(dir.path().to_str().unwrap());
+        let mut track = Track::list(&library)[0].clone();
+        track.title = Some("Fixed Title".to_string());
+        let track = track.save(&library);
+        let options = TagWriteOptions {
+            fields: vec![TagField::Title],
+            dry_run: false,
+        };
+        assert!(write_track_tags(&library, &track, &options).unwrap()[0].written);
+
+        let tag = read_id3v2(&path);
+        assert!(tag.title().as_deref() == Some("Fixed Title"));
+        assert!(tag.get_user_text("DIMPLE_KEEP") == Some("keep me"));
+        assert!(tag.into_iter().any(|frame| matches!(frame, Frame::Private(private) if private.owner == "dimple")));
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pink-noise.mp3");
+        std::fs::copy("tests/data/media_files/pink-noise-1s-192kbit.mp3", &path).unwrap();
+        let library = Library::open_memory();
+        library.import(dir.path().to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let track = Track::list(&library)[0].clone();
+        let options = TagWriteOptions {
+            fields: vec![TagField::Title],
+            dry_run: false,
+        };
+        let results = write_track_tags(&library, &track, &options).unwrap();
+        assert!(results.len() == 1);
+        assert!(!results[0].written);
+        assert!(results[0].error.is_some());
+    }
+
+    fn read_id3v2(path: &Path) -> Id3v2Tag {
+        let file = MpegFile::read_from(&mut File::open(path).unwrap(), ParseOptions::new()).unwrap();
+        file.id3v2().cloned().unwrap_or_default()
+    }
+}
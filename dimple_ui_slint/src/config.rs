@@ -1,18 +1,24 @@
+use std::sync::{Arc, RwLock};
+
 use serde::{Deserialize, Serialize};
 
+/// App settings, saved as JSON. Plugin settings are saved by the plugin
+/// registry, see Plugins::open. Clones share the settings.
 #[derive(Clone, Default)]
 pub struct Config {
     path: String,
-    config_file: ConfigFile,
+    config_file: Arc<RwLock<ConfigFile>>,
 }
 
 impl Config {
-    // pub fn open(path: &str) -> Config {
-    //     Config {
-    //         path: path.to_string(),
-    //         config_file: ConfigFile::default(),
-    //     }
-    // }
+    pub fn open(path: &str) -> Config {
+        let config = Config {
+            path: path.to_string(),
+            config_file: Default::default(),
+        };
+        config.load();
+        config
+    }
 
     pub fn on_change(&self) {
         todo!()
@@ -23,41 +29,38 @@ impl Config {
     }
 
     pub fn offline_mode(&self) -> bool {
-        self.config_file.offline_mode
+        self.config_file.read().unwrap().offline_mode
     }
 
-    pub fn set_offline_mode(&mut self, value: bool) {
-        self.config_file.offline_mode = value;
+    pub fn set_offline_mode(&self, value: bool) {
+        self.config_file.write().unwrap().offline_mode = value;
+        self.save();
         self.emit_change("offline_mode");
     }
 
-    pub fn plugin_config(&self) -> Vec<PluginConfig> {
-        self.config_file.plugin_config.clone()
-    }
-
-    pub fn add_plugin_config(&mut self, config: &PluginConfig) {
-        self.config_file.plugin_config.push(config.clone());
-    }
-
     fn load(&self) {
-
+        let Ok(bytes) = std::fs::read(&self.path) else {
+            return
+        };
+        match serde_json::from_slice(&bytes) {
+            Ok(config_file) => *self.config_file.write().unwrap() = config_file,
+            Err(e) => log::error!("Invalid config {}: {}", self.path, e),
+        }
     }
 
     fn save(&self) {
-
+        if self.path.is_empty() {
+            return
+        }
+        let json = serde_json::to_vec_pretty(&*self.config_file.read().unwrap()).unwrap();
+        if let Err(e) = std::fs::write(&self.path, json) {
+            log::error!("Error saving config {}: {}", self.path, e);
+        }
     }
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ConfigFile {
     pub offline_mode: bool,
-    pub plugin_config: Vec<PluginConfig>,
-}
-
-#[derive(Default, Serialize, Deserialize, Clone)]
-pub struct PluginConfig {
-    pub key: String,
-    pub type_name: String,
-    pub enabled: bool,
-    pub config: String,
 }
@@ -1,4 +1,4 @@
-use dimple_core::{librarian::Librarian, library::Library, player::{PlayWhen, Player, PlayerEvent}, plugins::{fanart_tv::FanartTvPlugin, lrclib::LrclibPlugin, musicbrainz::MusicBrainzPlugin, plugins::Plugins, wikidata::WikidataPlugin}};
+use dimple_core::{librarian::Librarian, library::Library, player::{PlayWhen, Player, PlayerEvent}, plugins::{acoustid::AcoustIdPlugin, fanart_tv::FanartTvPlugin, lrclib::LrclibPlugin, musicbrainz::MusicBrainzPlugin, plugins::Plugins, process, wikidata::WikidataPlugin}};
 use player_bar;
 use std::{collections::VecDeque, env, path::Path, sync::{Arc, Mutex}};
 
@@ -52,20 +52,27 @@ impl AppWindowController {
         std::fs::create_dir_all(&cache_dir).unwrap();
         std::fs::create_dir_all(&image_cache_dir).unwrap();
 
+        let config = Config::open(data_dir.join("config.json").to_str().unwrap());
         let library = Library::open(library_path.to_str().unwrap());
+        library.network.set_offline(config.offline_mode());
         let player = Player::new(Arc::new(library.clone()));
-        let plugins = Plugins::new(cache_dir.to_str().unwrap());
+        let mut plugins = Plugins::open(cache_dir.to_str().unwrap(), &data_dir.join("plugins.json"));
+        plugins.set_network(&library.network);
+        plugins.add_plugin(Arc::new(AcoustIdPlugin::default()));
         plugins.add_plugin(Arc::new(MusicBrainzPlugin::default()));
         plugins.add_plugin(Arc::new(WikidataPlugin::default()));
         plugins.add_plugin(Arc::new(LrclibPlugin::default()));
         plugins.add_plugin(Arc::new(FanartTvPlugin::default()));
+        for plugin in process::discover(&data_dir.join("plugins")) {
+            plugins.add_plugin(plugin);
+        }
         let librarian = Librarian::new(&library, &plugins);
         let images = ImageMangler::new(librarian, ui.as_weak().clone(), image_cache_dir.to_str().unwrap());        
         let ui_weak = ui.as_weak();
         Self {
             ui,
             app: App {
-                config: Config::default(),
+                config,
                 library,
                 history: Arc::new(Mutex::new(VecDeque::new())),
                 player,
@@ -145,6 +152,9 @@ impl App {
         else if url.starts_with("dimple://search") {
             pages::search_results::search_results(&url, self);
         }
+        else if url.starts_with("dimple://candidate/") {
+            pages::search_results::open_candidate(&url, self);
+        }
         // TODO change this mess to use a registry that pages call during init
         // Or maybe get rid of the navigator altogether? Now that we have proper
         // callbacks it might be superfluous.
@@ -306,7 +316,7 @@ fn desktop_integration(app: &App, ui: &AppWindow) -> MediaControls {
                 true => MediaPlayback::Playing { progress: Some(MediaPosition(track_position)) },
                 false => MediaPlayback::Paused { progress: Some(MediaPosition(track_position)) },
             };
-            let artist = current_track.clone().map(|t| t.artist_name(&app.library)).flatten();
+            let artist = current_track.clone().map(|t| t.artist_credit(&app.library)).flatten();
             let album = current_track.clone().map(|t| t.album_name(&app.library)).flatten();
             let title = current_track.clone().map(|t| t.title).flatten();
             let metadata = MediaMetadata {
@@ -24,6 +24,7 @@ impl From<Artist> for CardAdapter {
                 name: value.disambiguation.clone().unwrap_or("Artist".to_string()).into(),
                 url: format!("dimple://artist/{}", value.key.clone().unwrap_or_default()).into(),
             },
+            ..Default::default()
         }
     }
 }
@@ -45,6 +46,7 @@ impl From<Release> for CardAdapter {
                 name: format!("{} {}", value.date.unwrap_or_default(), value.country.unwrap_or_default()).into(),
                 url: format!("dimple://release/{}", value.key.clone().unwrap_or_default()).into(),
             },
+            ..Default::default()
         }
     }
 }
@@ -66,6 +68,7 @@ impl From<Genre> for CardAdapter {
                 name: value.disambiguation.unwrap_or_default().into(),
                 url: format!("dimple://genre/{}", value.key.clone().unwrap_or_default()).into(),
             },
+            ..Default::default()
         }
     }
 }
@@ -24,7 +24,8 @@ fn update_model(app: &App) {
         let artists = library.query("
             SELECT * 
             FROM Artist
-            ORDER BY name ASC, disambiguation ASC
+            ORDER BY COALESCE(sort_name, generate_sort_name(name)) COLLATE UNICODE ASC,
+                disambiguation COLLATE UNICODE ASC
         ", ());
         let ui = app.ui.clone();
         let images = app.images.clone();
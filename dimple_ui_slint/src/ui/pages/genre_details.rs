@@ -215,6 +215,7 @@ fn genre_card(genre: &Genre) -> CardAdapter {
             name: genre.disambiguation.clone().unwrap_or_default().into(),
             url: format!("dimple://genre/{}", genre.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 
@@ -28,7 +28,7 @@ fn update_model(app: &App) {
         let genres = library.query("
             SELECT * 
             FROM Genre 
-            ORDER BY name ASC, disambiguation ASC", ());
+            ORDER BY name COLLATE UNICODE ASC, disambiguation COLLATE UNICODE ASC", ());
         let ui = app.ui.clone();
         let images = app.images.clone();
         ui.upgrade_in_event_loop(move |ui| {
@@ -72,6 +72,7 @@ fn genre_card(genre: &Genre) -> CardAdapter {
             name: genre.disambiguation.clone().unwrap_or_default().into(),
             url: format!("dimple://genre/{}", genre.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 
@@ -76,7 +76,7 @@ fn row_data(library: &Library, tracks: &[Track]) -> ModelRc<ModelRc<StandardList
         row.push(track.position.unwrap_or_default().to_string().as_str().into()); // Track #
         row.push(track.title.clone().unwrap_or_default().as_str().into()); // Title
         row.push(track.album_name(library).unwrap_or_default().as_str().into()); // Album
-        row.push(track.artist_name(library).unwrap_or_default().as_str().into()); // Artist
+        row.push(track.artist_credit(library).unwrap_or_default().as_str().into()); // Artist
         row.push(length.unwrap_or_default().as_str().into()); // Length
         row_data.push(row.into());
     }
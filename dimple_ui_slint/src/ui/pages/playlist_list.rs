@@ -21,7 +21,7 @@ pub fn playlist_list(app: &App) {
     let app = app.clone();
     std::thread::spawn(move || {
         let playlists: Vec<Playlist> = app.library
-            .query("SELECT * FROM Playlist ORDER BY name ASC", ());
+            .query("SELECT * FROM Playlist ORDER BY name COLLATE UNICODE ASC", ());
         ui.upgrade_in_event_loop(move |ui| {
             let cards: Vec<CardAdapter> = playlists.iter().cloned().enumerate()
                 .map(|(index, playlist)| {
@@ -1,14 +1,17 @@
+use std::collections::BTreeMap;
 use std::rc::Rc;
 use std::time::Duration;
 
 use crate::ui::app_window_controller::App;
 use crate::ui::CardAdapter;
+use crate::ui::DiscAdapter;
 use crate::ui::Page;
 use dimple_core::librarian;
 use dimple_core::library::Library;
 use dimple_core::model::Artist;
 use dimple_core::model::Genre;
 use dimple_core::model::Link;
+use dimple_core::model::Medium;
 use dimple_core::model::ModelBasics;
 use dimple_core::model::Release;
 use dimple_core::model::Track;
@@ -86,6 +89,7 @@ fn update_model(app: &App) {
             let genres = release.genres(&library);
             let links = release.links(&library);
             let tracks = release.tracks(&app.library);
+            let media = release.media(&app.library);
             let ui = app.ui.clone();
             ui.upgrade_in_event_loop(move |ui| {
                 let mut card: CardAdapter = release.clone().into();                
@@ -108,8 +112,7 @@ fn update_model(app: &App) {
                 ui.global::<ReleaseDetailsAdapter>().set_genres(ModelRc::from(genres.as_slice()));
                 ui.global::<ReleaseDetailsAdapter>().set_links(ModelRc::from(links.as_slice()));
                 ui.global::<ReleaseDetailsAdapter>().set_dump(format!("{:?}", release).into());
-                ui.global::<ReleaseDetailsAdapter>().set_row_data(row_data(&library, &tracks));
-                ui.global::<ReleaseDetailsAdapter>().set_row_keys(row_keys(&tracks));
+                ui.global::<ReleaseDetailsAdapter>().set_discs(discs(&library, &media, &tracks));
             }).unwrap();
         });
     }).unwrap();
@@ -139,6 +142,34 @@ fn play_track_later(app: &App, key: &str) {
     app.player.play_later(&Track::get(&app.library, key).unwrap());
 }
 
+/// Groups the tracks by disc, in disc order, with a "Disc N" header and
+/// the medium's title and format as the subtitle. Releases with a single
+/// disc get one group with no header.
+fn discs(library: &Library, media: &[Medium], tracks: &[Track]) -> ModelRc<DiscAdapter> {
+    let mut groups: BTreeMap<u32, Vec<Track>> = BTreeMap::new();
+    for track in tracks {
+        let disc = track.medium(library).and_then(|m| m.position).or(track.media_position).unwrap_or(1);
+        groups.entry(disc).or_default().push(track.clone());
+    }
+    let multi_disc = groups.len() > 1;
+    let discs: Vec<_> = groups.into_iter().map(|(disc, tracks)| {
+        let medium = media.iter().find(|m| m.position == Some(disc));
+        let subtitle = medium.map(|m| [m.title.clone(), m.format.clone()].into_iter()
+                .flatten()
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(" · "))
+            .unwrap_or_default();
+        DiscAdapter {
+            title: if multi_disc { format!("Disc {}", disc).into() } else { Default::default() },
+            subtitle: if multi_disc { subtitle.into() } else { Default::default() },
+            row_data: row_data(library, &tracks),
+            row_keys: row_keys(&tracks),
+        }
+    }).collect();
+    discs.as_slice().into()
+}
+
 fn row_data(library: &Library, tracks: &[Track]) -> ModelRc<ModelRc<StandardListViewItem>> {
     let row_data: Rc<VecModel<ModelRc<StandardListViewItem>>> = Rc::new(VecModel::default());
     for track in tracks {
@@ -149,7 +180,7 @@ fn row_data(library: &Library, tracks: &[Track]) -> ModelRc<ModelRc<StandardList
             .map(|dur| format_length(dur));
         row.push(track.position.unwrap_or_default().to_string().as_str().into()); // Track #
         row.push(track.title.clone().unwrap_or_default().as_str().into()); // Title
-        row.push(track.artist_name(library).unwrap_or_default().as_str().into()); // Artist
+        row.push(track.artist_credit(library).unwrap_or_default().as_str().into()); // Artist
         row.push(length.unwrap_or_default().as_str().into()); // Length
         row_data.push(row.into());
     }
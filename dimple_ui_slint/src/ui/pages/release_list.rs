@@ -29,10 +29,15 @@ fn update_model(app: &App) {
     let app = app.clone();
     std::thread::spawn(move || {
         let library = app.library.clone();
+        // One card per release group, showing its oldest edition.
         let releases = library.query("
-            SELECT * 
-            FROM Release
-            ORDER BY title ASC
+            SELECT r.* 
+            FROM Release r
+            WHERE r.release_group_key IS NULL
+                OR r.key = (SELECT e.key FROM Release e
+                    WHERE e.release_group_key = r.release_group_key
+                    ORDER BY e.date IS NULL, e.date ASC, e.rowid ASC LIMIT 1)
+            ORDER BY COALESCE(r.sort_name, generate_sort_name(r.title)) COLLATE UNICODE ASC
         ", ());
         let ui = app.ui.clone();
         let images = app.images.clone();
@@ -1,22 +1,35 @@
 
+use std::sync::Mutex;
+
 use dimple_core::librarian;
+use dimple_core::librarian::Candidate;
+use dimple_core::librarian::CandidateSource;
+use dimple_core::librarian::Candidates;
 use dimple_core::library::Library;
 use dimple_core::model::Artist;
 use dimple_core::model::Genre;
 use dimple_core::model::ModelBasics;
 use dimple_core::model::Release;
 use dimple_core::model::Track;
+use dimple_core::search::highlight_runs;
+use dimple_core::search::SearchHit;
+use dimple_core::search::SearchOptions;
 use url::Url;
 use crate::ui::app_window_controller::App;
 use crate::ui::images::ImageMangler;
 use crate::ui::CardAdapter;
 use crate::ui::CardSectionAdapter;
+use crate::ui::HighlightRun;
 use crate::ui::ImageLinkAdapter;
 use crate::ui::LinkAdapter;
 use crate::ui::Page;
 use crate::ui::SearchResultsAdapter;
 use slint::ComponentHandle as _;
 
+/// Plugin results from the last search, found by Candidate::id. They're
+/// only merged into the library when opened, see open_candidate.
+static CANDIDATES: Mutex<Option<Candidates>> = Mutex::new(None);
+
 pub fn search_results_init(app: &App) {
 }
 
@@ -35,11 +48,18 @@ fn update_model(app: &App, query: &str) {
     let app = app.clone();
     let query = query.to_string();
     std::thread::spawn(move || {
-        let results = librarian::search(&app.library, &app.plugins, &query);
+        let options = search_options();
+        let results = librarian::search(&app.library, &app.plugins, &query, &options);
         let artists = results.artists;
         let tracks = results.tracks;
         let genres = results.genres;
         let releases = results.releases;
+        let candidates = results.candidates;
+        // Library results are for the corrected query if there were typos.
+        let sub_title = results.corrected_query
+            .map(|query| format!("Showing results for \"{}\".", query))
+            .unwrap_or_default();
+        *CANDIDATES.lock().unwrap() = Some(candidates.clone());
                                     
         let app = app.clone();
         app.ui.upgrade_in_event_loop(move |ui| {
@@ -48,8 +68,8 @@ fn update_model(app: &App, query: &str) {
             if !tracks.is_empty() {
                 sections.push(CardSectionAdapter {
                     title: "Tracks".into(),
-                    sub_title: Default::default(),
-                    cards: track_cards(&app.images, &tracks, &app.library).as_slice().into(),
+                    sub_title: sub_title.clone().into(),
+                    cards: track_cards(&app.images, &tracks, &app.library, &options).as_slice().into(),
                     ..Default::default()
                 });
             }
@@ -57,8 +77,8 @@ fn update_model(app: &App, query: &str) {
             if !artists.is_empty() {
                 sections.push(CardSectionAdapter {
                     title: "Artists".into(),
-                    sub_title: Default::default(),
-                    cards: artist_cards(&app.images, &artists).as_slice().into(),
+                    sub_title: sub_title.clone().into(),
+                    cards: artist_cards(&app.images, &artists, &options).as_slice().into(),
                     ..Default::default()
                 });
             }
@@ -66,8 +86,8 @@ fn update_model(app: &App, query: &str) {
             if !releases.is_empty() {
                 sections.push(CardSectionAdapter {
                     title: "Releases".into(),
-                    sub_title: Default::default(),
-                    cards: release_cards(&app.images, &releases, &app.library).as_slice().into(),
+                    sub_title: sub_title.clone().into(),
+                    cards: release_cards(&app.images, &releases, &app.library, &options).as_slice().into(),
                     ..Default::default()
                 });
             }
@@ -75,8 +95,46 @@ fn update_model(app: &App, query: &str) {
             if !genres.is_empty() {
                 sections.push(CardSectionAdapter {
                     title: "Genres".into(),
-                    sub_title: Default::default(),
-                    cards: genre_cards(&app.images, &genres).as_slice().into(),
+                    sub_title: sub_title.clone().into(),
+                    cards: genre_cards(&app.images, &genres, &options).as_slice().into(),
+                    ..Default::default()
+                });
+            }
+
+            if !candidates.artists.is_empty() {
+                sections.push(CardSectionAdapter {
+                    title: "More Artists".into(),
+                    sub_title: "Not in your library.".into(),
+                    cards: candidate_cards(&candidates.artists, "artist", |c| (c.artist.name.clone(), c.artist.disambiguation.clone())).as_slice().into(),
+                    ..Default::default()
+                });
+            }
+
+            if !candidates.releases.is_empty() {
+                sections.push(CardSectionAdapter {
+                    title: "More Releases".into(),
+                    sub_title: "Not in your library.".into(),
+                    cards: candidate_cards(&candidates.releases, "release", |c| (c.release.title.clone(), 
+                        c.artists.first().and_then(|a| a.artist.name.clone()))).as_slice().into(),
+                    ..Default::default()
+                });
+            }
+
+            if !candidates.tracks.is_empty() {
+                sections.push(CardSectionAdapter {
+                    title: "More Tracks".into(),
+                    sub_title: "Not in your library.".into(),
+                    cards: candidate_cards(&candidates.tracks, "track", |c| (c.track.title.clone(), 
+                        c.artists.first().and_then(|a| a.artist.name.clone()))).as_slice().into(),
+                    ..Default::default()
+                });
+            }
+
+            if !candidates.genres.is_empty() {
+                sections.push(CardSectionAdapter {
+                    title: "More Genres".into(),
+                    sub_title: "Not in your library.".into(),
+                    cards: candidate_cards(&candidates.genres, "genre", |c| (c.name.clone(), c.disambiguation.clone())).as_slice().into(),
                     ..Default::default()
                 });
             }
@@ -87,10 +145,98 @@ fn update_model(app: &App, query: &str) {
     });
 }
 
-fn release_cards(images: &ImageMangler, releases: &[Release], library: &Library) -> Vec<CardAdapter> {
-    releases.iter().cloned().enumerate()
-        .map(|(index, release)| {
-            let mut card: CardAdapter = release_card(&release, &release.artist(library).unwrap_or_default());
+/// Merge the candidate from a dimple://candidate/{type}/{id} url into the
+/// library, and show it.
+pub fn open_candidate(url: &str, app: &App) {
+    let app = app.clone();
+    let url = Url::parse(&url).unwrap();
+    let mut segments = url.path_segments().unwrap();
+    let kind = segments.next().unwrap_or_default().to_string();
+    let id = segments.next().unwrap_or_default();
+    let id = percent_encoding::percent_decode_str(id).decode_utf8_lossy().to_string();
+    std::thread::spawn(move || {
+        let Some(candidates) = CANDIDATES.lock().unwrap().clone() else {
+            return
+        };
+        fn find<T: CandidateSource>(candidates: &[Candidate<T>], id: &str) -> Option<&Candidate<T>> {
+            candidates.iter().find(|candidate| candidate.id() == id)
+        }
+        let key = match kind.as_str() {
+            "artist" => find(&candidates.artists, &id).map(|c| c.materialize(&app.library).key),
+            "release" => find(&candidates.releases, &id).map(|c| c.materialize(&app.library).key),
+            "track" => find(&candidates.tracks, &id).map(|c| c.materialize(&app.library).key),
+            "genre" => find(&candidates.genres, &id).map(|c| c.materialize(&app.library).key),
+            _ => None,
+        };
+        let Some(Some(key)) = key else {
+            return
+        };
+        // Not navigated to, so that going back returns to the search.
+        let url = format!("dimple://{}/{}", kind, key);
+        match kind.as_str() {
+            "artist" => crate::ui::pages::artist_details::artist_details(&url, &app),
+            "release" => crate::ui::pages::release_details::release_details(&url, &app),
+            "track" => crate::ui::pages::track_details::track_details(&url, &app),
+            _ => crate::ui::pages::genre_details::genre_details(&url, &app),
+        }
+    });
+}
+
+fn candidate_cards<T: CandidateSource>(candidates: &[Candidate<T>], kind: &str, names: impl Fn(&T) -> (Option<String>, Option<String>)) -> Vec<CardAdapter> {
+    candidates.iter()
+        .map(|candidate| {
+            let (title, sub_title) = names(&candidate.metadata);
+            let id = percent_encoding::utf8_percent_encode(&candidate.id(), percent_encoding::NON_ALPHANUMERIC);
+            let url = format!("dimple://candidate/{}/{}", kind, id);
+            CardAdapter {
+                image: ImageLinkAdapter {
+                    image: Default::default(),
+                    name: title.clone().unwrap_or_default().into(),
+                    url: url.clone().into(),
+                    ..Default::default()
+                },
+                title: LinkAdapter {
+                    name: title.unwrap_or_default().into(),
+                    url: url.clone().into(),
+                    ..Default::default()
+                },
+                sub_title: LinkAdapter {
+                    name: sub_title.unwrap_or(candidate.plugin.clone()).into(),
+                    url: url.into(),
+                },
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Markers that can't appear in names, see search::highlight_runs.
+fn search_options() -> SearchOptions {
+    SearchOptions {
+        highlight_start: "\u{2}".to_string(),
+        highlight_end: "\u{3}".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Show the hit's title with the matched terms highlighted, and the
+/// snippet if it matched on something else, such as lyrics.
+fn highlight<T>(mut card: CardAdapter, hit: &SearchHit<T>, options: &SearchOptions) -> CardAdapter {
+    let runs = |text: &str| highlight_runs(text, options).into_iter()
+        .map(|(text, highlighted)| HighlightRun { text: text.into(), highlighted })
+        .collect::<Vec<_>>();
+    card.title_runs = runs(&hit.highlight).as_slice().into();
+    if !hit.snippet.is_empty() && hit.snippet != hit.highlight {
+        card.snippet = runs(&hit.snippet).as_slice().into();
+    }
+    card
+}
+
+fn release_cards(images: &ImageMangler, hits: &[SearchHit<Release>], library: &Library, options: &SearchOptions) -> Vec<CardAdapter> {
+    hits.iter().enumerate()
+        .map(|(index, hit)| {
+            let release = hit.model.clone();
+            let mut card = highlight(release_card(&release, &release.artist(library).unwrap_or_default()), hit, options);
             card.image.image = images.lazy_get(release.clone(), 200, 200, move |ui, image| {
                 // let adapter = ui.global::<HomeAdapter>();
                 // let mut card = adapter.get_releases().row_data(index).unwrap();
@@ -125,10 +271,11 @@ fn release_card(release: &Release, artist: &Artist) -> CardAdapter {
     }
 }
 
-fn artist_cards(images: &ImageMangler, artists: &[Artist]) -> Vec<CardAdapter> {
-    artists.iter().cloned().enumerate()
-        .map(|(index, artist)| {
-            let mut card: CardAdapter = artist_card(&artist);
+fn artist_cards(images: &ImageMangler, hits: &[SearchHit<Artist>], options: &SearchOptions) -> Vec<CardAdapter> {
+    hits.iter().enumerate()
+        .map(|(index, hit)| {
+            let artist = hit.model.clone();
+            let mut card = highlight(artist_card(&artist), hit, options);
             card.image.image = images.lazy_get(artist.clone(), 200, 200, move |ui, image| {
                 // let mut card = ui.get_artist_list().cards.row_data(index).unwrap();
                 // card.image.image = image;
@@ -160,10 +307,11 @@ fn artist_card(artist: &Artist) -> CardAdapter {
     }
 }
 
-fn genre_cards(images: &ImageMangler, genres: &[Genre]) -> Vec<CardAdapter> {
-    genres.iter().cloned().enumerate()
-        .map(|(index, genre)| {
-            let mut card: CardAdapter = genre_card(&genre);
+fn genre_cards(images: &ImageMangler, hits: &[SearchHit<Genre>], options: &SearchOptions) -> Vec<CardAdapter> {
+    hits.iter().enumerate()
+        .map(|(index, hit)| {
+            let genre = hit.model.clone();
+            let mut card = highlight(genre_card(&genre), hit, options);
             card.image.image = images.lazy_get(genre.clone(), 200, 200, move |ui, image| {
                 // let mut card = ui.get_genre_list().cards.row_data(index).unwrap();
                 // card.image.image = image;
@@ -191,13 +339,15 @@ fn genre_card(genre: &Genre) -> CardAdapter {
             name: genre.disambiguation.clone().unwrap_or_default().into(),
             url: format!("dimple://genre/{}", genre.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 
-fn track_cards(images: &ImageMangler, tracks: &[Track], library: &Library) -> Vec<CardAdapter> {
-    tracks.iter().cloned().enumerate()
-        .map(|(index, track)| {
-            let mut card: CardAdapter = track_card(&track, &track.artist(library).unwrap_or_default());
+fn track_cards(images: &ImageMangler, hits: &[SearchHit<Track>], library: &Library, options: &SearchOptions) -> Vec<CardAdapter> {
+    hits.iter().enumerate()
+        .map(|(index, hit)| {
+            let track = hit.model.clone();
+            let mut card = highlight(track_card(&track, &track.artist(library).unwrap_or_default()), hit, options);
             card.image.image = images.lazy_get(track.clone(), 200, 200, move |ui, image| {
                 // let adapter = ui.global::<HomeAdapter>();
                 // let mut card = adapter.get_releases().row_data(index).unwrap();
@@ -1,5 +1,6 @@
 use std::thread;
 
+use dimple_core::fingerprint;
 use dimple_core::model::Artist;
 use dimple_core::model::Genre;
 use dimple_core::model::MediaFile;
@@ -9,7 +10,7 @@ use dimple_core::model::TrackSource;
 use size::Size;
 use slint::{ModelRc, SharedString};
 
-use crate::config::PluginConfig;
+use dimple_core::plugins::plugins::PluginSettings;
 use crate::ui::app_window_controller::App;
 
 use crate::ui::SettingsAdapter;
@@ -21,8 +22,18 @@ use crate::ui::PluginAdapter;
 use slint::ComponentHandle;
 
 pub fn settings_init(app: &App) {
+    let ui = app.ui.clone();
+    app.library.network.notifier.observe(move |event| {
+        ui.upgrade_in_event_loop(move |ui| {
+            ui.global::<AppState>().set_offline_mode(event.offline);
+        }).unwrap();
+    });
+
     let app_ = app.clone();
+    let offline = app.library.network.is_offline();
     app.ui.upgrade_in_event_loop(move |ui| {
+        ui.global::<AppState>().set_offline_mode(offline);
+
         let app = app_.clone();
         ui.global::<SettingsAdapter>().on_set_online(
             move |online| set_online(&app, online));
@@ -47,6 +58,22 @@ pub fn settings_init(app: &App) {
         ui.global::<SettingsAdapter>().on_quit(move || {
             slint::quit_event_loop().unwrap();
         });
+
+        let app = app_.clone();
+        ui.global::<SettingsAdapter>().on_set_plugin_enabled(
+            move |key, enabled| set_plugin_enabled(&app, &key, enabled));
+
+        let app = app_.clone();
+        ui.global::<SettingsAdapter>().on_move_plugin(
+            move |key, delta| move_plugin(&app, &key, delta));
+
+        let app = app_.clone();
+        ui.global::<SettingsAdapter>().on_set_plugin_config(
+            move |key, config| set_plugin_config(&app, &key, &config));
+
+        let app = app_.clone();
+        ui.global::<SettingsAdapter>().on_clear_cache(
+            move || clear_cache(&app));
     }).unwrap();
 }
 
@@ -55,7 +82,12 @@ pub fn settings(app: &App) {
     std::thread::spawn(move || {
         let db = app.library.clone();
 
-        let plugins = app.config.plugin_config();
+        let plugins = app.plugins.list().into_iter()
+            .map(|plugin| {
+                let status = app.plugins.status(&plugin.type_name);
+                (plugin, status)
+            })
+            .collect::<Vec<_>>();
 
         let mut database_stats: Vec<String> = vec![];
         database_stats.push(format!("Artists: {}", db.list::<Artist>().len()));
@@ -65,21 +97,17 @@ pub fn settings(app: &App) {
         database_stats.push(format!("Tracks: {}", db.list::<Track>().len()));
         database_stats.push(format!("TrackSources: {}", db.list::<TrackSource>().len()));
 
-        let mut cache_stats: Vec<String> = vec![];
         // TODO Before any music has been loaded, there are no images, so the
         // cache is empty, and this blows up. 
         // cache_stats.push(format!("Thumbnail cache: {}", Size::from_bytes(app.images.cache_len())));
-        // cache_stats.push(format!("Plugin cache: {}", Size::from_bytes(app.librarian.plugin_cache_len())));
+        let cache_stats = cache_stats(&app);
         
         app.ui.upgrade_in_event_loop(move |ui| {
             let database_stats: Vec<SharedString> = database_stats.into_iter()
                 .map(Into::into)
                 .collect();
-            let cache_stats: Vec<SharedString> = cache_stats.into_iter()
-                .map(Into::into)
-                .collect();
             let plugins: Vec<PluginAdapter> = plugins.into_iter()
-                .map(plugin_adapter)
+                .map(|(plugin, status)| plugin_adapter(plugin, &status))
                 .collect();
             ui.global::<SettingsAdapter>().set_database_stats(ModelRc::from(database_stats.as_slice()));
             ui.global::<SettingsAdapter>().set_cache_stats(ModelRc::from(cache_stats.as_slice()));
@@ -89,6 +117,27 @@ pub fn settings(app: &App) {
     });
 }
 
+fn cache_stats(app: &App) -> Vec<SharedString> {
+    let stats = app.plugins.cache_stats();
+    vec![
+        format!("Plugin cache: {} of {}", Size::from_bytes(stats.size), Size::from_bytes(stats.max_size)),
+        format!("Responses: {}", stats.entries),
+        format!("Hits: {}, misses: {}, revalidated: {}, stale: {}", 
+            stats.hits, stats.misses, stats.revalidated, stats.stale),
+    ].into_iter().map(Into::into).collect()
+}
+
+fn clear_cache(app: &App) {
+    let app = app.clone();
+    thread::spawn(move || {
+        app.plugins.purge_cache(None);
+        let cache_stats = cache_stats(&app);
+        app.ui.upgrade_in_event_loop(move |ui| {
+            ui.global::<SettingsAdapter>().set_cache_stats(ModelRc::from(cache_stats.as_slice()));
+        }).unwrap();
+    });
+}
+
 fn import_files(app: &App) {
     use rfd::FileDialog;
 
@@ -104,6 +153,8 @@ fn import_files(app: &App) {
             for file in files.iter() {
                 app.library.import(file.to_str().unwrap());
             }
+            // For AcoustID, which only looks up stored fingerprints.
+            fingerprint::fingerprint_library(&app.library);
         }
     });
 }
@@ -126,16 +177,16 @@ fn import_directories(app: &App) {
             for file in files.iter() {
                 app.library.import(file.to_str().unwrap());
             }
+            // For AcoustID, which only looks up stored fingerprints.
+            fingerprint::fingerprint_library(&app.library);
         }
     });
 }
 
+/// AppState.offline_mode follows the network's notifier, see settings_init.
 fn set_online(app: &App, online: bool) {
-    let app = app.clone();
-    // app.ui.upgrade_in_event_loop(move |ui| {
-    //     app.librarian.set_network_mode(if online { &NetworkMode::Online } else { &NetworkMode::Offline });
-    //     ui.global::<AppState>().set_online(app.librarian.network_mode() == NetworkMode::Online);
-    // }).unwrap();
+    app.config.set_offline_mode(!online);
+    app.library.network.set_offline(!online);
 }
 
 fn set_debug(app: &App, debug: bool) {
@@ -152,11 +203,56 @@ fn set_font_size(app: &App, font_size: f32) {
     }).unwrap();
 }
 
-fn plugin_adapter(plugin_config: PluginConfig) -> PluginAdapter{
+fn set_plugin_enabled(app: &App, key: &str, enabled: bool) {
+    app.plugins.set_enabled(key, enabled);
+    update_plugins(app, key, "");
+}
+
+fn move_plugin(app: &App, key: &str, delta: i32) {
+    let Some(index) = app.plugins.list().iter().position(|plugin| plugin.type_name == key) else {
+        return
+    };
+    app.plugins.set_priority(key, (index as i32 + delta).max(0) as usize);
+    update_plugins(app, key, "");
+}
+
+fn set_plugin_config(app: &App, key: &str, config: &str) {
+    let status = match app.plugins.set_configuration(key, config) {
+        Ok(_) => "Saved.".to_string(),
+        Err(e) => format!("Invalid settings: {}", e),
+    };
+    update_plugins(app, key, &status);
+}
+
+/// Show the plugins again, with the status for the one with the key, or
+/// its stats if there's no status.
+fn update_plugins(app: &App, key: &str, status: &str) {
+    let plugins = app.plugins.list().into_iter()
+        .map(|plugin| {
+            let status = if plugin.type_name == key && !status.is_empty() { 
+                status.to_string() 
+            } 
+            else { 
+                app.plugins.status(&plugin.type_name) 
+            };
+            (plugin, status)
+        })
+        .collect::<Vec<_>>();
+    app.ui.upgrade_in_event_loop(move |ui| {
+        let plugins: Vec<PluginAdapter> = plugins.into_iter()
+            .map(|(plugin, status)| plugin_adapter(plugin, &status))
+            .collect();
+        ui.global::<SettingsAdapter>().set_plugins(plugins.as_slice().into());
+    }).unwrap();
+}
+
+fn plugin_adapter(plugin: PluginSettings, status: &str) -> PluginAdapter {
     PluginAdapter {
-        title: plugin_config.type_name.into(),
-        sub_title: plugin_config.config.into(),
-        enabled: plugin_config.enabled,
-        status: Default::default(),
+        key: plugin.type_name.clone().into(),
+        title: plugin.display_name.into(),
+        sub_title: plugin.type_name.into(),
+        status: status.into(),
+        enabled: plugin.enabled,
+        config: plugin.config.into(),
     }
 }
\ No newline at end of file
@@ -7,6 +7,7 @@ use crate::ui::Page;
 use dimple_core::library::Library;
 use dimple_core::model::ModelBasics;
 use dimple_core::model::Track;
+use dimple_core::query;
 use slint::ModelRc;
 use slint::SharedString;
 use slint::StandardListViewItem;
@@ -46,9 +47,11 @@ fn row_data(library: &Library, tracks: &[Track]) -> ModelRc<ModelRc<StandardList
             .map(|dur| format_length(dur));
         row.push(track.title.clone().unwrap_or_default().as_str().into()); // Title
         row.push(track.album_name(library).unwrap_or_default().as_str().into()); // Album
-        row.push(track.artist_name(library).unwrap_or_default().as_str().into()); // Artist
+        row.push(track.artist_credit(library).unwrap_or_default().as_str().into()); // Artist
         row.push(track.position.unwrap_or_default().to_string().as_str().into()); // Track #
         row.push(length.unwrap_or_default().as_str().into()); // Length
+        row.push(track.bpm.map(|bpm| bpm.to_string()).unwrap_or_default().as_str().into()); // BPM
+        row.push(track.initial_key.clone().unwrap_or_default().as_str().into()); // Key
         row_data.push(row.into());
     }
     row_data.into()
@@ -63,10 +66,15 @@ fn row_keys(tracks: &[Track]) -> ModelRc<SharedString> {
 }
 
 fn sort_table(app: &App, col: i32, ascending: bool) {
-    let columns = vec!["title", "album", "artist", "position", "plays", "length_ms"];
-    let query = format!("SELECT * FROM Track ORDER BY {} {}", 
-        columns[col as usize], 
-        if ascending { "asc" } else { "desc" });
+    // The query fields for the columns. Album and artist aren't columns of
+    // Track, see query::Field. Track # isn't a field.
+    let fields = [Some("title"), Some("album"), Some("artist"), None, Some("length"), Some("bpm"), Some("key")];
+    let direction = if ascending { "asc" } else { "desc" };
+    let order_by = match fields[col as usize] {
+        Some(field) => query::order_by(&format!("{} {}", field, direction)).unwrap(),
+        None => format!("t.position {}", direction),
+    };
+    let query = format!("SELECT t.* FROM Track t ORDER BY {}", order_by);
     let tracks: Vec<Track> = app.library.query(&query, ());
     let library = app.library.clone();
     app.ui.upgrade_in_event_loop(move |ui| {
@@ -179,6 +179,7 @@ fn artist_card(artist: &Artist) -> CardAdapter {
             name: artist.disambiguation.clone().unwrap_or_default().into(),
             url: format!("dimple://artist/{}", artist.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 
@@ -199,6 +200,7 @@ fn release_card(release: &Release) -> CardAdapter {
             name: release.date.clone().unwrap_or_default().into(),
             url: format!("dimple://release/{}", release.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 
@@ -219,6 +221,7 @@ fn track_card(track: &Track) -> CardAdapter {
             name: "Track".into(),
             url: format!("dimple://track/{}", track.key.clone().unwrap_or_default()).into(),
         },
+        ..Default::default()
     }
 }
 